@@ -0,0 +1,138 @@
+//! 从文件中流式解压 LZ77（类型 `0x10`）数据，格式与 tools 中的 `utils::lz77` 相同
+//!
+//! 输出按 16 位写入，回溯时直接读取已经写入的输出，因此可以直接解压到显存和调色板。
+//! 压缩端保证回溯距离至少为 2，回溯读取的字节总是已经写入。
+
+use nitro::fs::{FSSeekFileMode, File};
+
+const MIN_MATCH: usize = 3;
+const READ_BUFFER_SIZE: usize = 64;
+
+struct FileReader<'a> {
+    file: &'a mut File,
+    buffer: [u8; READ_BUFFER_SIZE],
+    pos: usize,
+    len: usize,
+    /// 已经从文件读入缓冲区的字节数
+    read: usize,
+    /// 已经使用的字节数
+    consumed: usize,
+}
+
+impl FileReader<'_> {
+    fn next(&mut self) -> Option<u8> {
+        if self.pos == self.len {
+            self.len = self.file.read(&mut self.buffer);
+            self.pos = 0;
+            self.read += self.len;
+            if self.len == 0 {
+                return None;
+            }
+        }
+        let b = self.buffer[self.pos];
+        self.pos += 1;
+        self.consumed += 1;
+        Some(b)
+    }
+}
+
+struct HalfwordWriter {
+    dst: *mut u8,
+    len: usize,
+    pending: u8,
+}
+
+impl HalfwordWriter {
+    fn push(&mut self, b: u8) {
+        if self.len % 2 == 0 {
+            self.pending = b;
+        } else {
+            unsafe {
+                (self.dst.add(self.len - 1) as *mut u16)
+                    .write_volatile(self.pending as u16 | (b as u16) << 8);
+            }
+        }
+        self.len += 1;
+    }
+
+    fn get(&self, pos: usize) -> u8 {
+        unsafe { self.dst.add(pos).read_volatile() }
+    }
+
+    fn finish(&mut self) {
+        if self.len % 2 == 1 {
+            unsafe {
+                let p = self.dst.add(self.len - 1) as *mut u16;
+                p.write_volatile((p.read_volatile() & 0xFF00) | self.pending as u16);
+            }
+        }
+    }
+}
+
+/// 从文件的当前位置解压一段 LZ77 数据到 `dst`，`dst` 需要按 2 字节对齐
+///
+/// 解压完成后文件位置会跳过压缩数据末尾用于 4 字节对齐的填充。
+/// 返回解压后的大小，数据格式错误或解压后大小超出 `dst` 时返回 `None`。
+pub fn decompress_from_file(file: &mut File, dst: &mut [u8]) -> Option<usize> {
+    debug_assert!(dst.as_ptr() as usize % 2 == 0);
+    let mut reader = FileReader {
+        file,
+        buffer: [0; READ_BUFFER_SIZE],
+        pos: 0,
+        len: 0,
+        read: 0,
+        consumed: 0,
+    };
+    let mut writer = HalfwordWriter {
+        dst: dst.as_mut_ptr(),
+        len: 0,
+        pending: 0,
+    };
+
+    let header = [
+        reader.next()?,
+        reader.next()?,
+        reader.next()?,
+        reader.next()?,
+    ];
+    if header[0] != 0x10 {
+        return None;
+    }
+    let size = u32::from_le_bytes(header) as usize >> 8;
+    if size > dst.len() {
+        return None;
+    }
+
+    while writer.len < size {
+        let flags = reader.next()?;
+        for bit in (0..8).rev() {
+            if writer.len >= size {
+                break;
+            }
+            if flags & (1 << bit) == 0 {
+                writer.push(reader.next()?);
+            } else {
+                let b0 = reader.next()? as usize;
+                let b1 = reader.next()? as usize;
+                let len = (b0 >> 4) + MIN_MATCH;
+                let disp = (((b0 & 0xF) << 8) | b1) + 1;
+                if disp < 2 || disp > writer.len {
+                    return None;
+                }
+                let start = writer.len - disp;
+                for i in 0..len.min(size - writer.len) {
+                    writer.push(writer.get(start + i));
+                }
+            }
+        }
+    }
+    writer.finish();
+
+    // 文件位置移动到对齐后的压缩数据末尾
+    let end = reader.consumed.next_multiple_of(4);
+    let offset = end as isize - reader.read as isize;
+    if offset != 0 {
+        reader.file.seek(offset, FSSeekFileMode::FS_SEEK_CUR);
+    }
+    Some(size)
+}
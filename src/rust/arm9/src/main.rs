@@ -4,6 +4,7 @@
 
 mod font;
 mod game;
+mod lz77;
 mod script;
 mod splash_screen;
 mod video;
@@ -83,13 +84,7 @@ pub unsafe extern "C" fn fontapi_main(init_data: *const InitData) {
         global_data().init_data.font3_graph_amount()
     );
     video::set_brightness(16);
-    splash_screen::load_splash_screen();
-    video::fade_in();
-    while (nitro::pad::read() & 1) == 0 {
-        nitro::irq::set_key_irq(true);
-        nitro::irq::wait_any_intr();
-    }
-    video::fade_out();
+    splash_screen::show_splash_pages();
     println!("Finished loading patch");
 }
 
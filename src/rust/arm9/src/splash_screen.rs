@@ -1,3 +1,5 @@
+use crate::{lz77, video};
+use arrayvec::ArrayVec;
 use nitro::{
     fs::File,
     sys::{
@@ -7,19 +9,166 @@ use nitro::{
     },
 };
 
-pub fn load_splash_screen() {
-    let mut file = File::open("splash-screen.bin\0");
-    // 读取并设置调色板
-    let pltt =
-        unsafe { core::slice::from_raw_parts_mut(HW_BG_PLTT as *mut u8, HW_BG_PLTT_SIZE as usize) };
-    let db_pltt = unsafe {
-        core::slice::from_raw_parts_mut(HW_DB_BG_PLTT as *mut u8, HW_DB_BG_PLTT_SIZE as usize)
+// splash-screen.bin 的文件头，共 16 字节：
+// 0x0 魔数 "SPLS"
+// 0x4 版本号
+// 0x5 每像素位数，4 或 8
+// 0x6 屏幕数量，固定为 2，先上屏后下屏
+// 0x7 标志位，1 << 0: 各段数据使用 LZ77 压缩
+// 0x8 每个屏幕 4 字节：调色板颜色数 u16、图块数 u16
+// 之后每个屏幕依次存放调色板、32x24 的图块表和图块集，
+// 压缩时每段数据单独压缩，并以 4 字节对齐
+const SPLASH_MAGIC: &[u8; 4] = b"SPLS";
+const SPLASH_VERSION: u8 = 1;
+const SPLASH_HEADER_SIZE: usize = 16;
+const SPLASH_TILEMAP_SIZE: usize = 32 * 24 * 2;
+const SPLASH_FLAG_LZ77: u8 = 1 << 0;
+
+// splash-pages.bin 描述启动时依次显示的页面，文件头共 8 字节：
+// 0x0 魔数 "SPLP"
+// 0x4 版本号
+// 0x5 页面数量
+// 0x6 保留
+// 之后每个页面 40 字节：
+// 0x00 页面图像的文件名，以 '\0' 结尾，最长 31 字节
+// 0x20 淡入帧数
+// 0x21 淡出帧数
+// 0x22 标志位，1 << 0: 按任意键跳过，否则只响应 A 键
+// 0x23 保留
+// 0x24 自动切换到下一页前等待的帧数 u16，为 0 时一直等待按键
+// 0x26 保留
+// 文件不存在时只显示 splash-screen.bin，按 A 键继续
+const PAGES_MAGIC: &[u8; 4] = b"SPLP";
+const PAGES_VERSION: u8 = 1;
+const PAGES_HEADER_SIZE: usize = 8;
+const PAGE_SIZE: usize = 40;
+const PAGE_NAME_SIZE: usize = 32;
+const MAX_PAGES: usize = 16;
+
+const PAGE_FLAG_ANY_KEY: u8 = 1 << 0;
+const KEY_A: u16 = 1;
+
+struct SplashPage {
+    name: [u8; PAGE_NAME_SIZE],
+    fade_in: u8,
+    fade_out: u8,
+    skip_on_any_key: bool,
+    timeout: u16,
+}
+
+impl SplashPage {
+    fn parse(data: &[u8; PAGE_SIZE]) -> Self {
+        let mut name = [0u8; PAGE_NAME_SIZE];
+        name.copy_from_slice(&data[..PAGE_NAME_SIZE]);
+        // 确保文件名总是以 '\0' 结尾
+        name[PAGE_NAME_SIZE - 1] = 0;
+        Self {
+            name,
+            fade_in: data[0x20],
+            fade_out: data[0x21],
+            skip_on_any_key: data[0x22] & PAGE_FLAG_ANY_KEY != 0,
+            timeout: u16::from_le_bytes([data[0x24], data[0x25]]),
+        }
+    }
+
+    fn default_page() -> Self {
+        let mut name = [0u8; PAGE_NAME_SIZE];
+        let default_name = b"splash-screen.bin";
+        name[..default_name.len()].copy_from_slice(default_name);
+        Self {
+            name,
+            fade_in: 16,
+            fade_out: 16,
+            skip_on_any_key: false,
+            timeout: 0,
+        }
+    }
+
+    fn path(&self) -> Option<&str> {
+        let len = self.name.iter().position(|x| *x == 0)?;
+        core::str::from_utf8(&self.name[..=len]).ok()
+    }
+}
+
+fn read_pages() -> ArrayVec<SplashPage, MAX_PAGES> {
+    let mut pages = ArrayVec::new();
+    let Some(mut file) = File::try_open("splash-pages.bin\0") else {
+        pages.push(SplashPage::default_page());
+        return pages;
     };
-    let mut palette = [0u8; 256 * 2];
-    file.read(&mut palette);
 
-    nitro::mem::copy(&palette, pltt);
-    nitro::mem::copy(&palette, db_pltt);
+    let mut header = [0u8; PAGES_HEADER_SIZE];
+    file.read(&mut header);
+    if &header[..4] != PAGES_MAGIC || header[4] != PAGES_VERSION {
+        nitro::println!("Invalid splash pages header");
+        pages.push(SplashPage::default_page());
+        return pages;
+    }
+
+    for _ in 0..(header[5] as usize).min(MAX_PAGES) {
+        let mut data = [0u8; PAGE_SIZE];
+        if file.read(&mut data) != PAGE_SIZE {
+            break;
+        }
+        pages.push(SplashPage::parse(&data));
+    }
+    pages
+}
+
+/// 等待上一页按下的按键全部松开，避免一次按键跳过多个页面
+fn wait_key_release() {
+    while nitro::pad::read() != 0 {
+        nitro::irq::wait_vblank();
+    }
+}
+
+fn wait_page(page: &SplashPage) {
+    let mask = if page.skip_on_any_key {
+        u16::MAX
+    } else {
+        KEY_A
+    };
+    let mut frames = 0u16;
+    while nitro::pad::read() & mask == 0 {
+        if page.timeout != 0 && frames >= page.timeout {
+            break;
+        }
+        nitro::irq::wait_vblank();
+        frames = frames.saturating_add(1);
+    }
+}
+
+/// 依次显示 splash-pages.bin 中描述的所有页面，调用前屏幕亮度应为全白
+pub fn show_splash_pages() {
+    for page in read_pages() {
+        let Some(path) = page.path() else {
+            nitro::println!("Invalid splash page name");
+            continue;
+        };
+        if !load_splash_screen(path) {
+            continue;
+        }
+        video::fade_in(page.fade_in);
+        wait_key_release();
+        wait_page(&page);
+        video::fade_out(page.fade_out);
+    }
+}
+
+/// 加载一张启动图像到上下屏，`path` 必须以 '\0' 结尾，文件不存在或格式错误时返回 `false`
+pub fn load_splash_screen(path: &str) -> bool {
+    let Some(mut file) = File::try_open(path) else {
+        nitro::println!("Splash screen not found: {}", path.trim_end_matches('\0'));
+        return false;
+    };
+    let mut header = [0u8; SPLASH_HEADER_SIZE];
+    file.read(&mut header);
+    if &header[..4] != SPLASH_MAGIC || header[4] != SPLASH_VERSION || header[6] != 2 {
+        nitro::println!("Invalid splash screen header");
+        return false;
+    }
+    let is_4bpp = header[5] == 4;
+    let is_compressed = header[7] & SPLASH_FLAG_LZ77 != 0;
 
     // 初始化上下屏显存总线，设置它们的显存映射范围
     unsafe {
@@ -39,31 +188,50 @@ pub fn load_splash_screen() {
 
     unsafe {
         // 1 << 2: 设置图块集初始内存位置为 显存位置+0x4000
-        // 1 << 7: 设置成 256 色模式（8bpp）
-        let v = (1 << 7) | (1 << 2);
+        // 1 << 7: 设置成 256 色模式（8bpp），4bpp 时使用 16 组 16 色调色板
+        let v = if is_4bpp { 1 << 2 } else { (1 << 7) | (1 << 2) };
         *(REG_BG0CNT_ADDR as *mut u16) = v;
         *(REG_DB_BG0CNT_ADDR as *mut u16) = v;
     }
 
-    for i in 0..32 * 24 {
-        unsafe {
-            *((0x06000000 as *mut u16).offset(i as isize)) = i;
-        }
-    }
-
-    for i in 0..32 * 24 {
-        unsafe {
-            *((0x06200000 as *mut u16).offset(i as isize)) = i;
-        }
-    }
+    let tile_size = if is_4bpp { 8 * 8 / 2 } else { 8 * 8 };
+    let screens = [
+        (HW_BG_PLTT, HW_BG_PLTT_SIZE, 0x06000000usize),
+        (HW_DB_BG_PLTT, HW_DB_BG_PLTT_SIZE, 0x06200000),
+    ];
+    for (i, (pltt_addr, pltt_size, vram_addr)) in screens.into_iter().enumerate() {
+        let info = &header[8 + i * 4..12 + i * 4];
+        let palette_colors = u16::from_le_bytes([info[0], info[1]]) as usize;
+        let tile_count = u16::from_le_bytes([info[2], info[3]]) as usize;
 
-    file.read(unsafe {
-        core::slice::from_raw_parts_mut((0x06000000 + 0x4000) as _, 8 * 8 * 32 * 24)
-    });
+        // 读取并设置调色板，每个屏幕使用各自的调色板
+        let pltt =
+            unsafe { core::slice::from_raw_parts_mut(pltt_addr as *mut u8, pltt_size as usize) };
+        let palette_size = (palette_colors * 2).min(pltt.len());
+        let tilemap =
+            unsafe { core::slice::from_raw_parts_mut(vram_addr as *mut u8, SPLASH_TILEMAP_SIZE) };
+        let tiles = unsafe {
+            core::slice::from_raw_parts_mut((vram_addr + 0x4000) as *mut u8, tile_count * tile_size)
+        };
 
-    file.read(unsafe {
-        core::slice::from_raw_parts_mut((0x06200000 + 0x4000) as _, 8 * 8 * 32 * 24)
-    });
+        if is_compressed {
+            // 直接解压到调色板和显存
+            if lz77::decompress_from_file(&mut file, &mut pltt[..palette_size]).is_none()
+                || lz77::decompress_from_file(&mut file, tilemap).is_none()
+                || lz77::decompress_from_file(&mut file, tiles).is_none()
+            {
+                nitro::println!("Invalid compressed splash screen");
+                return false;
+            }
+            continue;
+        }
 
-    // REG_VRAMCNT_ADDR
+        let mut palette = [0u8; 256 * 2];
+        let palette = &mut palette[..palette_size];
+        file.read(palette);
+        nitro::mem::copy(palette, pltt);
+        file.read(tilemap);
+        file.read(tiles);
+    }
+    true
 }
@@ -9,16 +9,26 @@ pub fn set_brightness(level: i8) {
     }
 }
 
-pub fn fade_in() {
-    for i in (0..=16).rev() {
-        set_brightness(i);
+/// 在指定帧数内从全白淡入，帧数为 0 时立即显示
+pub fn fade_in(frames: u8) {
+    if frames == 0 {
+        set_brightness(0);
+        return;
+    }
+    for i in (0..=frames as i32).rev() {
+        set_brightness((i * 16 / frames as i32) as i8);
         nitro::irq::wait_vblank();
     }
 }
 
-pub fn fade_out() {
-    for i in 0..=16 {
-        set_brightness(i);
+/// 在指定帧数内淡出到全白，帧数为 0 时立即变白
+pub fn fade_out(frames: u8) {
+    if frames == 0 {
+        set_brightness(16);
+        return;
+    }
+    for i in 0..=frames as i32 {
+        set_brightness((i * 16 / frames as i32) as i8);
         nitro::irq::wait_vblank();
     }
 }
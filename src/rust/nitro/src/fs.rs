@@ -24,6 +24,19 @@ impl File {
         file
     }
 
+    /// 尝试打开文件，文件不存在时返回 `None`
+    #[inline(always)]
+    pub fn try_open(path: &str) -> Option<Self> {
+        debug_assert!(path.ends_with('\0'), "path {path:?} must end with '\\0'");
+        let mut file = Self(nitro_sys::FSFile::default(), false);
+        file.init();
+        if unsafe { nitro_sys::FS_OpenFile(&mut file.0, path.as_ptr() as _) } == 0 {
+            return None;
+        }
+        file.1 = true;
+        Some(file)
+    }
+
     #[inline(always)]
     pub fn init(&mut self) {
         unsafe {
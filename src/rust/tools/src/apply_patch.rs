@@ -0,0 +1,200 @@
+use std::{
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::*;
+use tools::utils::{
+    fs::write_atomic,
+    hash::FileHashes,
+    patch::{PatchFormat, ReleaseManifest, ReleaseRom, RELEASE_MANIFEST_FILE},
+};
+
+const USAGE: &str = "用法：apply_patch <原版 ROM> [输出路径] [选项]
+也可以直接把原版 ROM 拖放到本程序上，修补后的 ROM 会保存在原版 ROM 所在的文件夹
+选项：
+  --patch-dir <文件夹>        补丁和 release.json 所在的文件夹，默认为本程序所在的文件夹";
+
+/// NDS ROM 头部中记录实际使用的 ROM 大小的位置
+const HEADER_USED_SIZE_OFFSET: usize = 0x80;
+
+struct Options {
+    rom: PathBuf,
+    output: Option<PathBuf>,
+    patch_dir: Option<PathBuf>,
+}
+
+impl Options {
+    fn from_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut rom = None;
+        let mut output = None;
+        let mut patch_dir = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--patch-dir" => {
+                    patch_dir = Some(
+                        args.next()
+                            .with_context(|| format!("选项 {arg} 缺少参数\n{USAGE}"))?
+                            .into(),
+                    )
+                }
+                _ if arg.starts_with("--") => bail!("未知参数 {arg}\n{USAGE}"),
+                _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+                _ if output.is_none() => output = Some(PathBuf::from(arg)),
+                _ => bail!("多余的参数 {arg}\n{USAGE}"),
+            }
+        }
+        Ok(Self {
+            rom: rom.context(USAGE)?,
+            output,
+            patch_dir,
+        })
+    }
+
+    /// 未指定时依次在本程序所在的文件夹和当前文件夹中查找发布清单
+    fn patch_dir(&self) -> anyhow::Result<PathBuf> {
+        if let Some(dir) = &self.patch_dir {
+            return Ok(dir.clone());
+        }
+        let exe_dir = std::env::current_exe()
+            .ok()
+            .and_then(|x| x.parent().map(Path::to_path_buf));
+        exe_dir
+            .into_iter()
+            .chain(std::env::current_dir().ok())
+            .find(|dir| dir.join(RELEASE_MANIFEST_FILE).is_file())
+            .with_context(|| {
+                format!("找不到 {RELEASE_MANIFEST_FILE}，请把本程序和补丁文件放在同一文件夹下")
+            })
+    }
+}
+
+fn version_name(version: &str) -> &str {
+    match version {
+        "ninja" => "忍者版",
+        "saurian" => "恐龙版",
+        _ => version,
+    }
+}
+
+/// 根据 ROM 的哈希找出对应的原版 ROM，无法识别时说明可能的原因
+fn identify_rom<'a>(manifest: &'a ReleaseManifest, rom: &[u8]) -> anyhow::Result<&'a ReleaseRom> {
+    let hashes = FileHashes::new(rom);
+    if let Some(info) = manifest.roms.iter().find(|x| x.source == hashes) {
+        return Ok(info);
+    }
+    if let Some(info) = manifest.roms.iter().find(|x| x.target == hashes) {
+        bail!(
+            "这个 ROM 已经是{}的汉化版 {}，不需要再次修补",
+            version_name(&info.version),
+            manifest.release
+        );
+    }
+
+    let expected = manifest
+        .roms
+        .iter()
+        .map(|x| {
+            format!(
+                "  {}：{} 字节，CRC32 {}",
+                version_name(&x.version),
+                x.source.size,
+                x.source.crc32
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let used_size = rom
+        .get(HEADER_USED_SIZE_OFFSET..HEADER_USED_SIZE_OFFSET + 4)
+        .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]) as u64);
+    let min_size = manifest.roms.iter().map(|x| x.source.size).min();
+    if min_size.is_some_and(|min_size| hashes.size < min_size) {
+        // 裁剪后的 ROM 仍然包含头部记录的全部数据，只是去掉了末尾的填充
+        let trimmed = used_size.is_some_and(|used_size| hashes.size >= used_size);
+        bail!(
+            "ROM 大小为 {} 字节，比原版 ROM 小，{}\n请使用未经裁剪的完整 ROM dump，支持的原版 ROM：\n{expected}",
+            hashes.size,
+            if trimmed {
+                "可能经过裁剪（trim）"
+            } else {
+                "文件可能不完整"
+            }
+        );
+    }
+    bail!(
+        "无法识别这个 ROM（{} 字节，CRC32 {}），可能是损坏的 dump 或者其他版本的游戏\n支持的原版 ROM：\n{expected}",
+        hashes.size,
+        hashes.crc32
+    )
+}
+
+fn run() -> anyhow::Result<()> {
+    let options = Options::from_args(std::env::args().skip(1))?;
+    let patch_dir = options.patch_dir()?;
+    let manifest: ReleaseManifest = serde_json::from_slice(
+        &std::fs::read(patch_dir.join(RELEASE_MANIFEST_FILE))
+            .with_context(|| format!("无法读取 {RELEASE_MANIFEST_FILE}"))?,
+    )
+    .with_context(|| format!("无法解析 {RELEASE_MANIFEST_FILE}"))?;
+
+    let rom = std::fs::read(&options.rom)
+        .with_context(|| format!("无法读取 ROM {}", options.rom.display()))?;
+    println!("正在校验 {}", options.rom.display());
+    let info = identify_rom(&manifest, &rom)?;
+    println!("识别为 流星洛克人 2 {}", version_name(&info.version));
+
+    let patch_info = info
+        .patches
+        .iter()
+        .find(|x| x.format == PatchFormat::Bps)
+        .with_context(|| {
+            format!(
+                "{RELEASE_MANIFEST_FILE} 中没有{}的 BPS 补丁",
+                version_name(&info.version)
+            )
+        })?;
+    let patch_path = patch_dir.join(&patch_info.file);
+    let patch = std::fs::read(&patch_path)
+        .with_context(|| format!("无法读取补丁 {}", patch_path.display()))?;
+
+    println!("正在应用补丁 {}", patch_info.file);
+    let output = flips::BpsPatch::new(patch)
+        .apply(&rom)
+        .context("应用补丁失败，补丁文件可能已损坏")?;
+    let output = output.as_ref();
+    ensure!(
+        FileHashes::new(output) == info.target,
+        "修补后的 ROM 校验失败，补丁文件可能已损坏，请重新下载"
+    );
+
+    let output_path = options.output.clone().unwrap_or_else(|| {
+        options
+            .rom
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(&info.file)
+    });
+    write_atomic(&output_path, output)
+        .with_context(|| format!("无法写入 {}", output_path.display()))?;
+    println!("修补完成，已保存到 {}", output_path.display());
+    Ok(())
+}
+
+pub fn main() -> ExitCode {
+    let result = run();
+    if let Err(err) = &result {
+        eprintln!("错误：{err:?}");
+    }
+    // Windows 下拖放启动时窗口会在结束后立即关闭，等待按键以便看到结果
+    if cfg!(target_os = "windows") && std::io::stdin().is_terminal() {
+        println!("按回车键退出");
+        let _ = std::io::stdin().read_line(&mut String::new());
+    }
+    if result.is_ok() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,485 @@
+//! 按实际字宽检查译文的行宽和消息框行数
+//!
+//! 用 genfont 生成的字符表编码 `tpl` 中的文本，再按 `font3_width.bin` 中的字宽累加每行的宽度，
+//! 与 arm9 的 `fontapi_move_draw_cursor` 移动光标的方式一致。
+//! `print` 系列指令的输出经过打印缓冲区，每个字符固定 11px，字符数按 `[check_text]` 中的估计值计算。
+//! 渲染方式为 0x400/0x600 的文本每个字符也固定 11px，但无法从脚本本身判断，
+//! 需要在 `rnr2cn.toml` 的 `[[check_text.rules]]` 中为这些脚本设置 `fixed_width = true`。
+
+use std::{collections::BTreeSet, path::PathBuf};
+
+use anyhow::*;
+
+use crate::utils::{
+    project::{CheckTextConfig, Project},
+    tbl::code_to_glyph_index,
+    text_archive::{
+        read_tpl_dir, read_tpl_file, EndType, ParameterValue, Script, ScriptElement, TextCodec,
+    },
+};
+
+const USAGE: &str = "用法：rnr2cn check-text [tpl 文件或文件夹]...
+按实际字宽检查译文的行宽和消息框行数，默认检查 tpl/mess_tpl
+需要先运行 rnr2cn genfont 生成字符表和字宽表，宽度和行数的限制在 rnr2cn.toml 的 [check_text] 表中设置";
+
+/// 渲染方式为 0x400/0x600 时每个字符的宽度
+const FIXED_CHAR_WIDTH: u32 = 11;
+/// 换行符的编码
+const NEWLINE: u8 = 0xE9;
+/// 除指令数据库的 `splt` 和结束脚本的指令外，开始新的消息框的指令
+const BOX_COMMANDS: [&str; 4] = ["msgOpen", "msgOpenQuick", "msgClose", "msgCloseQuick"];
+
+/// 消息框中的一行
+#[derive(Default)]
+struct Line {
+    width: u32,
+    text: String,
+    /// 这一行开始时是否显示头像
+    mugshot: bool,
+    /// 按估计的字符数计入宽度的 print 指令
+    estimated: Vec<String>,
+}
+
+impl Line {
+    fn is_empty(&self) -> bool {
+        self.width == 0 && self.text.is_empty() && self.estimated.is_empty()
+    }
+}
+
+/// 一个脚本使用的限制，由 [`CheckTextConfig`] 和匹配的规则决定
+struct Limits {
+    fixed_width: bool,
+    line_width: Option<u32>,
+    box_lines: usize,
+}
+
+struct Checker<'a> {
+    codec: &'a TextCodec,
+    widths: &'a [u8],
+    config: &'a CheckTextConfig,
+    /// `space` 指令每格的宽度，取字符表中半角空格的字宽
+    space_width: u32,
+    /// 超出字宽表范围的字符序号
+    missing: BTreeSet<u32>,
+    issues: Vec<String>,
+}
+
+impl Checker<'_> {
+    fn glyph_width(&mut self, index: u32) -> u32 {
+        match self.widths.get(index as usize) {
+            Some(&width) => width as u32,
+            None => {
+                self.missing.insert(index);
+                0
+            }
+        }
+    }
+
+    fn limits(&self, archive: &str, script: usize) -> Option<Limits> {
+        let rule = self
+            .config
+            .rules
+            .iter()
+            .find(|x| x.matches(archive, script));
+        if rule.is_some_and(|x| x.skip) {
+            return None;
+        }
+        Some(Limits {
+            fixed_width: rule.is_some_and(|x| x.fixed_width),
+            line_width: rule.and_then(|x| x.line_width),
+            box_lines: rule
+                .and_then(|x| x.box_lines)
+                .unwrap_or(self.config.box_lines),
+        })
+    }
+
+    fn check_script(&mut self, id: &str, script: &Script, limits: &Limits) {
+        let mut lines = vec![Line::default()];
+        let mut mugshot = false;
+        let mut box_index = 1;
+        for element in &script.elements {
+            match element {
+                ScriptElement::Text(text) => {
+                    let mut data = Vec::new();
+                    if let Err(err) = self.codec.encode_text(text, &mut data) {
+                        self.issues
+                            .push(format!("{id}：{err:#}，请先运行 rnr2cn genfont"));
+                        return;
+                    }
+                    self.measure(&data, limits, mugshot, &mut lines);
+                }
+                ScriptElement::Command { name, parameters } => {
+                    let database = &self.codec.database;
+                    let definition = database.get(name);
+                    if BOX_COMMANDS.contains(&name.as_str())
+                        || database.split_commands.contains(name)
+                        || definition.is_some_and(|x| x.ends == EndType::Always)
+                    {
+                        if self.finish_box(id, box_index, &lines, limits) {
+                            box_index += 1;
+                        }
+                        lines = vec![Line::default()];
+                    } else if let Some(param) = definition.and_then(|x| x.mugshot.as_ref()) {
+                        // 部分显示头像的指令的头像由游戏状态决定，插件中 mugs 为空，按指令名判断
+                        mugshot = !param.is_empty() || name.starts_with("mugshotShow");
+                    } else if name == "space" || name == "spacePx" {
+                        let count = number_parameter(parameters, "count").unwrap_or_default();
+                        let unit = match (name.as_str(), limits.fixed_width) {
+                            ("spacePx", _) => 1,
+                            (_, true) => FIXED_CHAR_WIDTH,
+                            (_, false) => self.space_width,
+                        };
+                        current_line(&mut lines, mugshot).width += count * unit;
+                    } else if self.is_print_command(name) {
+                        let chars = self.print_chars(name, parameters);
+                        if chars > 0 {
+                            let line = current_line(&mut lines, mugshot);
+                            line.width += chars * FIXED_CHAR_WIDTH;
+                            line.estimated.push(name.clone());
+                        }
+                    }
+                }
+            }
+        }
+        self.finish_box(id, box_index, &lines, limits);
+    }
+
+    /// 指令是否通过打印缓冲区输出文本，见 arm9 的 `fontapi_move_draw_cursor`
+    fn is_print_command(&self, name: &str) -> bool {
+        self.codec.database.get(name).is_some_and(|x| x.prints)
+            && self
+                .config
+                .print_commands
+                .iter()
+                .any(|x| name.starts_with(x.as_str()))
+    }
+
+    /// print 指令输出的字符数，`printBuffer` 按最少输出的字符数和估计值中较大的一个计算
+    fn print_chars(&self, name: &str, parameters: &[(String, ParameterValue)]) -> u32 {
+        if let Some(&chars) = self.config.print_lengths.get(name) {
+            return chars;
+        }
+        number_parameter(parameters, "minLength")
+            .unwrap_or_default()
+            .max(self.config.print_chars)
+    }
+
+    /// 按 `arm9::script::decode_script` 的规则逐个字符累加宽度
+    fn measure(&mut self, data: &[u8], limits: &Limits, mugshot: bool, lines: &mut Vec<Line>) {
+        let mut pos = 0;
+        while pos < data.len() {
+            if data[pos] == NEWLINE {
+                lines.push(Line::default());
+                pos += 1;
+                continue;
+            }
+            let len = if (0xD0..=0xE4).contains(&data[pos]) {
+                2
+            } else {
+                1
+            };
+            let code = &data[pos..(pos + len).min(data.len())];
+            pos += len;
+            // 其他控制字节不占宽度
+            let Some(index) = code_to_glyph_index(code) else {
+                continue;
+            };
+            let width = if limits.fixed_width {
+                FIXED_CHAR_WIDTH
+            } else {
+                self.glyph_width(index)
+            };
+            let line = current_line(lines, mugshot);
+            line.width += width;
+            line.text
+                .push_str(self.codec.table.get_text(code).unwrap_or("?"));
+        }
+    }
+
+    /// 检查一个消息框中的各行，消息框中有内容时返回 `true`
+    fn finish_box(&mut self, id: &str, box_index: usize, lines: &[Line], limits: &Limits) -> bool {
+        let used = lines
+            .iter()
+            .rposition(|x| !x.is_empty())
+            .map_or(0, |x| x + 1);
+        for (i, line) in lines[..used].iter().enumerate() {
+            let limit = limits.line_width.unwrap_or(if line.mugshot {
+                self.config.mugshot_line_width
+            } else {
+                self.config.line_width
+            });
+            if line.width <= limit {
+                continue;
+            }
+            let mut issue = format!(
+                "{id} 第 {box_index} 个消息框第 {} 行宽 {}px，超过 {limit}px：{}",
+                i + 1,
+                line.width,
+                line.text
+            );
+            if !line.estimated.is_empty() {
+                issue += &format!(
+                    "（{} 输出的文本按估计的字符数计算）",
+                    line.estimated.join("、")
+                );
+            }
+            self.issues.push(issue);
+        }
+        if limits.box_lines > 0 && used > limits.box_lines {
+            self.issues.push(format!(
+                "{id} 第 {box_index} 个消息框有 {used} 行，超过 {} 行",
+                limits.box_lines
+            ));
+        }
+        used > 0
+    }
+}
+
+fn number_parameter(parameters: &[(String, ParameterValue)], key: &str) -> Option<u32> {
+    parameters.iter().find_map(|(name, value)| match value {
+        ParameterValue::Number(x) if name == key => Some(*x),
+        _ => None,
+    })
+}
+
+/// 当前行，行中还没有内容时记录是否显示头像
+fn current_line(lines: &mut [Line], mugshot: bool) -> &mut Line {
+    let line = lines.last_mut().unwrap();
+    if line.is_empty() {
+        line.mugshot = mugshot;
+    }
+    line
+}
+
+pub fn run(project: &Project, args: &[String]) -> anyhow::Result<()> {
+    let paths = &project.paths;
+    let mut inputs = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => bail!("未知参数 {arg}\n{USAGE}"),
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+    if inputs.is_empty() {
+        inputs.push(paths.tpl.join("mess_tpl"));
+    }
+
+    let widths_path = paths.fonts.join("font3_width.bin");
+    let widths = std::fs::read(&widths_path).with_context(|| {
+        format!(
+            "无法读取字宽表 {}，请先运行 rnr2cn genfont",
+            widths_path.display()
+        )
+    })?;
+    let codec = TextCodec::load(&paths.plugins, "rnr2-cn")?;
+    let space_width = codec
+        .table
+        .get_code(" ")
+        .and_then(code_to_glyph_index)
+        .and_then(|x| widths.get(x as usize))
+        .map_or(6, |&x| x as u32);
+
+    let mut archives = Vec::new();
+    for input in &inputs {
+        if input.is_dir() {
+            archives.extend(read_tpl_dir(input, true)?);
+        } else {
+            archives.extend(read_tpl_file(input)?);
+        }
+    }
+
+    let mut checker = Checker {
+        codec: &codec,
+        widths: &widths,
+        config: &project.config.check_text,
+        space_width,
+        missing: BTreeSet::new(),
+        issues: Vec::new(),
+    };
+    let mut count = 0;
+    for archive in &archives {
+        for (index, script) in &archive.scripts {
+            let Some(limits) = checker.limits(&archive.id, *index) else {
+                if project.verbose {
+                    println!("跳过 {} script {index}", archive.id);
+                }
+                continue;
+            };
+            checker.check_script(&format!("{} script {index}", archive.id), script, &limits);
+            count += 1;
+        }
+    }
+
+    for issue in &checker.issues {
+        println!("{issue}");
+    }
+    if !checker.missing.is_empty() {
+        println!(
+            "警告：字宽表 {} 中没有 {} 个字符的宽度，请重新运行 rnr2cn genfont",
+            widths_path.display(),
+            checker.missing.len()
+        );
+    }
+    ensure!(
+        checker.issues.is_empty(),
+        "检查了 {count} 个脚本，发现 {} 处问题",
+        checker.issues.len()
+    );
+    println!("检查了 {count} 个脚本，没有发现问题");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::project::ProjectConfig;
+    use std::path::Path;
+
+    fn codec() -> TextCodec {
+        let plugins = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../../tools/plugins");
+        TextCodec::load(plugins, "rnr2-cn").unwrap()
+    }
+
+    /// 除特别设置的字符外，字宽都是 10px
+    fn widths(special: &[(u32, u8)]) -> Vec<u8> {
+        let mut widths = vec![10; 0x1000];
+        for &(index, width) in special {
+            widths[index as usize] = width;
+        }
+        widths
+    }
+
+    fn new_checker<'a>(
+        codec: &'a TextCodec,
+        widths: &'a [u8],
+        config: &'a CheckTextConfig,
+    ) -> Checker<'a> {
+        Checker {
+            codec,
+            widths,
+            config,
+            space_width: 6,
+            missing: BTreeSet::new(),
+            issues: Vec::new(),
+        }
+    }
+
+    fn limits(fixed_width: bool) -> Limits {
+        Limits {
+            fixed_width,
+            line_width: None,
+            box_lines: 3,
+        }
+    }
+
+    fn measure(checker: &mut Checker, data: &[u8], limits: &Limits, mugshot: bool) -> Vec<Line> {
+        let mut lines = vec![Line::default()];
+        checker.measure(data, limits, mugshot, &mut lines);
+        lines
+    }
+
+    #[test]
+    fn newline_starts_a_new_line() {
+        let (codec, config) = (codec(), CheckTextConfig::default());
+        let widths = widths(&[(1, 5), (2, 7), (3, 9)]);
+        let mut checker = new_checker(&codec, &widths, &config);
+        let lines = measure(
+            &mut checker,
+            &[0x01, 0x02, NEWLINE, 0x03],
+            &limits(false),
+            false,
+        );
+        assert_eq!(lines.iter().map(|x| x.width).collect::<Vec<_>>(), [12, 9]);
+        assert_eq!(lines[0].text, "０１");
+        assert_eq!(lines[1].text, "２");
+    }
+
+    #[test]
+    fn two_byte_glyphs_use_their_glyph_width() {
+        let (codec, config) = (codec(), CheckTextConfig::default());
+        // D005 的字符序号为 0xD5，E401 为 0xE5，其他控制字节不占宽度
+        let widths = widths(&[(0xD5, 3), (0xE5, 4), (0xD0 + 0xE4 + 0x2F, 6)]);
+        let mut checker = new_checker(&codec, &widths, &config);
+        let data = [0xD0, 0x05, 0xE4, 0x01, 0xD1, 0x2F, 0xE6];
+        let lines = measure(&mut checker, &data, &limits(false), false);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].width, 13);
+        assert_eq!(lines[0].text, "でぁ ");
+        assert!(checker.missing.is_empty());
+
+        let short = [0u8; 4];
+        let mut checker = new_checker(&codec, &short, &config);
+        measure(&mut checker, &[0x01, 0xD0, 0x05], &limits(false), false);
+        assert_eq!(checker.missing, BTreeSet::from([0xD5]));
+    }
+
+    #[test]
+    fn fixed_width_counts_every_glyph_as_11px() {
+        let (codec, config) = (codec(), CheckTextConfig::default());
+        let widths = widths(&[(1, 5), (0xD5, 3)]);
+        let mut checker = new_checker(&codec, &widths, &config);
+        let lines = measure(
+            &mut checker,
+            &[0x01, 0xD0, 0x05, 0xE6],
+            &limits(true),
+            false,
+        );
+        assert_eq!(lines[0].width, 2 * FIXED_CHAR_WIDTH);
+    }
+
+    #[test]
+    fn mugshot_lines_use_the_narrower_width() {
+        let (codec, config) = (codec(), CheckTextConfig::default());
+        let widths = widths(&[]);
+        // 18 个字符宽 180px，超过显示头像时的 168px，但不超过 216px
+        let data = [0x01; 18];
+        for (mugshot, expected) in [(false, 0), (true, 1)] {
+            let mut checker = new_checker(&codec, &widths, &config);
+            let lines = measure(&mut checker, &data, &limits(false), mugshot);
+            assert_eq!(lines[0].mugshot, mugshot);
+            assert!(checker.finish_box("mess_test script 0", 1, &lines, &limits(false)));
+            assert_eq!(checker.issues.len(), expected, "{:?}", checker.issues);
+        }
+    }
+
+    #[test]
+    fn box_overflow_is_reported() {
+        let (codec, config) = (codec(), CheckTextConfig::default());
+        let widths = widths(&[]);
+        let mut checker = new_checker(&codec, &widths, &config);
+        // 结尾的空行不计入行数
+        let mut data = [0x01, NEWLINE].repeat(4);
+        data.extend([NEWLINE, NEWLINE]);
+        let lines = measure(&mut checker, &data, &limits(false), false);
+        assert!(checker.finish_box("mess_test script 2", 5, &lines, &limits(false)));
+        assert_eq!(
+            checker.issues,
+            ["mess_test script 2 第 5 个消息框有 4 行，超过 3 行"]
+        );
+
+        let mut checker = new_checker(&codec, &widths, &config);
+        let lines = measure(&mut checker, &[0x01; 22], &limits(false), false);
+        checker.finish_box("mess_test script 2", 1, &lines, &limits(false));
+        assert_eq!(
+            checker.issues,
+            [format!(
+                "mess_test script 2 第 1 个消息框第 1 行宽 220px，超过 216px：{}",
+                "０".repeat(22)
+            )]
+        );
+
+        let mut checker = new_checker(&codec, &widths, &config);
+        assert!(!checker.finish_box("mess_test script 2", 1, &[Line::default()], &limits(false)));
+        assert!(checker.issues.is_empty());
+    }
+
+    #[test]
+    fn unknown_config_fields_are_rejected() {
+        assert!(toml::from_str::<ProjectConfig>("[check_text]\nline_width = 200\n").is_ok());
+        assert!(toml::from_str::<ProjectConfig>("[check_text]\nline_widht = 200\n").is_err());
+    }
+}
@@ -0,0 +1,45 @@
+use anyhow::*;
+
+use crate::utils::project::Project;
+
+const USAGE: &str = "用法：rnr2cn clean [选项]
+默认删除中间文件 _temp
+选项：
+  --build       同时删除打包生成的 _build
+  --workspace   同时删除 setup 解包的 _workspace，之后需要重新运行 setup";
+
+pub fn run(project: &Project, args: &[String]) -> anyhow::Result<()> {
+    let paths = &project.paths;
+    let (mut build, mut workspace) = (false, false);
+    for arg in args {
+        match arg.as_str() {
+            "--build" => build = true,
+            "--workspace" => workspace = true,
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => bail!("未知参数 {arg}\n{USAGE}"),
+        }
+    }
+    let dirs = [
+        Some(&paths.temp),
+        build.then_some(&paths.build),
+        workspace.then_some(&paths.workspace),
+    ];
+
+    for dir in dirs.into_iter().flatten() {
+        if !dir.exists() {
+            if project.verbose {
+                println!("{} 不存在", dir.display());
+            }
+            continue;
+        }
+        if project.skip_in_dry_run(format_args!("删除 {}", dir.display())) {
+            continue;
+        }
+        std::fs::remove_dir_all(dir).with_context(|| format!("无法删除 {}", dir.display()))?;
+        println!("已删除 {}", dir.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use crate::utils::project::Project;
+
+pub fn run(project: &Project, args: &[String]) -> anyhow::Result<()> {
+    super::ensure_no_args("compile", args)?;
+    let paths = &project.paths;
+
+    let ninja_target_dir_path = paths.temp.join("target-ninja");
+    let saurian_target_dir_path = paths.temp.join("target-saurian");
+
+    let target = "armv5te-none-eabi";
+    // let target = "thumbv5te-none-eabi";
+
+    project.run(
+        std::process::Command::new("rustup")
+            .arg("run")
+            .arg("nightly")
+            .arg("cargo")
+            .arg("build")
+            .arg("-Zbuild-std=core,alloc")
+            .arg("--release")
+            .arg("--no-default-features")
+            .arg("--features")
+            .arg("ninja")
+            .arg("--package")
+            .arg("arm9")
+            .arg("--target")
+            .arg(target)
+            .arg("--target-dir")
+            .arg(&ninja_target_dir_path),
+    )?;
+
+    project.run(
+        std::process::Command::new("rustup")
+            .arg("run")
+            .arg("nightly")
+            .arg("cargo")
+            .arg("build")
+            .arg("-Zbuild-std=core,alloc")
+            .arg("--release")
+            .arg("--no-default-features")
+            .arg("--features")
+            .arg("saurian")
+            .arg("--package")
+            .arg("arm9")
+            .arg("--target")
+            .arg(target)
+            .arg("--target-dir")
+            .arg(&saurian_target_dir_path),
+    )?;
+
+    if project.skip_in_dry_run(format_args!("复制编译结果到 {}", paths.asm.display())) {
+        return Ok(());
+    }
+    let ninja_target_bin_path = ninja_target_dir_path.join(target).join("release/arm9");
+    let saurian_target_bin_path = saurian_target_dir_path.join(target).join("release/arm9");
+
+    std::fs::copy(ninja_target_bin_path, paths.asm.join("ninja/rust-code.bin"))?;
+    std::fs::copy(
+        saurian_target_bin_path,
+        paths.asm.join("saurian/rust-code.bin"),
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use crate::utils::project::Project;
+
+pub fn run(project: &Project, args: &[String]) -> anyhow::Result<()> {
+    super::ensure_no_args("compile-zig", args)?;
+    let paths = &project.paths;
+
+    // let target = "thumbv5te-none-eabi";
+
+    project.run(
+        std::process::Command::new("zig")
+            .arg("build")
+            .arg("-Drelease=true")
+            .current_dir(paths.root.join("src/zig")),
+    )?;
+
+    if project.skip_in_dry_run(format_args!("复制编译结果到 {}", paths.asm.display())) {
+        return Ok(());
+    }
+    let ninja_target_bin_path = paths.root.join("src/zig/zig-out/lib/libninja.a");
+    let saurian_target_bin_path = paths.root.join("src/zig/zig-out/lib/libsaurian.a");
+
+    std::fs::copy(ninja_target_bin_path, paths.asm.join("ninja/zig-code.bin"))?;
+    std::fs::copy(
+        saurian_target_bin_path,
+        paths.asm.join("saurian/zig-code.bin"),
+    )?;
+
+    Ok(())
+}
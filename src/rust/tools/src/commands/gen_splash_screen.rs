@@ -0,0 +1,462 @@
+use std::{collections::HashMap, io::Read, path::Path, time::Instant};
+
+use crate::utils::{
+    color_match::TileRegion,
+    fs::write_atomic,
+    jobs::JobRunner,
+    lz77,
+    project::Project,
+    splash::SplashPages,
+    tile_img::{extract_palette_banks, find_tile, TilemapEntry},
+};
+use anyhow::*;
+use image::RgbImage;
+use rscolorq::*;
+use sfbase::{GBAColor, Tile, Tile4BPP, Tile8BPP};
+
+// splash-screen.bin 的格式见 arm9 的 splash_screen.rs
+const SPLASH_MAGIC: &[u8; 4] = b"SPLS";
+const SPLASH_VERSION: u8 = 1;
+const SPLASH_HEADER_SIZE: usize = 16;
+const SPLASH_FLAG_LZ77: u8 = 1 << 0;
+const SCREEN_HEIGHT: u32 = 192;
+
+const USAGE: &str = "用法：rnr2cn gen-splash-screen [选项]
+选项：
+  --palette-size <颜色数>     量化后的颜色数，默认 64
+  --dithering-level <等级>    抖动等级，auto 为根据图像大小自动选择，默认 auto
+  --filter-size <1|3|5>       滤波器大小，默认 3
+  --4bpp                      使用 16 组 16 色调色板编码图块，每个图块最多使用 15 种颜色和背景色，
+                              默认使用 256 色
+  --lz77                      使用 LZ77 压缩调色板、图块表和图块集，游戏启动时解压
+  --force                     忽略缓存，重新量化所有图像";
+
+/// 启动画面的生成参数，量化参数变化时也会重新量化
+#[derive(Debug, Clone)]
+struct SplashOptions {
+    palette_size: u8,
+    /// 为 `None` 时根据图像大小自动选择
+    dithering_level: Option<f64>,
+    filter_size: u8,
+    is_4bpp: bool,
+    lz77: bool,
+    force: bool,
+}
+
+impl Default for SplashOptions {
+    fn default() -> Self {
+        Self {
+            palette_size: 64,
+            dithering_level: None,
+            filter_size: 3,
+            is_4bpp: false,
+            lz77: false,
+            force: false,
+        }
+    }
+}
+
+impl SplashOptions {
+    /// `--help` 时返回 `None`
+    fn from_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Option<Self>> {
+        fn value<T: std::str::FromStr>(name: &str, value: Option<String>) -> anyhow::Result<T> {
+            value
+                .with_context(|| format!("选项 {name} 缺少参数\n{USAGE}"))?
+                .parse()
+                .ok()
+                .with_context(|| format!("选项 {name} 的参数无效\n{USAGE}"))
+        }
+
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--palette-size" => options.palette_size = value(&arg, args.next())?,
+                "--dithering-level" => {
+                    let level: String = value(&arg, args.next())?;
+                    options.dithering_level = if level == "auto" {
+                        None
+                    } else {
+                        Some(value(&arg, Some(level))?)
+                    };
+                }
+                "--filter-size" => options.filter_size = value(&arg, args.next())?,
+                "--4bpp" => options.is_4bpp = true,
+                "--lz77" => options.lz77 = true,
+                "--force" => options.force = true,
+                "--help" | "-h" => {
+                    println!("{USAGE}");
+                    return Ok(None);
+                }
+                _ => bail!("未知参数 {arg}\n{USAGE}"),
+            }
+        }
+        ensure!(
+            (2..=u8::MAX).contains(&options.palette_size),
+            "颜色数必须在 2 到 255 之间"
+        );
+        ensure!(
+            matches!(options.filter_size, 1 | 3 | 5),
+            "滤波器大小只能为 1、3 或 5"
+        );
+        Ok(Some(options))
+    }
+
+    fn filter_size(&self) -> FilterSize {
+        match self.filter_size {
+            1 => FilterSize::One,
+            5 => FilterSize::Five,
+            _ => FilterSize::Three,
+        }
+    }
+
+    /// 缓存中记录的内容，源图像或参数变化时都会改变
+    fn cache_key(&self, source_hash: &str) -> String {
+        let dithering_level = self
+            .dithering_level
+            .map(|x| x.to_string())
+            .unwrap_or_else(|| "auto".to_string());
+        format!(
+            "source {source_hash}\npalette_size {}\ndithering_level {dithering_level}\nfilter_size {}\n",
+            self.palette_size, self.filter_size
+        )
+    }
+}
+
+fn get_file_md5(input: impl AsRef<Path>) -> anyhow::Result<String> {
+    let mut buf = [0u8; 1024];
+    let mut ctx = md5::Context::new();
+    let mut file = std::fs::File::open(input)?;
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        ctx.consume(&buf[..len]);
+    }
+    Ok(format!("{:?}", ctx.compute()))
+}
+
+/// 一个屏幕编码后的调色板、32x24 图块表和去重后的图块集
+struct EncodedScreen {
+    palette: Vec<GBAColor>,
+    tilemap: Vec<u16>,
+    tiles: Vec<u8>,
+    tile_count: usize,
+}
+
+fn encode_screen<T: Tile>(img: &RgbImage, is_4bpp: bool) -> anyhow::Result<EncodedScreen> {
+    // 出现次数最多的颜色作为 0 号背景色
+    let mut colors = Vec::<(GBAColor, usize)>::with_capacity(256);
+    for p in img.pixels() {
+        let p = GBAColor::from(p);
+        if let Some(p) = colors.iter_mut().find(|x| x.0 == p) {
+            p.1 += 1;
+        } else {
+            colors.push((p, 1));
+        }
+    }
+    colors.sort_by_key(|x| std::cmp::Reverse(x.1));
+
+    let palette = if is_4bpp {
+        let region = TileRegion {
+            x: 0,
+            y: 0,
+            width: img.width() as usize,
+            height: img.height() as usize,
+        };
+        extract_palette_banks(img, &[region], colors[0].0)?
+            .into_iter()
+            .map(|x| x.0)
+            .collect::<Vec<_>>()
+    } else {
+        ensure!(
+            colors.len() <= 256,
+            "palette size should not be more than 256"
+        );
+        colors.into_iter().map(|x| x.0).collect::<Vec<_>>()
+    };
+    println!("palette size: {}", palette.len());
+    let indices = palette
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, c)| (*c, i))
+        .collect::<HashMap<_, _>>();
+
+    let mut tileset = Vec::<T>::new();
+    let mut tilemap = Vec::with_capacity(32 * 24);
+    for tile_y in 0..(img.height() as usize / 8) {
+        for tile_x in 0..(img.width() as usize / 8) {
+            let pixels = (0..64)
+                .map(|i| {
+                    GBAColor::from(
+                        img.get_pixel((tile_x * 8 + i % 8) as _, (tile_y * 8 + i / 8) as _),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            // 4bpp 图块使用能放下所有颜色的第一组调色板，颜色 0 为共用的背景色
+            let bank = if is_4bpp {
+                (0..palette.len().div_ceil(16))
+                    .find(|bank| {
+                        let bank_colors = &palette[bank * 16..palette.len().min(bank * 16 + 16)];
+                        pixels
+                            .iter()
+                            .all(|p| *p == palette[0] || bank_colors[1..].contains(p))
+                    })
+                    .context("Could not find a palette bank for tile")?
+            } else {
+                0
+            };
+
+            let mut tile = T::default();
+            for (i, p) in pixels.iter().enumerate() {
+                let index = if !is_4bpp {
+                    indices[p]
+                } else if *p == palette[0] {
+                    0
+                } else {
+                    palette[bank * 16 + 1..]
+                        .iter()
+                        .position(|x| x == p)
+                        .unwrap()
+                        + 1
+                };
+                tile.set_pixel(i % 8, i / 8, index as u8);
+            }
+
+            let entry = if let Some((pos, h_flip, v_flip)) = find_tile(&tileset, &tile) {
+                TilemapEntry {
+                    tile: pos,
+                    h_flip,
+                    v_flip,
+                    palette_bank: bank as u8,
+                }
+            } else {
+                tileset.push(tile);
+                ensure!(
+                    tileset.len() <= 1024,
+                    "tileset size should not be more than 1024"
+                );
+                TilemapEntry {
+                    tile: tileset.len() - 1,
+                    palette_bank: bank as u8,
+                    ..Default::default()
+                }
+            };
+            tilemap.push(entry.to_raw());
+        }
+    }
+    println!("tileset size: {}", tileset.len());
+
+    Ok(EncodedScreen {
+        palette,
+        tilemap,
+        tiles: tileset
+            .iter()
+            .flat_map(|x| x.as_raw().iter().copied())
+            .collect(),
+        tile_count: tileset.len(),
+    })
+}
+
+fn process_splash_screen_image_to_tiles(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    options: &SplashOptions,
+) -> anyhow::Result<()> {
+    let input = input.as_ref();
+    let output = output.as_ref();
+    let input_md5_cache = input.with_extension(format!(
+        "{}.md5",
+        input.extension().unwrap_or_default().to_string_lossy()
+    ));
+    let patch_output = input.with_extension(format!(
+        "patch.{}",
+        input.extension().unwrap_or_default().to_string_lossy()
+    ));
+
+    let source_hash = get_file_md5(input)?;
+    let cache_key = options.cache_key(&source_hash);
+    let cached = std::fs::read_to_string(&input_md5_cache).unwrap_or_default();
+    // 旧版缓存只记录源图像的哈希，不知道补丁的生成参数，没有指定参数时沿用已有的补丁
+    let is_cached = cached == cache_key
+        || (cached.trim() == source_hash
+            && cache_key == SplashOptions::default().cache_key(&source_hash));
+    let should_generate_patch = options.force || !patch_output.is_file() || !is_cached;
+
+    if should_generate_patch {
+        let img = image::open(input)?;
+        ensure!(img.width() == 256, "image width is not 256");
+        ensure!(img.height() == 384, "image height is not 384");
+        let mut img = img.into_rgb8();
+
+        let mut result = Matrix2d::new(img.width() as _, img.height() as _);
+        let mut conditions = Params::new();
+        conditions.palette_size(options.palette_size);
+        if let Some(level) = options.dithering_level {
+            conditions.dithering_level(level);
+        } else {
+            conditions.dithering_level_auto(img.width(), img.height(), options.palette_size as _);
+        }
+        conditions.filter_size(options.filter_size());
+        conditions.verify_parameters()?;
+
+        let input_img = Matrix2d::from_vec(
+            img.enumerate_pixels()
+                .map(|x| color::Rgb {
+                    red: x.2 .0[0] as f64 / 255.0,
+                    green: x.2 .0[1] as f64 / 255.0,
+                    blue: x.2 .0[2] as f64 / 255.0,
+                })
+                .collect(),
+            img.width() as _,
+            img.height() as _,
+        );
+
+        let mut palette = Vec::with_capacity(u8::MAX as _);
+
+        println!("processing image {}", input.to_string_lossy());
+        let t = Instant::now();
+        rscolorq::spatial_color_quant(&input_img, &mut result, &mut palette, &conditions)?;
+        println!(
+            "processed image {} in {:?}",
+            input.to_string_lossy(),
+            t.elapsed()
+        );
+
+        let palette = palette
+            .into_iter()
+            .map(|c| {
+                image::Rgb([
+                    (c.red * u8::MAX as f64) as u8,
+                    (c.green * u8::MAX as f64) as u8,
+                    (c.blue * u8::MAX as f64) as u8,
+                ])
+            })
+            .collect::<Vec<_>>();
+
+        for (i, &c) in result.iter().enumerate() {
+            let c = palette
+                .get(c as usize)
+                .context("Could not retrieve color from palette")?;
+            let x = i as u32 % img.width();
+            let y = i as u32 / img.width();
+            img.put_pixel(x, y, *c);
+        }
+        println!("output image {}", patch_output.to_string_lossy());
+        let temp_patch_output = patch_output.with_extension("tmp.png");
+        img.save(&temp_patch_output)?;
+        std::fs::rename(&temp_patch_output, &patch_output)?;
+        // 量化图像写出后才更新缓存，中断时下次会重新量化
+        write_atomic(&input_md5_cache, &cache_key)?;
+    } else {
+        println!("skipped processing image {}", input.to_string_lossy());
+        if cached != cache_key {
+            write_atomic(&input_md5_cache, &cache_key)?;
+        }
+    }
+
+    println!("generating tiles");
+    let img = image::open(&patch_output)?.into_rgb8();
+    ensure!(img.width() == 256, "image width is not 256");
+    ensure!(img.height() == 384, "image height is not 384");
+
+    let screens = [0, SCREEN_HEIGHT]
+        .into_iter()
+        .map(|y| {
+            let screen = image::imageops::crop_imm(&img, 0, y, 256, SCREEN_HEIGHT).to_image();
+            if options.is_4bpp {
+                encode_screen::<Tile4BPP>(&screen, true)
+            } else {
+                encode_screen::<Tile8BPP>(&screen, false)
+            }
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut data = Vec::with_capacity(SPLASH_HEADER_SIZE + 0x10000);
+    data.extend_from_slice(SPLASH_MAGIC);
+    data.push(SPLASH_VERSION);
+    data.push(if options.is_4bpp { 4 } else { 8 });
+    data.push(screens.len() as u8);
+    data.push(if options.lz77 { SPLASH_FLAG_LZ77 } else { 0 });
+    for screen in &screens {
+        data.extend_from_slice(&(screen.palette.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(screen.tile_count as u16).to_le_bytes());
+    }
+    for screen in &screens {
+        let palette = screen
+            .palette
+            .iter()
+            .flat_map(|c| c.to_le_bytes())
+            .collect::<Vec<_>>();
+        let tilemap = screen
+            .tilemap
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        for section in [&palette, &tilemap, &screen.tiles] {
+            if options.lz77 {
+                // 每段数据单独压缩，解压后直接写入调色板和显存
+                data.extend_from_slice(&lz77::compress(section));
+            } else {
+                data.extend_from_slice(section);
+            }
+        }
+    }
+    println!("output {} ({} bytes)", output.to_string_lossy(), data.len());
+    write_atomic(output, &data)?;
+
+    Ok(())
+}
+
+pub fn run(project: &Project, args: &[String]) -> anyhow::Result<()> {
+    let Some(options) = SplashOptions::from_args(args.iter().cloned())? else {
+        return Ok(());
+    };
+    let image_path = &project.paths.images;
+
+    let pages = SplashPages::load(image_path)?;
+
+    // 不含版本名的图像在两个版本中共用，只需处理一次
+    let mut jobs = Vec::new();
+    for page in &pages.pages {
+        for version in ["ninja", "saurian"] {
+            let job = (
+                page.source_image(image_path, version),
+                page.output_bin(image_path, version),
+            );
+            if !jobs.contains(&job) {
+                jobs.push(job);
+            }
+        }
+    }
+
+    if project.dry_run {
+        for (input, output) in &jobs {
+            println!(
+                "dry-run：将从 {} 生成 {}",
+                input.display(),
+                output.display()
+            );
+        }
+        return Ok(());
+    }
+
+    let options = &options;
+    let mut runner = JobRunner::new("生成启动画面");
+    for (input, output) in jobs {
+        let label = input
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        runner.add(label, move || {
+            process_splash_screen_image_to_tiles(&input, &output, options)
+        });
+    }
+    runner.run()?;
+
+    Ok(())
+}
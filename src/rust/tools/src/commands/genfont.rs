@@ -0,0 +1,237 @@
+use std::{collections::BTreeSet, path::Path};
+
+use anyhow::*;
+
+use crate::utils::{
+    font_raster::{self, RasterizeOptions},
+    project::Project,
+    tbl::TextTable,
+    text_archive,
+};
+
+const RASTERIZE_USAGE: &str = "用法：rnr2cn genfont rasterize <字体文件> <输出 .sfont 文件> [选项]
+选项：
+  --cell <宽>x<高>       单元格大小，例如 8x12、12x12，默认 12x12
+  --size <像素>          矢量字体的像素大小，默认与单元格高度相同
+  --baseline <像素>      基线偏移，正数向下
+  --x-shift <像素>       水平偏移，正数向右，-1 对应 .shifted 字形集
+  --fixed-width <像素>   所有字形使用固定字宽
+  --threshold <0~1>      矢量字体的笔画覆盖率阈值，默认 0.5
+  --shadow               添加阴影，对应 .shadow 字形集
+  --bold                 添加加粗阴影，对应 .shadow.bold 字形集
+  --chars <文件>         只栅格化文件中出现的字符，.tbl 文件取其中的单个字符";
+
+fn parse_option<T: std::str::FromStr>(name: &str, value: Option<&str>) -> anyhow::Result<T> {
+    value
+        .with_context(|| format!("选项 {name} 缺少参数\n{RASTERIZE_USAGE}"))?
+        .parse()
+        .ok()
+        .with_context(|| format!("选项 {name} 的参数无效\n{RASTERIZE_USAGE}"))
+}
+
+fn read_chars(path: &Path) -> anyhow::Result<BTreeSet<char>> {
+    let is_tbl = path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("tbl"));
+    if is_tbl {
+        Ok(TextTable::open(path)?
+            .entries
+            .iter()
+            .filter_map(|(_, text)| {
+                let mut chars = text.chars();
+                chars.next().filter(|_| chars.next().is_none())
+            })
+            .collect())
+    } else {
+        Ok(std::fs::read_to_string(path)
+            .with_context(|| format!("无法读取字符文件 {}", path.display()))?
+            .chars()
+            .filter(|x| !x.is_control())
+            .collect())
+    }
+}
+
+/// 将字体文件栅格化为 `gen-font` 使用的 `.sfont` 字形集
+fn rasterize(project: &Project, args: &[String]) -> anyhow::Result<()> {
+    let mut positional = Vec::new();
+    let (mut cell_width, mut cell_height) = (12, 12);
+    let (mut baseline_shift, mut x_shift) = (None, None);
+    let mut fixed_width = None;
+    let mut threshold = None;
+    let mut font_size = None;
+    let mut shadow = None;
+    let mut chars_path = None;
+    let mut args = args.iter().map(|x| x.as_str());
+    while let Some(arg) = args.next() {
+        match arg {
+            "--cell" => {
+                let value: String = parse_option(arg, args.next())?;
+                let (width, height) = value
+                    .split_once('x')
+                    .with_context(|| format!("单元格大小 {value} 无效，应为 <宽>x<高>"))?;
+                cell_width = parse_option(arg, Some(width))?;
+                cell_height = parse_option(arg, Some(height))?;
+            }
+            "--size" => font_size = Some(parse_option(arg, args.next())?),
+            "--baseline" => baseline_shift = Some(parse_option(arg, args.next())?),
+            "--x-shift" => x_shift = Some(parse_option(arg, args.next())?),
+            "--fixed-width" => fixed_width = Some(parse_option(arg, args.next())?),
+            "--threshold" => threshold = Some(parse_option(arg, args.next())?),
+            "--shadow" => shadow = Some(shadow.unwrap_or(false)),
+            "--bold" => shadow = Some(true),
+            "--chars" => chars_path = Some(parse_option::<String>(arg, args.next())?),
+            _ if arg.starts_with("--") => bail!("未知选项 {arg}\n{RASTERIZE_USAGE}"),
+            _ => positional.push(arg),
+        }
+    }
+    let [font_path, output_path] = positional[..] else {
+        bail!("{RASTERIZE_USAGE}");
+    };
+
+    let mut options = RasterizeOptions::new(cell_width, cell_height);
+    if let Some(font_size) = font_size {
+        options = options.with_font_size(font_size);
+    }
+    if let Some(baseline_shift) = baseline_shift {
+        options = options.with_baseline_shift(baseline_shift);
+    }
+    if let Some(x_shift) = x_shift {
+        options = options.with_x_shift(x_shift);
+    }
+    if let Some(fixed_width) = fixed_width {
+        options = options.with_fixed_width(fixed_width);
+    }
+    if let Some(threshold) = threshold {
+        options = options.with_threshold(threshold);
+    }
+    if let Some(bold) = shadow {
+        options = options.with_shadow(bold);
+    }
+    if let Some(chars_path) = chars_path {
+        options = options.with_chars(read_chars(Path::new(&chars_path))?);
+    }
+
+    if project.skip_in_dry_run(format_args!("栅格化 {font_path} 到 {output_path}")) {
+        return Ok(());
+    }
+    let sfont = font_raster::rasterize_font(font_path, &options)?;
+    sfont.save(output_path)?;
+    println!(
+        "已生成字形集 {output_path}，单元格 {}x{}，共 {} 个字形",
+        sfont.cell_width,
+        sfont.cell_height,
+        sfont.glyphs.len()
+    );
+    Ok(())
+}
+
+pub fn run(project: &Project, args: &[String]) -> anyhow::Result<()> {
+    if args.first().map(|x| x.as_str()) == Some("rasterize") {
+        return rasterize(project, &args[1..]);
+    }
+    super::ensure_no_args("genfont", args)?;
+
+    let paths = &project.paths;
+    let tpl_path = &paths.tpl;
+    let workspace_tpl_path = paths.workspace.join("mess_out_tpl");
+    let sfonts_path = paths.tools.join("sfonts");
+    let original_tbl_path = paths.plugins.join("rnr2-utf8-cn-base.tbl");
+    let generated_tbl_path = paths.plugins.join("rnr2-utf8-cn.tbl");
+    let temp_fonts_path = &paths.fonts;
+
+    let base_table = TextTable::open(&original_tbl_path)?;
+    let mut chars = BTreeSet::new();
+    for dir in [tpl_path, &workspace_tpl_path] {
+        if !dir.is_dir() {
+            continue;
+        }
+        for archive in text_archive::read_tpl_dir(dir, true)? {
+            text_archive::collect_archive_unknown_chars(&base_table, &archive, &mut chars);
+        }
+    }
+    let generated_table = base_table.generate(&chars).context("生成字符表失败")?;
+    if project.skip_in_dry_run(format_args!(
+        "生成字符表 {}（共 {} 项，新增 {} 个字符）和字库到 {}",
+        generated_tbl_path.display(),
+        generated_table.entries.len(),
+        generated_table.entries.len() - base_table.entries.len(),
+        temp_fonts_path.display()
+    )) {
+        return Ok(());
+    }
+
+    let _ = std::fs::remove_dir_all(temp_fonts_path);
+    std::fs::create_dir_all(temp_fonts_path)?;
+    std::fs::create_dir_all(tpl_path)?;
+    generated_table.save(&generated_tbl_path)?;
+    println!(
+        "已生成字符表，共 {} 项，新增 {} 个字符",
+        generated_table.entries.len(),
+        generated_table.entries.len() - base_table.entries.len()
+    );
+
+    project.run(
+        project
+            .tools
+            .sfont_gen()?
+            .arg("gen-font")
+            .arg("--output-base-font")
+            .arg(sfonts_path.join("font3.original.bin"))
+            .arg("--full-space-width")
+            .arg("12")
+            .arg("--half-space-width")
+            .arg("6")
+            .arg("-t")
+            .arg(&generated_tbl_path)
+            .arg("-o")
+            .arg(temp_fonts_path.join("font3.bin"))
+            .arg("-w")
+            .arg(temp_fonts_path.join("font3_width.bin"))
+            .arg("-f")
+            .arg(sfonts_path.join("cn/sf1-jp-font3.sfont"))
+            .arg("-f")
+            .arg(sfonts_path.join("us/font-12x12-us.resized.sfont"))
+            .arg("-f")
+            .arg(sfonts_path.join("simsun/font-simsun-12x12.cliped.sfont")),
+    )?;
+
+    project.run(
+        project
+            .tools
+            .sfont_gen()?
+            .arg("gen-font")
+            .arg("--output-base-font")
+            .arg(sfonts_path.join("font2.original.bin"))
+            .arg("-t")
+            .arg(&generated_tbl_path)
+            .arg("-o")
+            .arg(temp_fonts_path.join("font2.bin"))
+            .arg("-f")
+            .arg(sfonts_path.join("cn/sf1-jp-font2.sfont"))
+            .arg("-f")
+            .arg(sfonts_path.join("muzai/font-muzai-8x12.mod.shadow.bold.sfont"))
+            .arg("-f")
+            .arg(sfonts_path.join("gb2312/gb2312.purified.shifted.shadow.bold.sfont")),
+    )?;
+
+    project.run(
+        project
+            .tools
+            .sfont_gen()?
+            .arg("gen-font")
+            .arg("--output-base-font")
+            .arg(sfonts_path.join("font1.original.bin"))
+            .arg("-t")
+            .arg(&generated_tbl_path)
+            .arg("-o")
+            .arg(temp_fonts_path.join("font1.bin"))
+            .arg("-f")
+            .arg(sfonts_path.join("cn/sf1-jp-font1.sfont"))
+            .arg("-f")
+            .arg(sfonts_path.join("muzai/font-muzai-8x12.mod.shadow.sfont"))
+            .arg("-f")
+            .arg(sfonts_path.join("gb2312/gb2312.purified.shifted.shadow.sfont")),
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,111 @@
+//! `rnr2cn` 的子命令
+//!
+//! 每个子命令都是 `run(project, args)`，`args` 为子命令名之后的参数。
+//! 翻译工具包中的 setup、genfont、pack 等独立程序通过 [`run_as`] 调用对应的子命令。
+
+use anyhow::*;
+
+use crate::utils::project::Project;
+
+pub mod check_text;
+pub mod clean;
+pub mod compile;
+pub mod compile_zig;
+pub mod gen_splash_screen;
+pub mod genfont;
+pub mod pack;
+pub mod setup;
+pub mod translator_pack;
+
+pub const USAGE: &str = "用法：rnr2cn [全局选项] <命令> [参数]
+命令：
+  setup                 检查原版 ROM 并解包到 _workspace
+  genfont               生成字符表和字库，genfont rasterize 用于栅格化字体
+  pack                  打包汉化 ROM，选项见 rnr2cn pack --help
+  compile               编译 arm9 的 Rust 代码
+  compile-zig           编译 Zig 代码
+  gen-splash-screen     生成启动画面，选项见 rnr2cn gen-splash-screen --help
+  check-text            按实际字宽检查译文的行宽和消息框行数
+  dump-images           导出需要翻译的图像
+  translator-pack       生成翻译工具包
+  clean                 删除中间文件，选项见 rnr2cn clean --help
+全局选项：
+  --dry-run             只显示将要执行的操作，不修改任何文件
+  --verbose, -v         显示运行的外部命令和跳过的构建步骤";
+
+/// 解析全局选项并运行子命令，`args` 不含程序名
+///
+/// 全局选项只能写在命令之前，命令之后的参数原样交给子命令。
+pub fn run(args: impl IntoIterator<Item = String>) -> anyhow::Result<()> {
+    let mut dry_run = false;
+    let mut verbose = false;
+    let mut args = args.into_iter();
+    let command = loop {
+        let arg = args.next().context(USAGE)?;
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--verbose" | "-v" => verbose = true,
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => {
+                ensure!(!arg.starts_with('-'), "未知选项 {arg}\n{USAGE}");
+                break arg;
+            }
+        }
+    };
+    let command_args = args.collect::<Vec<_>>();
+
+    let project = Project::open(std::env::current_dir()?)?
+        .with_dry_run(dry_run)
+        .with_verbose(verbose);
+    match command.as_str() {
+        "setup" => setup::run(&project, &command_args),
+        "genfont" => genfont::run(&project, &command_args),
+        "pack" => pack::run(&project, &command_args),
+        "compile" => compile::run(&project, &command_args),
+        "compile-zig" => compile_zig::run(&project, &command_args),
+        "gen-splash-screen" => gen_splash_screen::run(&project, &command_args),
+        "dump-images" => {
+            ensure_no_args(&command, &command_args)?;
+            if project.skip_in_dry_run(format_args!(
+                "导出图像到 {}",
+                project.paths.workspace.join("images").display()
+            )) {
+                return Ok(());
+            }
+            crate::dump_images::dump_images(&project.paths)
+        }
+        "check-text" => check_text::run(&project, &command_args),
+        "translator-pack" => translator_pack::run(&project, &command_args),
+        "clean" => clean::run(&project, &command_args),
+        _ => bail!("未知命令 {command}\n{USAGE}"),
+    }
+}
+
+/// 相当于 `rnr2cn [全局选项] <command> <其余参数>`，本程序参数开头的全局选项放在命令之前
+pub fn run_as(command: &str) -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    let mut global_options = Vec::new();
+    while let Some(arg) = args.next_if(|x| is_global_option(x)) {
+        global_options.push(arg);
+    }
+    run(global_options
+        .into_iter()
+        .chain(std::iter::once(command.to_string()))
+        .chain(args))
+}
+
+fn is_global_option(arg: &str) -> bool {
+    matches!(arg, "--dry-run" | "--verbose" | "-v")
+}
+
+fn ensure_no_args(command: &str, args: &[String]) -> anyhow::Result<()> {
+    ensure!(
+        args.is_empty(),
+        "命令 {command} 不接受参数 {}\n{USAGE}",
+        args.join(" ")
+    );
+    Ok(())
+}
@@ -0,0 +1,480 @@
+use std::path::Path;
+
+use anyhow::*;
+
+use crate::utils::{
+    build_graph::{BuildGraph, Target},
+    fs::copy_dir_all,
+    hash::FileHashes,
+    jobs::JobRunner,
+    nds_rom::NdsRomBuilder,
+    patch::{PatchFormat, ReleaseManifest, ReleasePatch, ReleaseRom, RELEASE_MANIFEST_FILE},
+    project::{Project, ProjectPaths},
+    rom_check::{self, ROM_VERSIONS},
+    sfarc::{self, GrownEntry},
+    splash::{SplashPages, SPLASH_PAGES_BIN, SPLASH_PAGES_FILE},
+    text_archive::{self, TextCodec},
+};
+
+const USAGE: &str = "用法：rnr2cn pack [选项]
+选项：
+  --force          忽略记录的构建状态，重新执行所有打包步骤
+  --test-release   打包后复制一份带编号的测试版本到 _build/测试版本
+  --release        打包后生成正式版本、补丁和 release.json 到 _build/正式版本";
+
+#[derive(Debug, Clone, Default)]
+struct PackOptions {
+    force: bool,
+    test_release: bool,
+    release: bool,
+}
+
+impl PackOptions {
+    fn from_args(args: &[String]) -> anyhow::Result<Option<Self>> {
+        let mut options = Self::default();
+        for arg in args {
+            match arg.as_str() {
+                "--force" => options.force = true,
+                "--test-release" => options.test_release = true,
+                "--release" => options.release = true,
+                "--help" | "-h" => {
+                    println!("{USAGE}");
+                    return Ok(None);
+                }
+                _ => bail!("未知参数 {arg}\n{USAGE}"),
+            }
+        }
+        Ok(Some(options))
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn print_grown_entries(name: &str, grown: &[GrownEntry]) {
+    for entry in grown {
+        println!(
+            "  - {name} 第 {} 项变大了：{} -> {} 字节",
+            entry.index, entry.old_size, entry.new_size
+        );
+    }
+}
+
+/// 包含需要写回的图像的归档，除了精灵图所在的归档外也需要重新打包
+const IMAGE_ARCHIVES: [&str; 3] = [
+    "capcomlogo_local.bin",
+    "subscreen_local.bin",
+    "result_local.bin",
+];
+
+fn version_name(version: &str) -> &str {
+    match version {
+        "ninja" => "忍者版",
+        "saurian" => "恐龙版",
+        _ => version,
+    }
+}
+
+/// 把 `images/sfsprites_out` 中的精灵图写回忍者版解包的归档，再复制到恐龙版
+fn patch_sprites(project: &Project, bin_names: &[String]) -> anyhow::Result<()> {
+    let paths = &project.paths;
+    println!("正在处理精灵图");
+    let mut jobs = JobRunner::new("处理精灵图");
+    for bin_name in bin_names {
+        let dir = paths.images.join("sfsprites_out").join(bin_name);
+        for sprite_png in std::fs::read_dir(dir)?.flatten() {
+            if sprite_png.path().extension().map(|x| x.to_string_lossy()) != Some("png".into()) {
+                continue;
+            }
+            let sprite_bin_path = sprite_png.path();
+            let sprite_bin = sprite_png
+                .path()
+                .with_extension("")
+                .with_extension("bin")
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            let sprite_bin_ninja_path = paths
+                .unpacked_bins
+                .join("ninja")
+                .join(bin_name)
+                .join(&sprite_bin);
+            let sprite_bin_saurian_path = paths
+                .unpacked_bins
+                .join("saurian")
+                .join(bin_name)
+                .join(&sprite_bin);
+            println!("  - {:?} -> {:?}", sprite_bin_path, sprite_bin_ninja_path);
+            jobs.add(format!("{bin_name}/{sprite_bin}"), move || {
+                project.run(
+                    project
+                        .tools
+                        .sfspatcher()?
+                        .arg("patch")
+                        .arg("--buildin-palette-only")
+                        .arg("true")
+                        .arg("-t")
+                        .arg(sprite_bin_path)
+                        .arg("-i")
+                        .arg(&sprite_bin_ninja_path)
+                        .arg("-o")
+                        .arg(&sprite_bin_ninja_path),
+                )?;
+                std::fs::copy(sprite_bin_ninja_path, sprite_bin_saurian_path)?;
+                Ok(())
+            });
+        }
+    }
+    jobs.run()
+}
+
+pub fn run(project: &Project, args: &[String]) -> anyhow::Result<()> {
+    let Some(options) = PackOptions::from_args(args)? else {
+        return Ok(());
+    };
+    let paths = &project.paths;
+    let workspace_path = &paths.workspace;
+    let unpacked_path = &paths.unpacked_bins;
+    let build_path = &paths.build;
+    let images_path = &paths.images;
+    let fonts_path = &paths.fonts;
+    let temp_path = &paths.temp.join("pack");
+    let mess_path = temp_path.join("mess");
+
+    if !project.dry_run {
+        std::fs::create_dir_all(&paths.tpl)?;
+        std::fs::create_dir_all(build_path)?;
+    }
+
+    let mut sprite_bins = std::fs::read_dir(images_path.join("sfsprites_out"))?
+        .flatten()
+        .filter(|x| x.path().is_dir())
+        .map(|x| x.file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    sprite_bins.sort();
+    let mut repack_bins = IMAGE_ARCHIVES
+        .iter()
+        .map(|x| x.to_string())
+        .chain(sprite_bins.iter().cloned())
+        .collect::<Vec<_>>();
+    repack_bins.sort();
+    repack_bins.dedup();
+    let image_files = std::fs::read_dir(images_path)?
+        .flatten()
+        .map(|x| x.path())
+        .filter(|x| x.is_file())
+        .collect::<Vec<_>>();
+    let splash_pages = SplashPages::load(images_path)?;
+    let (sprite_bins, repack_bins, splash_pages) = (&sprite_bins, &repack_bins, &splash_pages);
+
+    // 每个目标只写入自己的输出，未受影响的目标会被跳过
+    let mut graph = BuildGraph::new(temp_path.join("build-state.json"))
+        .with_force(options.force)
+        .with_dry_run(project.dry_run)
+        .with_verbose(project.verbose);
+
+    graph.add(
+        Target::new("tpl", move || {
+            project.run(project.tools.textpet_checker()?.arg(&paths.tpl))
+        })
+        .with_input(&paths.tpl),
+    );
+
+    graph.add(
+        Target::new("mess", || {
+            let _ = std::fs::remove_dir_all(&mess_path);
+            std::fs::create_dir_all(&mess_path)?;
+            let codec = TextCodec::load(&paths.plugins, "rnr2-cn")?;
+            text_archive::tpl_dir_to_msg_dir(
+                &codec,
+                &paths.mess_tpl,
+                Some(paths.tpl.join("mess_tpl")),
+                &mess_path,
+            )
+            .context("编译游戏脚本文件失败")?;
+
+            let grown = sfarc::pack_archive(
+                &mess_path,
+                Some(workspace_path.join("ninja/data/datbin/jpn/mess.bin")),
+                temp_path.join("mess.bin"),
+            )?;
+            print_grown_entries("mess.bin", &grown);
+            Ok(())
+        })
+        .with_dep("tpl")
+        .with_input(&paths.mess_tpl)
+        .with_input(paths.tpl.join("mess_tpl"))
+        .with_input(&paths.plugins)
+        .with_input(workspace_path.join("ninja/data/datbin/jpn/mess.bin"))
+        .with_output(temp_path.join("mess.bin")),
+    );
+
+    // 图像和精灵图直接写回 _workspace 中解包的归档，这些归档就是本目标的输出
+    graph.add(
+        Target::new("images", move || {
+            crate::dump_images::save_images(paths)?;
+            patch_sprites(project, sprite_bins)
+        })
+        .with_inputs(image_files)
+        .with_input(images_path.join("sfsprites_out"))
+        .with_outputs(ROM_VERSIONS.iter().flat_map(|version| {
+            repack_bins
+                .iter()
+                .map(move |bin_name| unpacked_path.join(version).join(bin_name))
+        })),
+    );
+
+    for version in ROM_VERSIONS {
+        let version_workspace_path = workspace_path.join(version);
+        let version_temp_path = temp_path.join(version);
+        let code_path = version_temp_path.join("code");
+        let archives_path = version_temp_path.join("archives");
+        let sym_path = build_path.join(format!("{version}.sym"));
+
+        graph.add(
+            Target::new(format!("{version}/code"), {
+                let version_workspace_path = version_workspace_path.clone();
+                let code_path = code_path.clone();
+                let sym_path = sym_path.clone();
+                move || {
+                    let _ = std::fs::remove_dir_all(&code_path);
+                    std::fs::create_dir_all(&code_path)?;
+                    for file in ["arm9.bin", "y9.bin"] {
+                        std::fs::copy(version_workspace_path.join(file), code_path.join(file))?;
+                    }
+                    copy_dir_all(
+                        version_workspace_path.join("overlay"),
+                        code_path.join("overlay"),
+                    )?;
+                    project.run(
+                        project
+                            .tools
+                            .armips()?
+                            .arg("-strequ")
+                            .arg("TEMP")
+                            .arg(&code_path)
+                            .arg("-sym")
+                            .arg(&sym_path)
+                            .arg(paths.asm.join(format!("{version}/_main.asm")))
+                            .current_dir(&paths.asm),
+                    )
+                }
+            })
+            .with_input(version_workspace_path.join("arm9.bin"))
+            .with_input(version_workspace_path.join("y9.bin"))
+            .with_input(version_workspace_path.join("overlay"))
+            .with_input(&paths.asm)
+            .with_input(fonts_path)
+            .with_output(&code_path)
+            .with_output(&sym_path),
+        );
+
+        for bin_name in repack_bins {
+            let input = unpacked_path.join(version).join(bin_name);
+            let reference = version_workspace_path
+                .join("data/datbin/jpn")
+                .join(bin_name);
+            let output = archives_path.join(bin_name);
+            graph.add(
+                Target::new(format!("{version}/{bin_name}"), {
+                    let (input, reference, output) =
+                        (input.clone(), reference.clone(), output.clone());
+                    let archives_path = archives_path.clone();
+                    move || {
+                        std::fs::create_dir_all(&archives_path)?;
+                        let grown = sfarc::pack_archive(input, Some(reference), output)?;
+                        print_grown_entries(
+                            &format!("{} {bin_name}", version_name(version)),
+                            &grown,
+                        );
+                        Ok(())
+                    }
+                })
+                .with_dep("images")
+                .with_input(input)
+                .with_input(reference)
+                .with_output(output),
+            );
+        }
+
+        let rom_path = build_path.join(format!("{version}.nds"));
+        let mut rom = Target::new(format!("{version}/rom"), {
+            let version_workspace_path = version_workspace_path.clone();
+            let version_temp_path = version_temp_path.clone();
+            let code_path = code_path.clone();
+            let archives_path = archives_path.clone();
+            let rom_path = rom_path.clone();
+            move || {
+                // 在原版数据上覆盖本次打包生成的文件
+                let data_path = version_temp_path.join("data");
+                let _ = std::fs::remove_dir_all(&data_path);
+                copy_dir_all(version_workspace_path.join("data"), &data_path)?;
+                let datbin_path = data_path.join("datbin/jpn");
+                for bin_name in repack_bins {
+                    std::fs::copy(archives_path.join(bin_name), datbin_path.join(bin_name))?;
+                }
+                std::fs::copy(temp_path.join("mess.bin"), datbin_path.join("mess.bin"))?;
+
+                for page in &splash_pages.pages {
+                    let page_path = page.output_bin(images_path, version);
+                    std::fs::copy(&page_path, data_path.join(page.rom_name())).with_context(
+                        || {
+                            format!(
+                                "无法复制启动画面 {}，请先运行 rnr2cn gen-splash-screen",
+                                page_path.display()
+                            )
+                        },
+                    )?;
+                }
+                std::fs::write(
+                    data_path.join(SPLASH_PAGES_BIN),
+                    splash_pages.to_descriptor(),
+                )?;
+
+                copy_dir_all(fonts_path, data_path.join("fonts"))?;
+
+                NdsRomBuilder::new()
+                    .with_header(version_workspace_path.join("header.bin"))
+                    .with_arm9(code_path.join("arm9.bin"))
+                    .with_arm7(version_workspace_path.join("arm7.bin"))
+                    .with_y9(version_workspace_path.join("y9.bin"))
+                    .with_y7(version_workspace_path.join("y7.bin"))
+                    .with_data(&data_path)
+                    .with_fnt(version_workspace_path.join("fnt.bin"))
+                    .with_overlay(code_path.join("overlay"))
+                    .with_overlay7(version_workspace_path.join("overlay7"))
+                    .with_banner(version_workspace_path.join("banner.bin"))
+                    .build_to(&rom_path)
+            }
+        })
+        .with_dep(format!("{version}/code"))
+        .with_dep("mess")
+        .with_inputs(
+            [
+                "header.bin",
+                "arm7.bin",
+                "y9.bin",
+                "y7.bin",
+                "overlay7",
+                "fnt.bin",
+                "banner.bin",
+                "data",
+            ]
+            .map(|x| version_workspace_path.join(x)),
+        )
+        .with_input(images_path.join(SPLASH_PAGES_FILE))
+        .with_inputs(
+            splash_pages
+                .pages
+                .iter()
+                .map(|x| x.output_bin(images_path, version)),
+        )
+        .with_input(fonts_path)
+        .with_output(&rom_path);
+        for bin_name in repack_bins {
+            rom = rom.with_dep(format!("{version}/{bin_name}"));
+        }
+        graph.add(rom);
+    }
+
+    graph.run()?;
+
+    if options.test_release
+        && !project.skip_in_dry_run(format_args!(
+            "复制测试版本到 {}",
+            build_path.join("测试版本").display()
+        ))
+    {
+        test_release(paths)?;
+    }
+    if options.release
+        && !project.skip_in_dry_run(format_args!(
+            "生成正式版本 {} 到 {}",
+            project.config.release.version,
+            build_path.join("正式版本").display()
+        ))
+    {
+        release(project)?;
+    }
+
+    Ok(())
+}
+
+fn test_release(paths: &ProjectPaths) -> anyhow::Result<()> {
+    let build_path = &paths.build;
+    let test_rel_path = build_path.join("测试版本");
+    std::fs::create_dir_all(&test_rel_path)?;
+    let mut rel_id = 0;
+    loop {
+        rel_id += 1;
+        let rel_ninja_path =
+            test_rel_path.join(format!("流星洛克人2 忍者 测试版本（{rel_id}）.nds"));
+        let rel_saurian_path =
+            test_rel_path.join(format!("流星洛克人2 恐龙 测试版本（{rel_id}）.nds"));
+        if !rel_ninja_path.exists() && !rel_saurian_path.exists() {
+            std::fs::copy(build_path.join("ninja.nds"), rel_ninja_path)?;
+            std::fs::copy(build_path.join("saurian.nds"), rel_saurian_path)?;
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn release(project: &Project) -> anyhow::Result<()> {
+    let build_path = &project.paths.build;
+    let test_rel_path = build_path.join("正式版本");
+    std::fs::create_dir_all(&test_rel_path)?;
+    let rel_id = &project.config.release.version;
+
+    let rel_ninja_path = test_rel_path.join(format!("流星洛克人 2 忍者 完全汉化版 {rel_id}.nds"));
+    let rel_saurian_path = test_rel_path.join(format!("流星洛克人 2 恐龙 完全汉化版 {rel_id}.nds"));
+    std::fs::copy(build_path.join("ninja.nds"), &rel_ninja_path)?;
+    std::fs::copy(build_path.join("saurian.nds"), &rel_saurian_path)?;
+
+    let roms = rom_check::locate_roms(&project.paths.root)?;
+    let mut manifest = ReleaseManifest {
+        release: rel_id.to_string(),
+        roms: Vec::new(),
+    };
+    for (version, rel_path) in [("ninja", &rel_ninja_path), ("saurian", &rel_saurian_path)] {
+        let source = std::fs::read(&roms[version])?;
+        let target = std::fs::read(build_path.join(format!("{version}.nds")))?;
+
+        let mut patches = Vec::new();
+        for format in PatchFormat::ALL {
+            let patch_path = rel_path.with_extension(format.extension());
+            let Some(patch) = format.create(&source, &target)? else {
+                println!(
+                    "ROM 大小超出 {} 格式的限制，跳过生成 {}",
+                    format.extension(),
+                    patch_path.display()
+                );
+                continue;
+            };
+            std::fs::write(&patch_path, &patch)?;
+            patches.push(ReleasePatch {
+                format,
+                file: file_name(&patch_path),
+                size: patch.len() as u64,
+            });
+        }
+
+        manifest.roms.push(ReleaseRom {
+            version: version.to_string(),
+            file: file_name(rel_path),
+            source: FileHashes::new(&source),
+            target: FileHashes::new(&target),
+            patches,
+        });
+    }
+    std::fs::write(
+        test_rel_path.join(RELEASE_MANIFEST_FILE),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    Ok(())
+}
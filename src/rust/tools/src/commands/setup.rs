@@ -0,0 +1,145 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+
+use crate::utils::{
+    jobs::JobRunner,
+    nds_rom,
+    project::{Project, ProjectPaths},
+    rom_check, sfarc,
+    text_archive::{self, TextCodec},
+};
+
+const USAGE: &str = "用法：rnr2cn setup [选项] [原版 ROM]...
+检查原版 ROM 并解包到 _workspace
+未指定 ROM 时在 _rom 文件夹和项目根目录中查找，按头部的游戏代码识别版本并检查 SHA-1，
+仍然找不到时提示输入 ROM 的路径。使用的 ROM 会复制到 _rom/<版本>.nds
+选项：
+  --trust    roms.toml 中没有已知 SHA-1 时接受头部正确的 ROM，并把它的 SHA-1 和标题记录到 roms.toml，
+             只应该用于已经确认完好的 dump";
+
+pub fn run(project: &Project, args: &[String]) -> anyhow::Result<()> {
+    let mut files = Vec::new();
+    let mut trust = false;
+    for arg in args {
+        match arg.as_str() {
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            "--trust" => trust = true,
+            _ if arg.starts_with("--") => bail!("未知参数 {arg}\n{USAGE}"),
+            _ => files.push(PathBuf::from(arg)),
+        }
+    }
+    let paths = &project.paths;
+    // 先检查原版 ROM，避免删除工作区后才发现 ROM 有问题
+    let roms = rom_check::find_roms(&paths.root, &files, trust)?;
+    if project.skip_in_dry_run(format_args!(
+        "删除并重新生成 {}，使用的 ROM：{}",
+        paths.workspace.display(),
+        roms.values()
+            .map(|x| x.display().to_string())
+            .collect::<Vec<_>>()
+            .join("、")
+    )) {
+        return Ok(());
+    }
+
+    if trust {
+        rom_check::trust_roms(&paths.root, &roms)?;
+    }
+    copy_roms(paths, &roms)?;
+
+    let _ = std::fs::remove_dir_all(&paths.workspace);
+    let _ = std::fs::create_dir_all(&paths.workspace);
+    std::fs::create_dir_all(&paths.tpl)?;
+
+    nds_rom::extract_rom(&roms["ninja"], paths.version_workspace("ninja"))
+        .context("解包 忍者版 原始游戏文件失败")?;
+    nds_rom::extract_rom(&roms["saurian"], paths.version_workspace("saurian"))
+        .context("解包 恐龙版 原始游戏文件失败")?;
+
+    let mut jobs = JobRunner::new("解包游戏归档");
+    for (version_name, archive_dir, unpack_dir, extract_sprites) in [
+        ("忍者版 通用", "ninja/data/datbin/com", "common", false),
+        ("忍者版", "ninja/data/datbin/jpn", "ninja", true),
+        ("恐龙版", "saurian/data/datbin/jpn", "saurian", true),
+    ] {
+        let archive_dir = paths.workspace.join(archive_dir);
+        for entry in std::fs::read_dir(&archive_dir)?.flatten() {
+            let filename = entry.file_name().to_string_lossy().into_owned();
+            if !filename.ends_with(".bin") {
+                continue;
+            }
+            let archive_path = archive_dir.join(&filename);
+            let unpack_dest = paths.unpacked_bins.join(unpack_dir).join(&filename);
+            jobs.add(format!("{version_name} {filename}"), move || {
+                sfarc::extract_archive(&archive_path, &unpack_dest)
+                    .with_context(|| format!("解包 {version_name} 游戏归档文件 {filename} 失败"))?;
+                if extract_sprites {
+                    project
+                        .run(
+                            project
+                                .tools
+                                .sfspatcher()?
+                                .arg("--ignore-errors")
+                                .arg("extract")
+                                .arg("-i")
+                                .arg(&unpack_dest)
+                                .arg("-o")
+                                .arg(paths.images.join("sfsprites").join(&filename)),
+                        )
+                        .with_context(|| {
+                            format!("解包 {version_name} 游戏归档文件 {filename} 中的精灵图失败")
+                        })?;
+                }
+                Ok(())
+            });
+        }
+    }
+    jobs.run()?;
+
+    crate::dump_images::dump_images(paths)?;
+
+    let codec = TextCodec::load(&paths.plugins, "rnr2")?;
+    text_archive::msg_dir_to_tpl_dir(
+        &codec,
+        paths.unpacked_bins.join("ninja/mess.bin"),
+        &paths.mess_tpl,
+    )
+    .context("解包 忍者版 游戏脚本文件失败")?;
+
+    Ok(())
+}
+
+/// 把 `_rom` 以外的 ROM 复制为 `_rom/<版本>.nds`，打包发布时只查找 `_rom` 下的 ROM
+fn copy_roms(paths: &ProjectPaths, roms: &BTreeMap<String, PathBuf>) -> anyhow::Result<()> {
+    let rom_dir = paths.roms.canonicalize().ok();
+    for (version, rom) in roms {
+        let parent = rom
+            .canonicalize()
+            .ok()
+            .and_then(|x| x.parent().map(Path::to_path_buf));
+        if rom_dir.is_some() && parent == rom_dir {
+            continue;
+        }
+        let dest = paths.roms.join(format!("{version}.nds"));
+        if dest.exists() {
+            println!(
+                "警告：{} 已存在，没有复制 {}",
+                dest.display(),
+                rom.display()
+            );
+            continue;
+        }
+        std::fs::create_dir_all(&paths.roms)?;
+        std::fs::copy(rom, &dest)
+            .with_context(|| format!("无法复制 {} 到 {}", rom.display(), dest.display()))?;
+        println!("已复制 {} 到 {}", rom.display(), dest.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,352 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::*;
+use sevenz_rust::{SevenZArchiveEntry, SevenZWriter};
+
+use crate::utils::{
+    fs::copy_dir_all,
+    hash::FileHashes,
+    project::Project,
+    rom_check::{self, KnownRoms},
+    Tool,
+};
+
+const USAGE: &str = "用法：rnr2cn translator-pack [选项]
+为每个目标平台生成一个翻译工具包，位于 _temp/translator-pack
+默认不附带原版 ROM，工具包中的 roms.toml 会记录当前使用的 ROM 的 SHA-1，
+setup 据此检查用户自己的 ROM，生成的工具包可以公开发布
+选项：
+  --target <平台>   生成指定平台的工具包，可以指定多次，
+                    默认使用 rnr2cn.toml 中 [translator_pack] 的 targets
+  --with-roms       附带 _rom 下的原版 ROM，生成的工具包不能公开发布
+支持的平台：
+  x86_64-pc-windows-msvc
+  x86_64-unknown-linux-gnu
+  x86_64-apple-darwin
+  aarch64-apple-darwin";
+
+/// 翻译工具包中的程序、Windows 版的中文文件名和说明
+const PACK_BINS: [(&str, &str, &str); 3] = [
+    (
+        "setup",
+        "初始化工作环境",
+        "检查原版 ROM 并解包，第一次使用时运行",
+    ),
+    ("genfont", "生成字库", "根据 tpl 中的译文生成字符表和字库"),
+    ("pack", "打包游戏", "打包汉化 ROM 到 _build"),
+];
+
+/// 翻译工具包的目标平台
+struct PackTarget {
+    triple: &'static str,
+    /// 系统名，与 `std::env::consts::OS` 一致，为该系统编译的工具放在 `tools/bin/<系统名>` 下
+    os: &'static str,
+    arch: &'static str,
+}
+
+static PACK_TARGETS: [PackTarget; 4] = [
+    PackTarget {
+        triple: "x86_64-pc-windows-msvc",
+        os: "windows",
+        arch: "x86_64",
+    },
+    PackTarget {
+        triple: "x86_64-unknown-linux-gnu",
+        os: "linux",
+        arch: "x86_64",
+    },
+    PackTarget {
+        triple: "x86_64-apple-darwin",
+        os: "macos",
+        arch: "x86_64",
+    },
+    PackTarget {
+        triple: "aarch64-apple-darwin",
+        os: "macos",
+        arch: "aarch64",
+    },
+];
+
+impl PackTarget {
+    fn find(triple: &str) -> Result<&'static Self> {
+        PACK_TARGETS
+            .iter()
+            .find(|x| x.triple == triple)
+            .with_context(|| format!("不支持的目标平台 {triple}\n{USAGE}"))
+    }
+
+    fn is_windows(&self) -> bool {
+        self.os == "windows"
+    }
+
+    fn name(&self) -> String {
+        format!("{}-{}", self.os, self.arch)
+    }
+
+    fn exe_suffix(&self) -> &'static str {
+        if self.is_windows() {
+            ".exe"
+        } else {
+            ""
+        }
+    }
+
+    /// Windows 版使用中文文件名方便双击运行，其他系统使用英文文件名方便在终端中运行
+    fn exe_name(&self, bin: &str, name: &str) -> String {
+        if self.is_windows() {
+            format!("{name}.exe")
+        } else {
+            bin.to_string()
+        }
+    }
+}
+
+pub fn run(project: &Project, args: &[String]) -> anyhow::Result<()> {
+    let mut triples = Vec::new();
+    let mut with_roms = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--target" => triples.push(
+                args.next()
+                    .with_context(|| format!("选项 --target 缺少参数\n{USAGE}"))?
+                    .clone(),
+            ),
+            "--with-roms" => with_roms = true,
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => bail!("未知参数 {arg}\n{USAGE}"),
+        }
+    }
+    if triples.is_empty() {
+        triples = project.config.translator_pack.targets.clone();
+    }
+    ensure!(!triples.is_empty(), "没有指定目标平台\n{USAGE}");
+    let targets = triples
+        .iter()
+        .map(|x| PackTarget::find(x))
+        .collect::<Result<Vec<_>>>()?;
+
+    super::compile::run(project, &[])?;
+
+    for target in targets {
+        build_pack(project, target, with_roms)
+            .with_context(|| format!("生成 {} 的翻译工具包失败", target.triple))?;
+    }
+    Ok(())
+}
+
+fn build_pack(project: &Project, target: &PackTarget, with_roms: bool) -> anyhow::Result<()> {
+    let paths = &project.paths;
+    // 附带 ROM 的工具包单独命名，避免误传
+    let pack_name = if with_roms {
+        format!("{}-with-roms", target.name())
+    } else {
+        target.name()
+    };
+    let temp_trans_dir_path = paths.temp.join("translator-pack");
+    let pack_target_dir_path = temp_trans_dir_path.join("target");
+    let pack_dir_path = temp_trans_dir_path.join(format!("pack-{pack_name}"));
+    let archive_path = temp_trans_dir_path.join(format!("Ryuusei-No-Rockman-2-CN-{pack_name}.7z"));
+
+    let mut command = Command::new("rustup");
+    command.args(["run", "stable", "cargo", "build", "--release"]);
+    for (bin, ..) in PACK_BINS {
+        command.arg("--bin").arg(bin);
+    }
+    command
+        .arg("--target")
+        .arg(target.triple)
+        .arg("--target-dir")
+        .arg(&pack_target_dir_path);
+    project.run(&mut command)?;
+
+    if project.skip_in_dry_run(format_args!("生成翻译工具包 {}", archive_path.display())) {
+        return Ok(());
+    }
+
+    let _ = std::fs::remove_dir_all(&pack_dir_path);
+    std::fs::create_dir_all(&pack_dir_path)?;
+
+    let release_dir_path = pack_target_dir_path.join(target.triple).join("release");
+    let mut executables = Vec::new();
+    for (bin, name, _) in PACK_BINS {
+        let exe = release_dir_path.join(format!("{bin}{}", target.exe_suffix()));
+        let exe_name = target.exe_name(bin, name);
+        std::fs::copy(&exe, pack_dir_path.join(&exe_name))
+            .with_context(|| format!("无法复制 {}", exe.display()))?;
+        if target.is_windows() {
+            std::fs::copy(&exe, paths.root.join(format!("{bin}.exe")))?;
+        }
+        executables.push(exe_name);
+    }
+
+    copy_dir_all(&paths.asm, pack_dir_path.join("src/asm"))?;
+    copy_dir_all(&paths.images, pack_dir_path.join("images"))?;
+
+    // 工具包只接受与当前使用的 ROM 一致的 dump
+    let mut known = KnownRoms::load(&paths.root)?;
+    std::fs::create_dir_all(pack_dir_path.join("_rom"))?;
+    for (version, rom) in rom_check::locate_roms(&paths.root)? {
+        known.add_sha1(&version, &FileHashes::from_file(&rom)?.sha1);
+        if with_roms {
+            std::fs::copy(rom, pack_dir_path.join(format!("_rom/{version}.nds")))?;
+        }
+    }
+    known.save(
+        pack_dir_path.join(rom_check::KNOWN_ROMS_FILE),
+        "原版 ROM 的识别信息，由 rnr2cn translator-pack 生成
+setup 只接受 SHA-1 与 sha1 中记录的 dump 一致的 ROM",
+    )?;
+    copy_tools(&paths.tools, &pack_dir_path.join("tools"), target)?;
+    for tool in Tool::ALL {
+        if !target.is_windows() && pack_dir_path.join("tools").join(tool.name()).is_file() {
+            executables.push(format!("tools/{}", tool.name()));
+        }
+    }
+    std::fs::write(
+        pack_dir_path.join("README.txt"),
+        pack_readme(target, &pack_dir_path, with_roms),
+    )?;
+
+    compress_pack(&pack_dir_path, &archive_path, target, &executables)
+        .with_context(|| format!("无法生成 {}", archive_path.display()))?;
+    println!("已生成 {}", archive_path.display());
+    Ok(())
+}
+
+/// 复制 `tools` 文件夹，`tools/bin` 下只复制目标系统的工具，并放在 `tools` 下
+///
+/// 目标系统有对应工具时不再复制同名的 `.exe`，没有时保留 `.exe` 通过 wine 运行。
+fn copy_tools(src: &Path, dst: &Path, target: &PackTarget) -> anyhow::Result<()> {
+    let native_dir = src.join("bin").join(target.os);
+    std::fs::create_dir_all(dst)?;
+    for entry in std::fs::read_dir(src)?.flatten() {
+        let path = entry.path();
+        let file_name = entry.file_name();
+        if file_name == "bin" {
+            continue;
+        }
+        if path.is_dir() {
+            copy_dir_all(&path, dst.join(&file_name))?;
+            continue;
+        }
+        let is_replaced = !target.is_windows()
+            && path
+                .extension()
+                .is_some_and(|x| x.eq_ignore_ascii_case("exe"))
+            && native_dir
+                .join(path.file_stem().unwrap_or_default())
+                .is_file();
+        if !is_replaced {
+            std::fs::copy(&path, dst.join(&file_name))?;
+        }
+    }
+    if native_dir.is_dir() {
+        copy_dir_all(&native_dir, dst)?;
+    }
+    Ok(())
+}
+
+/// 工具包中的 README.txt，列出工具包的内容
+fn pack_readme(target: &PackTarget, pack_dir_path: &Path, with_roms: bool) -> String {
+    let tools_dir = pack_dir_path.join("tools");
+    let mut readme = format!(
+        "流星之洛克人 2 汉化翻译工具包（{}）\n\n程序：\n",
+        target.name()
+    );
+    for (bin, name, description) in PACK_BINS {
+        readme += &format!("  {}：{description}\n", target.exe_name(bin, name));
+    }
+    if !target.is_windows() {
+        readme += "  请在终端中进入工具包文件夹运行，例如 ./setup\n";
+    }
+
+    readme += "\n外部工具：\n";
+    for tool in Tool::ALL {
+        let exe = format!("{}.exe", tool.name());
+        let line = if !target.is_windows() && tools_dir.join(tool.name()).is_file() {
+            format!("tools/{}", tool.name())
+        } else if tools_dir.join(&exe).is_file() {
+            if target.is_windows() {
+                format!("tools/{exe}")
+            } else {
+                format!("tools/{exe}（Windows 程序，需要安装 wine）")
+            }
+        } else {
+            format!(
+                "{}（未包含，请自行安装并放入 tools 文件夹或 PATH）",
+                tool.name()
+            )
+        };
+        readme += &format!("  {line}\n");
+    }
+
+    if !with_roms {
+        readme += "
+本工具包不附带游戏本体，请自行准备原版 ROM 放在 _rom 文件夹下（ninja.nds、saurian.nds），
+或者运行 setup 时按提示输入 ROM 的路径。setup 会按 roms.toml 中记录的 SHA-1 检查 ROM，
+只有与制作工具包时使用的 ROM 一致的完整 dump 才能使用。
+";
+    }
+    readme += "
+文件夹：
+  _rom        原版 ROM，识别信息见 roms.toml
+  images      需要翻译的图像
+  src/asm     汉化代码
+  tools       外部工具、字库和码表
+  tpl         译文，运行 setup 后生成
+
+运行 setup 后会生成 _workspace，pack 生成的 ROM 位于 _build。
+";
+    readme
+}
+
+/// 压缩工具包，非 Windows 平台记录 Unix 权限，解压后程序可以直接运行
+fn compress_pack(
+    src: &Path,
+    dest: &Path,
+    target: &PackTarget,
+    executables: &[String],
+) -> anyhow::Result<()> {
+    let mut writer = SevenZWriter::create(dest)?;
+    let mut dirs = vec![src.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = std::fs::read_dir(&dir)?
+            .flatten()
+            .map(|x| x.path())
+            .collect::<Vec<PathBuf>>();
+        entries.sort();
+        for path in entries {
+            let name = path.strip_prefix(src)?.to_string_lossy().replace('\\', "/");
+            let mut entry = SevenZArchiveEntry::from_path(&path, name.clone());
+            if !target.is_windows() {
+                // 高 16 位为 Unix 权限，0x8000 表示记录了 Unix 权限
+                let mode = if path.is_dir() {
+                    0o040755
+                } else if executables.contains(&name) {
+                    0o100755
+                } else {
+                    0o100644
+                };
+                let attributes = if path.is_dir() { 0x10 } else { 0x20 };
+                entry.has_windows_attributes = true;
+                entry.windows_attributes = attributes | 0x8000 | (mode << 16);
+            }
+            if path.is_dir() {
+                writer.push_archive_entry::<File>(entry, None)?;
+                dirs.push(path);
+            } else {
+                writer.push_archive_entry(entry, Some(File::open(&path)?))?;
+            }
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
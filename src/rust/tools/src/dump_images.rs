@@ -1,618 +1,260 @@
-use std::io::Write;
+use std::path::{Path, PathBuf};
 
-use crate::utils::{tile_img::TileImage, ToolsRunner};
+use crate::utils::{color_match::ColorMatching, project::ProjectPaths, tile_img::TileImage};
 use anyhow::*;
-use image::GenericImageView;
-use sfbase::Tile4BPP;
+use serde::{Deserialize, Serialize};
 
-#[allow(unused)]
-trait FileSize {
-    fn file_size(&self) -> u64;
-}
+/// 图像清单文件，位于 `images` 文件夹下
+pub const IMAGE_MANIFEST_FILE: &str = "manifest.yaml";
 
-#[cfg(target_os = "windows")]
-impl<T: std::os::windows::fs::MetadataExt> FileSize for T {
-    fn file_size(&self) -> u64 {
-        self.file_size()
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestTileset {
+    pub entry: usize,
+    #[serde(default)]
+    pub base_tile: usize,
 }
 
-#[cfg(target_os = "linux")]
-impl<T: std::os::linux::fs::MetadataExt> FileSize for T {
-    fn file_size(&self) -> u64 {
-        self.st_size()
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestTilemap {
+    /// 图块表所在的归档项序号，不填时没有图块表，图块集中的图块按顺序排列
+    pub entry: Option<usize>,
+    pub width: usize,
+    pub height: usize,
+    /// 使用的图块集在 `tilesets` 中的序号
+    pub tileset: Option<usize>,
+    /// 以图块为单位的位置
+    pub position: Option<(usize, usize)>,
 }
 
-#[cfg(unix)]
-impl<T: std::os::unix::fs::MetadataExt> FileSize for T {
-    fn file_size(&self) -> u64 {
-        self.size()
-    }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaletteMode {
+    /// 写回索引 PNG 时用 PNG 的调色板按原顺序覆盖原调色板，真彩色 PNG 按 RGB 匹配原调色板
+    #[default]
+    Extract,
+    /// 只把原调色板作为参考，不修改调色板
+    Reference,
 }
 
-#[cfg(target_os = "wasi")]
-impl<T: std::os::wasi::fs::MetadataExt> FileSize for T {
-    fn file_size(&self) -> u64 {
-        self.size()
-    }
+fn default_bpp() -> u8 {
+    8
 }
 
-pub fn dump_images() -> anyhow::Result<()> {
-    let cwd = std::env::current_dir().unwrap();
-    let _workspace_path = cwd.join("_workspace");
-    let _build_path = cwd.join("_build");
-    let _images_path = cwd.join("images");
-    let temp_path = cwd.join("_temp/pack");
-    let _mess_path = temp_path.join("mess");
-    let _ninja_temp_path = temp_path.join("ninja");
-    let _saurian_temp_path = temp_path.join("saurian");
-    let _tools = ToolsRunner::new(Some(&cwd))?;
-
-    let saurian_ccl_path = cwd.join("_workspace/unpacked_bins/saurian/capcomlogo_local.bin");
-    let ninja_ccl_path = cwd.join("_workspace/unpacked_bins/ninja/capcomlogo_local.bin");
-    let image_path = cwd.join("_workspace/images");
-    std::fs::create_dir_all(&image_path)?;
-
-    let dirs = &[(&saurian_ccl_path, "saurian"), (&ninja_ccl_path, "ninja")];
-
-    for (p, n) in dirs {
-        let t = TileImage::new()
-            .with_tileset(p.join("capcomlogo_local_00.bin"), 1)
-            .with_tilemap(p.join("capcomlogo_local_01.bin"), 32, 24)
-            .with_palette(p.join("capcomlogo_local_02.bin"))
-            .with_output(image_path.join(format!("capcomlogo_local_{n}_0.png")));
-        t.read_tileimg()?;
-
-        let t = TileImage::new()
-            .with_palette(p.join("capcomlogo_local_07.bin"))
-            .with_tileset(p.join("capcomlogo_local_08.bin"), 1)
-            .with_tilemap(p.join("capcomlogo_local_09.bin"), 32, 24)
-            .with_tileset(p.join("capcomlogo_local_10.bin"), 1)
-            .with_tilemap(p.join("capcomlogo_local_11.bin"), 32, 24)
-            .with_tileset(p.join("capcomlogo_local_12.bin"), 1)
-            .with_tilemap(p.join("capcomlogo_local_13.bin"), 32, 24)
-            .with_tileset(p.join("capcomlogo_local_14.bin"), 1)
-            .with_tilemap(p.join("capcomlogo_local_15.bin"), 32, 24)
-            .with_tileset(p.join("capcomlogo_local_16.bin"), 1)
-            .with_tilemap(p.join("capcomlogo_local_17.bin"), 32, 24)
-            .with_tileset(p.join("capcomlogo_local_18.bin"), 1)
-            .with_tilemap(p.join("capcomlogo_local_19.bin"), 32, 24)
-            .with_output(image_path.join(format!("capcomlogo_local_{n}_1.png")));
-        t.read_tileimg()?;
-
-        let t = TileImage::new()
-            .with_tileset(p.join("capcomlogo_local_22.bin"), 1)
-            .with_tilemap(p.join("capcomlogo_local_23.bin"), 32, 48)
-            .with_tileset(p.join("capcomlogo_local_24.bin"), 1)
-            .with_tilemap(p.join("capcomlogo_local_25.bin"), 32, 48)
-            .with_palette(p.join("capcomlogo_local_26.bin"))
-            .with_output(image_path.join(format!("capcomlogo_local_{n}_2.png")));
-        t.read_tileimg()?;
-
-        let t = TileImage::new()
-            .with_tileset(p.join("capcomlogo_local_28.bin"), 1)
-            .with_palette(p.join("capcomlogo_local_29.bin"))
-            .with_tilemap(p.join("capcomlogo_local_30.bin"), 32, 24)
-            .with_output(image_path.join(format!("capcomlogo_local_{n}_3.png")));
-        t.read_tileimg()?;
-
-        let t = TileImage::new()
-            .with_tileset(p.join("capcomlogo_local_32.bin"), 1)
-            .with_palette(p.join("capcomlogo_local_33.bin"))
-            .with_tilemap(p.join("capcomlogo_local_34.bin"), 32, 24)
-            .with_output(image_path.join(format!("capcomlogo_local_{n}_4.png")));
-        t.read_tileimg()?;
-
-        let t = TileImage::new()
-            .with_tileset(p.join("capcomlogo_local_35.bin"), 1)
-            .with_palette(p.join("capcomlogo_local_36.bin"))
-            .with_tilemap(p.join("capcomlogo_local_37.bin"), 32, 24)
-            .with_output(image_path.join(format!("capcomlogo_local_{n}_5.png")));
-        t.read_tileimg()?;
-
-        let t = TileImage::new()
-            .with_tileset(p.join("capcomlogo_local_38.bin"), 1)
-            .with_palette(p.join("capcomlogo_local_39.bin"))
-            .with_tilemap(p.join("capcomlogo_local_40.bin"), 32, 24)
-            .with_output(image_path.join(format!("capcomlogo_local_{n}_6.png")));
-        t.read_tileimg()?;
-    }
-
-    let saurian_ssl_path = cwd.join("_workspace/unpacked_bins/saurian/subscreen_local.bin");
-    let ninja_ssl_path = cwd.join("_workspace/unpacked_bins/ninja/subscreen_local.bin");
-    let dirs = &[(&saurian_ssl_path, "saurian"), (&ninja_ssl_path, "ninja")];
-
-    for (p, n) in dirs {
-        let t = TileImage::new()
-            .with_palette(p.join("subscreen_local_026.bin"))
-            .with_tileset(p.join("subscreen_local_027.bin"), 0)
-            .with_tilemap(p.join("subscreen_local_028.bin"), 11, 17)
-            .with_tilemap(p.join("subscreen_local_029.bin"), 11, 14)
-            .with_tilemap(p.join("subscreen_local_030.bin"), 11, 19)
-            .with_tilemap(p.join("subscreen_local_031.bin"), 11, 16)
-            .with_4bpp()
-            .with_output(image_path.join(format!("subscreen_local_{n}_0.png")));
-        t.read_tileimg()?;
-
-        let t = TileImage::new()
-            .with_palette(p.join("subscreen_local_036.bin"))
-            .with_tileset(p.join("subscreen_local_037.bin"), 1)
-            .with_tilemap(p.join("subscreen_local_038.bin"), 32, 24)
-            .with_tilemap(p.join("subscreen_local_039.bin"), 32, 24)
-            .with_tilemap(p.join("subscreen_local_040.bin"), 32, 24)
-            .with_tilemap(p.join("subscreen_local_041.bin"), 32, 24)
-            .with_tilemap(p.join("subscreen_local_042.bin"), 32, 24)
-            .with_output(image_path.join(format!("subscreen_local_{n}_1.png")));
-        t.read_tileimg()?;
-
-        let t = TileImage::new()
-            .with_palette(p.join("subscreen_local_087.bin"))
-            .with_tilemap(p.join("subscreen_local_088.bin"), 32, 24)
-            .with_tileset(p.join("subscreen_local_089.bin"), 1)
-            .with_output(image_path.join(format!("subscreen_local_{n}_2.png")));
-        t.read_tileimg()?;
-
-        let t = TileImage::new()
-            .with_palette(p.join("subscreen_local_069.bin"))
-            .with_tilemap(p.join("subscreen_local_072.bin"), 12, 5)
-            .with_tileset(p.join("subscreen_local_071.bin"), 1)
-            .with_4bpp()
-            .with_output(image_path.join(format!("subscreen_local_{n}_3.png")));
-        t.read_tileimg()?;
-    }
-
-    let saurian_rl_path = cwd.join("_workspace/unpacked_bins/saurian/result_local.bin");
-    let ninja_rl_path = cwd.join("_workspace/unpacked_bins/ninja/result_local.bin");
-    let dirs = &[(&saurian_rl_path, "saurian"), (&ninja_rl_path, "ninja")];
-
-    for (p, n) in dirs {
-        let t = TileImage::new()
-            .with_palette(p.join("result_local_00.bin"))
-            .with_tilemap(p.join("result_local_17.bin"), 32, 8)
-            .with_tileset(p.join("result_local_10.bin"), 0)
-            .with_4bpp()
-            .with_output(image_path.join(format!("result_local_{n}_0.png")));
-        t.read_tileimg()?;
-
-        let t = TileImage::new()
-            .with_palette(p.join("result_local_00.bin"))
-            .with_tilemap(p.join("result_local_21.bin"), 32, 8)
-            .with_tileset(p.join("result_local_14.bin"), 0)
-            .with_4bpp()
-            .with_output(image_path.join(format!("result_local_{n}_1.png")));
-        t.read_tileimg()?;
-    }
-
-    let ninja_cp_path = cwd.join("_workspace/unpacked_bins/ninja/cockpit_local.bin");
-
-    let tmp_tilemap_path = cwd.join("_temp/cockpit_customing_text_tilemap.bin");
-
-    std::fs::write(&tmp_tilemap_path, [0x00, 0x00, 0x01, 0x00])?;
-
-    for i in 13..=18 {
-        let t = TileImage::new()
-            .with_tilemap(tmp_tilemap_path.clone(), 1, 2)
-            .with_tileset(ninja_cp_path.join(format!("cockpit_local_{i}.bin")), 0)
-            .with_4bpp()
-            .with_output(image_path.join(format!("cockpit_customing_text_{}.png", i - 13)));
-        t.read_tileimg()?;
-    }
-
-    // let mut has_space = false;
-    // for entry in
-    //     std::fs::read_dir(cwd.join("_workspace/unpacked_bins/ninja/subscreen_local.bin"))?.flatten()
-    // {
-    //     if let std::result::Result::Ok(meta) = entry.metadata() {
-    //         if meta.is_file() {
-    //             let size = meta.file_size();
-    //             let data = std::fs::read(entry.path())?;
-    //             if sfsprite::SFSpriteEditor::from_data(&data).is_ok() {
-    //                 if !has_space {
-    //                     println!();
-    //                     has_space = true
-    //                 };
-    //                 continue;
-    //             }
-    //             match size / 2 {
-    //                 768 => {
-    //                     println!(
-    //                         "{}: {:<16} 疑似为图块表，大小可能是 32x24",
-    //                         entry
-    //                             .path()
-    //                             .file_name()
-    //                             .map(|x| x.to_string_lossy())
-    //                             .unwrap_or_default(),
-    //                         size,
-    //                     );
-    //                     has_space = false;
-    //                 }
-    //                 1536 => {
-    //                     println!(
-    //                         "{}: {:<16} 疑似为图块表，大小可能是 32x48",
-    //                         entry
-    //                             .path()
-    //                             .file_name()
-    //                             .map(|x| x.to_string_lossy())
-    //                             .unwrap_or_default(),
-    //                         size,
-    //                     );
-    //                     has_space = false;
-    //                 }
-    //                 _ => {
-    //                     if size % 2 == 0 && size <= 256 * 2 {
-    //                         println!(
-    //                             "{}: {:<16} 疑似为调色板",
-    //                             entry
-    //                                 .path()
-    //                                 .file_name()
-    //                                 .map(|x| x.to_string_lossy())
-    //                                 .unwrap_or_default(),
-    //                             size,
-    //                         );
-    //                         has_space = false;
-    //                     } else if size % (8 * 8) == 0 {
-    //                         println!(
-    //                             "{}: {:<16} 疑似为 8BPP 图块集，可能有 {} 个图块",
-    //                             entry
-    //                                 .path()
-    //                                 .file_name()
-    //                                 .map(|x| x.to_string_lossy())
-    //                                 .unwrap_or_default(),
-    //                             size,
-    //                             size / (8 * 8)
-    //                         );
-    //                         has_space = false;
-    //                     } else if size % (8 * 8 / 2) == 0 {
-    //                         println!(
-    //                             "{}: {:<16} 疑似为 4BPP 图块集，可能有 {} 个图块",
-    //                             entry
-    //                                 .path()
-    //                                 .file_name()
-    //                                 .map(|x| x.to_string_lossy())
-    //                                 .unwrap_or_default(),
-    //                             size,
-    //                             size / (8 * 8 / 2)
-    //                         );
-    //                         has_space = false;
-    //                     } else if !has_space {
-    //                         println!();
-    //                         has_space = true
-    //                     }
-    //                 }
-    //             }
-    //         }
-    //     }
-    // }
-
-    Ok(())
+fn default_versions() -> Vec<String> {
+    vec!["ninja".to_string(), "saurian".to_string()]
 }
 
-pub fn save_images() -> anyhow::Result<()> {
-    let cwd = std::env::current_dir().unwrap();
-    let _workspace_path = cwd.join("_workspace");
-    let _build_path = cwd.join("_build");
-    let images_path = cwd.join("images");
-    let temp_path = cwd.join("_temp/pack");
-    let _mess_path = temp_path.join("mess");
-    let _ninja_temp_path = temp_path.join("ninja");
-    let _saurian_temp_path = temp_path.join("saurian");
-    let _tools = ToolsRunner::new(Some(&cwd))?;
-
-    let saurian_ccl_path = cwd.join("_workspace/unpacked_bins/saurian/capcomlogo_local.bin");
-    let ninja_ccl_path = cwd.join("_workspace/unpacked_bins/ninja/capcomlogo_local.bin");
-    let t = TileImage::new()
-        .with_tileset(ninja_ccl_path.join("capcomlogo_local_22.bin"), 1)
-        .with_tilemap(ninja_ccl_path.join("capcomlogo_local_23.bin"), 32, 48)
-        .with_tileset(ninja_ccl_path.join("capcomlogo_local_24.bin"), 1)
-        .with_tilemap(ninja_ccl_path.join("capcomlogo_local_25.bin"), 32, 48)
-        .with_palette(ninja_ccl_path.join("capcomlogo_local_26.bin"))
-        .with_output(images_path.join("capcomlogo_local_ninja_0.png"));
-    t.save_tileimg()?;
-
-    let t = TileImage::new()
-        .with_tileset(saurian_ccl_path.join("capcomlogo_local_22.bin"), 1)
-        .with_tilemap(saurian_ccl_path.join("capcomlogo_local_23.bin"), 32, 48)
-        .with_tileset(saurian_ccl_path.join("capcomlogo_local_24.bin"), 1)
-        .with_tilemap(saurian_ccl_path.join("capcomlogo_local_25.bin"), 32, 48)
-        .with_palette(saurian_ccl_path.join("capcomlogo_local_26.bin"))
-        .with_output(images_path.join("capcomlogo_local_saurian_0.png"));
-    t.save_tileimg()?;
-
-    let t = TileImage::new()
-        .with_tileset(ninja_ccl_path.join("capcomlogo_local_00.bin"), 1)
-        .with_tilemap(ninja_ccl_path.join("capcomlogo_local_01.bin"), 32, 24)
-        .with_palette(ninja_ccl_path.join("capcomlogo_local_02.bin"))
-        .with_output(images_path.join("capcomlogo_local_ninja_1.png"));
-    t.save_tileimg()?;
-
-    let t = TileImage::new()
-        .with_tileset(saurian_ccl_path.join("capcomlogo_local_00.bin"), 1)
-        .with_tilemap(saurian_ccl_path.join("capcomlogo_local_01.bin"), 32, 24)
-        .with_palette(saurian_ccl_path.join("capcomlogo_local_02.bin"))
-        .with_output(images_path.join("capcomlogo_local_saurian_1.png"));
-    t.save_tileimg()?;
-
-    let p = ninja_ccl_path.to_owned();
-    let t = TileImage::new()
-        .with_palette(p.join("capcomlogo_local_07.bin"))
-        .with_tileset(p.join("capcomlogo_local_08.bin"), 1)
-        .with_tilemap(p.join("capcomlogo_local_09.bin"), 32, 24)
-        .with_tileset(p.join("capcomlogo_local_10.bin"), 1)
-        .with_tilemap(p.join("capcomlogo_local_11.bin"), 32, 24)
-        .with_tileset(p.join("capcomlogo_local_12.bin"), 1)
-        .with_tilemap(p.join("capcomlogo_local_13.bin"), 32, 24)
-        .with_tileset(p.join("capcomlogo_local_14.bin"), 1)
-        .with_tilemap(p.join("capcomlogo_local_15.bin"), 32, 24)
-        .with_tileset(p.join("capcomlogo_local_16.bin"), 1)
-        .with_tilemap(p.join("capcomlogo_local_17.bin"), 32, 24)
-        .with_tileset(p.join("capcomlogo_local_18.bin"), 1)
-        .with_tilemap(p.join("capcomlogo_local_19.bin"), 32, 24)
-        .with_output(images_path.join("capcomlogo_local_achivements.png"));
-    t.save_tileimg()?;
-
-    // #[rustfmt::skip]
-    {
-        std::fs::copy(
-            p.join("capcomlogo_local_07.bin"),
-            saurian_ccl_path.join("capcomlogo_local_07.bin"),
-        )?;
-        std::fs::copy(
-            p.join("capcomlogo_local_08.bin"),
-            saurian_ccl_path.join("capcomlogo_local_08.bin"),
-        )?;
-        std::fs::copy(
-            p.join("capcomlogo_local_09.bin"),
-            saurian_ccl_path.join("capcomlogo_local_09.bin"),
-        )?;
-        std::fs::copy(
-            p.join("capcomlogo_local_10.bin"),
-            saurian_ccl_path.join("capcomlogo_local_10.bin"),
-        )?;
-        std::fs::copy(
-            p.join("capcomlogo_local_11.bin"),
-            saurian_ccl_path.join("capcomlogo_local_11.bin"),
-        )?;
-        std::fs::copy(
-            p.join("capcomlogo_local_12.bin"),
-            saurian_ccl_path.join("capcomlogo_local_12.bin"),
-        )?;
-        std::fs::copy(
-            p.join("capcomlogo_local_13.bin"),
-            saurian_ccl_path.join("capcomlogo_local_13.bin"),
-        )?;
-        std::fs::copy(
-            p.join("capcomlogo_local_14.bin"),
-            saurian_ccl_path.join("capcomlogo_local_14.bin"),
-        )?;
-        std::fs::copy(
-            p.join("capcomlogo_local_15.bin"),
-            saurian_ccl_path.join("capcomlogo_local_15.bin"),
-        )?;
-        std::fs::copy(
-            p.join("capcomlogo_local_16.bin"),
-            saurian_ccl_path.join("capcomlogo_local_16.bin"),
-        )?;
-        std::fs::copy(
-            p.join("capcomlogo_local_17.bin"),
-            saurian_ccl_path.join("capcomlogo_local_17.bin"),
-        )?;
-        std::fs::copy(
-            p.join("capcomlogo_local_18.bin"),
-            saurian_ccl_path.join("capcomlogo_local_18.bin"),
-        )?;
-        std::fs::copy(
-            p.join("capcomlogo_local_19.bin"),
-            saurian_ccl_path.join("capcomlogo_local_19.bin"),
-        )?;
-    }
-
-    let saurian_ssl_path = cwd.join("_workspace/unpacked_bins/saurian/subscreen_local.bin");
-    let ninja_ssl_path = cwd.join("_workspace/unpacked_bins/ninja/subscreen_local.bin");
-    let p = ninja_ssl_path.to_owned();
+/// 清单中的一张可编辑图像
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestImage {
+    pub archive: String,
+    #[serde(default)]
+    pub tilesets: Vec<ManifestTileset>,
+    pub tilemaps: Vec<ManifestTilemap>,
+    pub palette: Option<usize>,
+    /// 写回时改用 `images` 文件夹下的调色板文件，没有 `palette` 时导出也使用它
+    pub palette_file: Option<PathBuf>,
+    #[serde(default)]
+    pub palette_mode: PaletteMode,
+    pub default_color_index: Option<u8>,
+    /// 使用参考调色板写回时，调色板中没有的颜色如何映射
+    #[serde(default)]
+    pub color_matching: ColorMatching,
+    #[serde(default = "default_bpp")]
+    pub bpp: u8,
+    #[serde(default = "default_versions")]
+    pub versions: Vec<String>,
+    /// 写回时只使用第一个版本的图像，写出的文件再复制到其余版本
+    #[serde(default)]
+    pub shared: bool,
+    pub dump: String,
+    pub save: Option<String>,
+}
 
-    let t = TileImage::new()
-        .with_palette(images_path.join("subscreen_local_026.bin")) // 将第一个颜色修改成了透明色，即黑色
-        .with_tileset(p.join("subscreen_local_027.bin"), 0)
-        .with_tilemap(p.join("subscreen_local_028.bin"), 11, 17)
-        .with_tilemap(p.join("subscreen_local_029.bin"), 11, 14)
-        .with_tilemap(p.join("subscreen_local_030.bin"), 11, 19)
-        .with_tilemap(p.join("subscreen_local_031.bin"), 11, 16)
-        .with_palette_reference()
-        .with_4bpp()
-        .with_output(images_path.join("subscreen_local_sort.png"));
-    t.save_tileimg()?;
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageManifest {
+    pub screens: Vec<ManifestImage>,
+}
 
-    // #[rustfmt::skip]
-    {
-        std::fs::copy(
-            p.join("subscreen_local_027.bin"),
-            saurian_ssl_path.join("subscreen_local_027.bin"),
-        )?;
-        std::fs::copy(
-            p.join("subscreen_local_028.bin"),
-            saurian_ssl_path.join("subscreen_local_028.bin"),
-        )?;
-        std::fs::copy(
-            p.join("subscreen_local_029.bin"),
-            saurian_ssl_path.join("subscreen_local_029.bin"),
-        )?;
-        std::fs::copy(
-            p.join("subscreen_local_030.bin"),
-            saurian_ssl_path.join("subscreen_local_030.bin"),
-        )?;
-        std::fs::copy(
-            p.join("subscreen_local_031.bin"),
-            saurian_ssl_path.join("subscreen_local_031.bin"),
-        )?;
+impl ImageManifest {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("无法打开图像清单 {}", path.display()))?;
+        let manifest: Self = serde_yaml::from_reader(file)
+            .with_context(|| format!("无法解析图像清单 {}", path.display()))?;
+        for screen in &manifest.screens {
+            ensure!(
+                screen.bpp == 4 || screen.bpp == 8,
+                "图像 {} 的 bpp 只能为 4 或 8",
+                screen.dump
+            );
+            ensure!(
+                !screen.versions.is_empty(),
+                "图像 {} 没有指定版本",
+                screen.dump
+            );
+            ensure!(
+                screen.color_matching == ColorMatching::Exact
+                    || screen.palette_mode == PaletteMode::Reference,
+                "图像 {} 的 color_matching 只能在 palette_mode 为 reference 时使用",
+                screen.dump
+            );
+        }
+        Ok(manifest)
     }
+}
 
-    let t = TileImage::new()
-        .with_palette(p.join("subscreen_local_036.bin"))
-        .with_tileset(p.join("subscreen_local_037.bin"), 1)
-        .with_tilemap(p.join("subscreen_local_038.bin"), 32, 24)
-        .with_tilemap(p.join("subscreen_local_039.bin"), 32, 24)
-        .with_tilemap(p.join("subscreen_local_040.bin"), 32, 24)
-        .with_tilemap(p.join("subscreen_local_041.bin"), 32, 24)
-        .with_tilemap(p.join("subscreen_local_042.bin"), 32, 24)
-        .with_palette_reference()
-        .with_output(images_path.join("subscreen_local_input.png"));
-    t.save_tileimg()?;
+/// 查找解包后的归档项文件，序号位数与解包时一致
+fn find_archive_entry(archive_dir: &Path, archive: &str, entry: usize) -> anyhow::Result<PathBuf> {
+    (2..=4)
+        .map(|width| archive_dir.join(format!("{archive}_{entry:0width$}.bin")))
+        .find(|x| x.is_file())
+        .with_context(|| {
+            format!(
+                "无法在 {} 中找到归档 {archive} 的第 {entry} 项",
+                archive_dir.display()
+            )
+        })
+}
 
-    // #[rustfmt::skip]
-    {
-        std::fs::copy(
-            p.join("subscreen_local_037.bin"),
-            saurian_ssl_path.join("subscreen_local_037.bin"),
-        )?;
-        std::fs::copy(
-            p.join("subscreen_local_038.bin"),
-            saurian_ssl_path.join("subscreen_local_038.bin"),
-        )?;
-        std::fs::copy(
-            p.join("subscreen_local_039.bin"),
-            saurian_ssl_path.join("subscreen_local_039.bin"),
-        )?;
-        std::fs::copy(
-            p.join("subscreen_local_040.bin"),
-            saurian_ssl_path.join("subscreen_local_040.bin"),
-        )?;
-        std::fs::copy(
-            p.join("subscreen_local_041.bin"),
-            saurian_ssl_path.join("subscreen_local_041.bin"),
-        )?;
-        std::fs::copy(
-            p.join("subscreen_local_042.bin"),
-            saurian_ssl_path.join("subscreen_local_042.bin"),
-        )?;
+impl ManifestImage {
+    fn archive_dir(&self, paths: &ProjectPaths, version: &str) -> PathBuf {
+        paths
+            .unpacked_bins
+            .join(version)
+            .join(format!("{}.bin", self.archive))
     }
 
-    let t = TileImage::new()
-        .with_palette(p.join("subscreen_local_087.bin"))
-        .with_tilemap(p.join("subscreen_local_088.bin"), 32, 24)
-        .with_tileset(p.join("subscreen_local_089.bin"), 1)
-        .with_palette_reference()
-        .with_output(images_path.join("subscreen_local_shop.png"));
-    t.save_tileimg()?;
-
-    // #[rustfmt::skip]
-    {
-        std::fs::copy(
-            p.join("subscreen_local_088.bin"),
-            saurian_ssl_path.join("subscreen_local_088.bin"),
-        )?;
-        std::fs::copy(
-            p.join("subscreen_local_089.bin"),
-            saurian_ssl_path.join("subscreen_local_089.bin"),
-        )?;
+    /// 图像对应的所有归档项，写回后共享的版本需要复制这些文件
+    fn entries(&self) -> Vec<usize> {
+        let mut entries = self
+            .tilesets
+            .iter()
+            .map(|x| x.entry)
+            .chain(self.tilemaps.iter().filter_map(|x| x.entry))
+            .collect::<Vec<_>>();
+        if self.palette_mode == PaletteMode::Extract && self.palette_file.is_none() {
+            entries.extend(self.palette);
+        }
+        entries
     }
 
-    let t = TileImage::new()
-        .with_palette(p.join("subscreen_local_069.bin"))
-        .with_tilemap(p.join("subscreen_local_072.bin"), 12, 5)
-        .with_tileset(p.join("subscreen_local_071.bin"), 1)
-        .with_palette_reference()
-        .with_4bpp()
-        .with_output(images_path.join("subscreen_local_mailer_list.png"));
-    t.save_tileimg()?;
-
-    {
-        std::fs::copy(
-            p.join("subscreen_local_072.bin"),
-            saurian_ssl_path.join("subscreen_local_072.bin"),
-        )?;
-        std::fs::copy(
-            p.join("subscreen_local_071.bin"),
-            saurian_ssl_path.join("subscreen_local_071.bin"),
-        )?;
+    fn to_tile_image(
+        &self,
+        paths: &ProjectPaths,
+        version: &str,
+        for_save: bool,
+        output: impl AsRef<Path>,
+    ) -> anyhow::Result<TileImage> {
+        let archive_dir = self.archive_dir(paths, version);
+        let output = output.as_ref();
+        let mut image = TileImage::new().with_output(output);
+        for tileset in &self.tilesets {
+            image = image.with_tileset(
+                find_archive_entry(&archive_dir, &self.archive, tileset.entry)?,
+                tileset.base_tile,
+            );
+        }
+        for tilemap in &self.tilemaps {
+            image = match tilemap.entry {
+                Some(entry) => image.with_tilemap(
+                    find_archive_entry(&archive_dir, &self.archive, entry)?,
+                    tilemap.width,
+                    tilemap.height,
+                ),
+                None => image.with_tile_sequence(tilemap.width, tilemap.height, 0, 0, 0),
+            };
+            if let Some(meta) = image.tilemap.last_mut() {
+                meta.tileset = tilemap.tileset;
+                meta.position = tilemap.position;
+            }
+        }
+        match (&self.palette_file, self.palette) {
+            (Some(palette_file), palette) if for_save || palette.is_none() => {
+                image = image.with_palette(paths.images.join(palette_file));
+            }
+            (_, Some(palette)) => {
+                image =
+                    image.with_palette(find_archive_entry(&archive_dir, &self.archive, palette)?);
+            }
+            _ => {}
+        }
+        if self.palette_mode == PaletteMode::Reference {
+            image = image.with_palette_reference();
+        }
+        if let Some(index) = self.default_color_index {
+            image = image.with_default_color_index(index);
+        }
+        if for_save && self.color_matching != ColorMatching::Exact {
+            let report = paths
+                .workspace
+                .join("images/report")
+                .join(output.file_name().unwrap_or_default());
+            image = image
+                .with_color_matching(self.color_matching)
+                .with_report(report);
+        }
+        if self.bpp == 4 {
+            image = image.with_4bpp();
+        }
+        Ok(image)
     }
+}
 
-    let saurian_rl_path = cwd.join("_workspace/unpacked_bins/saurian/result_local.bin");
-    let ninja_rl_path = cwd.join("_workspace/unpacked_bins/ninja/result_local.bin");
-    let p = ninja_rl_path.to_owned();
-
-    let t = TileImage::new()
-        .with_palette(p.join("result_local_00.bin"))
-        .with_tilemap(p.join("result_local_17.bin"), 32, 8)
-        .with_tileset(p.join("result_local_10.bin"), 0)
-        .with_palette_reference()
-        .with_4bpp()
-        .with_output(images_path.join("result_local_delete_panel.png"));
-    t.save_tileimg()?;
-
-    {
-        std::fs::copy(
-            p.join("result_local_17.bin"),
-            saurian_rl_path.join("result_local_17.bin"),
-        )?;
-        std::fs::copy(
-            p.join("result_local_10.bin"),
-            saurian_rl_path.join("result_local_10.bin"),
-        )?;
-    }
+fn load_manifest(paths: &ProjectPaths) -> anyhow::Result<ImageManifest> {
+    ImageManifest::open(paths.images.join(IMAGE_MANIFEST_FILE))
+}
 
-    let t = TileImage::new()
-        .with_palette(p.join("result_local_00.bin"))
-        .with_tilemap(p.join("result_local_21.bin"), 32, 8)
-        .with_tileset(p.join("result_local_14.bin"), 0)
-        .with_palette_reference()
-        .with_4bpp()
-        .with_output(images_path.join("result_local_loser_delete_panel.png"));
-    t.save_tileimg()?;
+pub fn dump_images(paths: &ProjectPaths) -> anyhow::Result<()> {
+    let image_path = paths.workspace.join("images");
+    std::fs::create_dir_all(&image_path)?;
 
-    {
-        std::fs::copy(
-            p.join("result_local_21.bin"),
-            saurian_rl_path.join("result_local_21.bin"),
-        )?;
-        std::fs::copy(
-            p.join("result_local_14.bin"),
-            saurian_rl_path.join("result_local_14.bin"),
-        )?;
+    let manifest = load_manifest(paths)?;
+    for screen in &manifest.screens {
+        for version in &screen.versions {
+            let output = image_path.join(screen.dump.replace("{version}", version));
+            screen
+                .to_tile_image(paths, version, false, &output)?
+                .read_tileimg()
+                .with_context(|| format!("导出图像 {} 失败", output.display()))?;
+        }
     }
 
-    let ninja_cp_path = cwd.join("_workspace/unpacked_bins/ninja/cockpit_local.bin");
-    let saurian_cp_path = cwd.join("_workspace/unpacked_bins/saurian/cockpit_local.bin");
+    Ok(())
+}
 
-    let cockpit_customing_text = image::open(images_path.join("cockpit_customing_text.png"))?;
+pub fn save_images(paths: &ProjectPaths) -> anyhow::Result<()> {
+    let images_path = &paths.images;
 
-    let mut t = Tile4BPP::new();
-    for i in 0..6 {
-        let mut out =
-            std::fs::File::create(ninja_cp_path.join(format!("cockpit_local_{}.bin", i + 13)))?;
-        let croped = cockpit_customing_text.crop_imm(i * 8, 0, 8, 16);
-        let get_p = |x: usize, y: usize| -> u8 {
-            let pixel = croped.get_pixel(x as u32, y as u32);
-            // a80840 3010e8
-            if pixel.0 == [0xA8, 0x08, 0x40, 0xFF] {
-                2
-            } else if pixel.0 == [0x30, 0x10, 0xE8, 0xFF] {
-                1
-            } else {
-                0
-            }
+    let manifest = load_manifest(paths)?;
+    for screen in &manifest.screens {
+        let Some(save) = &screen.save else {
+            continue;
         };
-        t.fill(0);
-        for y in 0..8 {
-            for x in 0..8 {
-                let pixel = get_p(x, y);
-                t.set_pixel(x, y, pixel);
-            }
+        let versions = if screen.shared {
+            &screen.versions[..1]
+        } else {
+            &screen.versions[..]
+        };
+        for version in versions {
+            let input = images_path.join(save.replace("{version}", version));
+            screen
+                .to_tile_image(paths, version, true, &input)?
+                .save_tileimg()
+                .with_context(|| format!("写回图像 {} 失败", input.display()))?;
         }
-        out.write_all(t.as_raw())?;
-        t.fill(0);
-        for y in 0..8 {
-            for x in 0..8 {
-                let pixel = get_p(x, y + 8);
-                t.set_pixel(x, y, pixel);
+
+        if screen.shared {
+            let source_dir = screen.archive_dir(paths, &screen.versions[0]);
+            for version in &screen.versions[1..] {
+                let target_dir = screen.archive_dir(paths, version);
+                for entry in screen.entries() {
+                    let source = find_archive_entry(&source_dir, &screen.archive, entry)?;
+                    std::fs::copy(&source, target_dir.join(source.file_name().unwrap()))?;
+                }
             }
         }
-        out.write_all(t.as_raw())?;
-        drop(out);
-        std::fs::copy(
-            ninja_cp_path.join(format!("cockpit_local_{}.bin", i + 13)),
-            saurian_cp_path.join(format!("cockpit_local_{}.bin", i + 13)),
-        )?;
     }
 
     Ok(())
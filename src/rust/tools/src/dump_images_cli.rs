@@ -1,3 +0,0 @@
-pub fn main() -> anyhow::Result<()> {
-    tools::dump_images::dump_images()
-}
@@ -1,2 +1,3 @@
+pub mod commands;
 pub mod dump_images;
 pub mod utils;
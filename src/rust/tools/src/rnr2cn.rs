@@ -0,0 +1,3 @@
+fn main() -> anyhow::Result<()> {
+    tools::commands::run(std::env::args().skip(1))
+}
@@ -0,0 +1,493 @@
+//! 增量构建：把构建步骤描述为目标之间的依赖图，只重新执行受影响的目标
+//!
+//! 每个目标声明输入和输出的文件或文件夹，执行成功后把输入和输出的哈希记录在状态文件中。
+//! 再次构建时，输入（包括依赖目标的输出）和输出都与记录一致的目标会被跳过。
+//! 依赖都已完成的目标通过 [`JobRunner`] 并行执行。
+//! 文件哈希按大小和修改时间缓存，未改动的大文件不需要重新读取。
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use super::{fs::write_atomic, jobs::JobRunner};
+
+type Action<'a> = Box<dyn FnOnce() -> anyhow::Result<()> + Send + 'a>;
+
+pub struct Target<'a> {
+    name: String,
+    deps: Vec<String>,
+    inputs: Vec<PathBuf>,
+    outputs: Vec<PathBuf>,
+    action: Action<'a>,
+}
+
+impl<'a> Target<'a> {
+    pub fn new(
+        name: impl Into<String>,
+        action: impl FnOnce() -> anyhow::Result<()> + Send + 'a,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            deps: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            action: Box::new(action),
+        }
+    }
+
+    /// 依赖的目标完成后才会执行，依赖目标的输出变化时也会重新执行
+    pub fn with_dep(mut self, name: impl Into<String>) -> Self {
+        self.deps.push(name.into());
+        self
+    }
+
+    /// 输入的文件或文件夹，文件夹会递归计算哈希
+    pub fn with_input(mut self, path: impl AsRef<Path>) -> Self {
+        self.inputs.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn with_inputs(mut self, paths: impl IntoIterator<Item = impl AsRef<Path>>) -> Self {
+        self.inputs
+            .extend(paths.into_iter().map(|x| x.as_ref().to_path_buf()));
+        self
+    }
+
+    /// 输出的文件或文件夹，输出缺失或者被修改过时也会重新执行
+    pub fn with_output(mut self, path: impl AsRef<Path>) -> Self {
+        self.outputs.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn with_outputs(mut self, paths: impl IntoIterator<Item = impl AsRef<Path>>) -> Self {
+        self.outputs
+            .extend(paths.into_iter().map(|x| x.as_ref().to_path_buf()));
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileStamp {
+    size: u64,
+    /// 修改时间，自 UNIX 纪元起的纳秒数
+    modified: u128,
+    md5: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct TargetRecord {
+    inputs: String,
+    outputs: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BuildState {
+    targets: BTreeMap<String, TargetRecord>,
+    files: BTreeMap<String, FileStamp>,
+}
+
+/// 计算文件和文件夹的哈希，只保留本次用到的文件缓存
+struct Hasher {
+    cache: BTreeMap<String, FileStamp>,
+    files: BTreeMap<String, FileStamp>,
+}
+
+impl Hasher {
+    fn hash_file(&mut self, path: &Path) -> Result<String> {
+        let meta = std::fs::metadata(path)?;
+        let modified = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_nanos());
+        let key = path.to_string_lossy().into_owned();
+        let cached = self.files.get(&key).or_else(|| self.cache.get(&key));
+        if let Some(stamp) = cached.filter(|x| x.size == meta.len() && x.modified == modified) {
+            let md5 = stamp.md5.clone();
+            self.files.insert(key, stamp.clone());
+            return Ok(md5);
+        }
+        let data = std::fs::read(path).with_context(|| format!("无法读取 {}", path.display()))?;
+        let md5 = format!("{:x}", md5::compute(data));
+        self.files.insert(
+            key,
+            FileStamp {
+                size: meta.len(),
+                modified,
+                md5: md5.clone(),
+            },
+        );
+        Ok(md5)
+    }
+
+    fn hash_path(&mut self, path: &Path, context: &mut md5::Context) -> Result<()> {
+        if path.is_dir() {
+            let mut entries = std::fs::read_dir(path)
+                .with_context(|| format!("无法读取文件夹 {}", path.display()))?
+                .flatten()
+                .map(|x| x.path())
+                .collect::<Vec<_>>();
+            entries.sort();
+            context.consume(b"dir\0");
+            for entry in entries {
+                context.consume(entry.file_name().unwrap_or_default().as_encoded_bytes());
+                context.consume(b"\0");
+                self.hash_path(&entry, context)?;
+            }
+            context.consume(b"end\0");
+        } else if path.is_file() {
+            context.consume(self.hash_file(path)?);
+            context.consume(b"\0");
+        } else {
+            context.consume(b"missing\0");
+        }
+        Ok(())
+    }
+
+    fn hash_paths(&mut self, paths: &[PathBuf]) -> Result<String> {
+        let mut context = md5::Context::new();
+        for path in paths {
+            context.consume(path.as_os_str().as_encoded_bytes());
+            context.consume(b"\0");
+            self.hash_path(path, &mut context)?;
+        }
+        Ok(format!("{:x}", context.compute()))
+    }
+}
+
+pub struct BuildGraph<'a> {
+    state_path: PathBuf,
+    force: bool,
+    dry_run: bool,
+    verbose: bool,
+    targets: Vec<Target<'a>>,
+}
+
+impl<'a> BuildGraph<'a> {
+    /// `state_path` 为记录构建状态的文件
+    pub fn new(state_path: impl AsRef<Path>) -> Self {
+        Self {
+            state_path: state_path.as_ref().to_path_buf(),
+            force: false,
+            dry_run: false,
+            verbose: false,
+            targets: Vec::new(),
+        }
+    }
+
+    /// 忽略记录的状态，重新执行所有目标
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// 只列出需要重新构建的目标，不执行也不更新状态
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// 同时列出已是最新的目标
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    pub fn add(&mut self, target: Target<'a>) {
+        self.targets.push(target);
+    }
+
+    fn load_state(&self) -> BuildState {
+        let Result::Ok(content) = std::fs::read(&self.state_path) else {
+            return BuildState::default();
+        };
+        serde_json::from_slice(&content).unwrap_or_else(|err| {
+            println!(
+                "警告：无法解析构建状态 {}，将重新构建所有目标：{err}",
+                self.state_path.display()
+            );
+            BuildState::default()
+        })
+    }
+
+    /// 按依赖顺序执行所有需要重新构建的目标
+    pub fn run(self) -> anyhow::Result<()> {
+        let mut names = HashSet::new();
+        for target in &self.targets {
+            ensure!(
+                names.insert(target.name.as_str()),
+                "构建目标 {} 重复",
+                target.name
+            );
+        }
+        for target in &self.targets {
+            for dep in &target.deps {
+                ensure!(
+                    names.contains(dep.as_str()),
+                    "构建目标 {} 依赖的 {dep} 不存在",
+                    target.name
+                );
+            }
+        }
+
+        let mut state = self.load_state();
+        let mut hasher = Hasher {
+            cache: std::mem::take(&mut state.files),
+            files: BTreeMap::new(),
+        };
+        if self.force {
+            state.targets.clear();
+        }
+        // 已完成的目标的输出哈希
+        let mut finished: HashMap<String, String> = HashMap::new();
+        // dry-run 时需要重新构建的目标，依赖它们的目标也需要重新构建
+        let mut dirty = HashSet::new();
+        let (mut skipped, mut rebuilt) = (0, 0);
+        let mut pending = self.targets;
+
+        while !pending.is_empty() {
+            let (ready, rest): (Vec<_>, Vec<_>) = pending
+                .into_iter()
+                .partition(|x| x.deps.iter().all(|dep| finished.contains_key(dep)));
+            pending = rest;
+            ensure!(
+                !ready.is_empty(),
+                "构建目标之间存在循环依赖：{}",
+                pending
+                    .iter()
+                    .map(|x| x.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join("、")
+            );
+
+            let succeeded = Mutex::new(Vec::new());
+            let mut scheduled = HashMap::new();
+            let mut runner = JobRunner::new("构建");
+            for target in ready {
+                if target.deps.iter().any(|dep| dirty.contains(dep)) {
+                    println!("dry-run：将重新构建 {}", target.name);
+                    dirty.insert(target.name.clone());
+                    finished.insert(target.name, String::new());
+                    continue;
+                }
+                let mut context = md5::Context::new();
+                for dep in &target.deps {
+                    context.consume(dep.as_bytes());
+                    context.consume(b"\0");
+                    context.consume(finished[dep.as_str()].as_bytes());
+                }
+                context.consume(hasher.hash_paths(&target.inputs)?);
+                let inputs = format!("{:x}", context.compute());
+
+                if let Some(record) = state.targets.get(&target.name) {
+                    let outputs = hasher.hash_paths(&target.outputs)?;
+                    if record.inputs == inputs && record.outputs == outputs {
+                        if self.verbose {
+                            println!("{} 已是最新", target.name);
+                        }
+                        skipped += 1;
+                        finished.insert(target.name, outputs);
+                        continue;
+                    }
+                }
+
+                if self.dry_run {
+                    println!("dry-run：将重新构建 {}", target.name);
+                    dirty.insert(target.name.clone());
+                    finished.insert(target.name, String::new());
+                    continue;
+                }
+
+                scheduled.insert(target.name.clone(), (inputs, target.outputs));
+                let succeeded = &succeeded;
+                let name = target.name.clone();
+                let action = target.action;
+                runner.add(target.name, move || {
+                    action()?;
+                    succeeded.lock().unwrap().push(name);
+                    Ok(())
+                });
+            }
+            if self.dry_run {
+                continue;
+            }
+            let result = runner.run();
+
+            for name in succeeded.into_inner().unwrap() {
+                let (inputs, outputs) = scheduled.remove(&name).unwrap();
+                let outputs = hasher.hash_paths(&outputs)?;
+                state.targets.insert(
+                    name.clone(),
+                    TargetRecord {
+                        inputs,
+                        outputs: outputs.clone(),
+                    },
+                );
+                finished.insert(name, outputs);
+                rebuilt += 1;
+            }
+            // 失败的目标下次一定重新执行
+            for name in scheduled.keys() {
+                state.targets.remove(name);
+            }
+            state.files = hasher.files.clone();
+            if let Some(parent) = self.state_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            write_atomic(&self.state_path, serde_json::to_vec_pretty(&state)?)
+                .with_context(|| format!("无法写入构建状态 {}", self.state_path.display()))?;
+            result?;
+        }
+
+        if self.dry_run {
+            println!(
+                "dry-run：{} 个目标需要重新构建，{skipped} 个目标已是最新",
+                dirty.len()
+            );
+        } else {
+            println!("{rebuilt} 个目标重新构建，{skipped} 个目标已是最新");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("build-graph-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// 复制文件并计数的目标
+    fn copy<'a>(
+        name: &str,
+        input: &'a Path,
+        output: &'a Path,
+        count: &'a AtomicUsize,
+    ) -> Target<'a> {
+        Target::new(name, move || {
+            count.fetch_add(1, Ordering::SeqCst);
+            std::fs::copy(input, output)?;
+            Ok(())
+        })
+        .with_input(input)
+        .with_output(output)
+    }
+
+    /// a 复制 src，b 依赖 a 并复制 a 的输出，c 与它们无关，返回三个目标的执行次数
+    fn build(dir: &Path, force: bool) -> anyhow::Result<[usize; 3]> {
+        let counts = [0; 3].map(AtomicUsize::new);
+        let paths = ["src", "a", "b", "other", "c"].map(|x| dir.join(x));
+        let mut graph = BuildGraph::new(dir.join("state.json")).with_force(force);
+        graph.add(copy("a", &paths[0], &paths[1], &counts[0]));
+        graph.add(copy("b", &paths[1], &paths[2], &counts[1]).with_dep("a"));
+        graph.add(copy("c", &paths[3], &paths[4], &counts[2]));
+        graph.run()?;
+        Ok(counts.map(|x| x.into_inner()))
+    }
+
+    fn setup(name: &str) -> PathBuf {
+        let dir = temp_dir(name);
+        std::fs::write(dir.join("src"), "src").unwrap();
+        std::fs::write(dir.join("other"), "other").unwrap();
+        assert_eq!(build(&dir, false).unwrap(), [1, 1, 1]);
+        dir
+    }
+
+    #[test]
+    fn unchanged_inputs_are_skipped() {
+        let dir = setup("skip");
+        assert_eq!(build(&dir, false).unwrap(), [0, 0, 0]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn changed_input_reruns_dependents() {
+        let dir = setup("changed");
+        std::fs::write(dir.join("src"), "changed src").unwrap();
+        assert_eq!(build(&dir, false).unwrap(), [1, 1, 0]);
+        assert_eq!(std::fs::read(dir.join("b")).unwrap(), b"changed src");
+        assert_eq!(build(&dir, false).unwrap(), [0, 0, 0]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn deleted_output_forces_rerun() {
+        let dir = setup("deleted");
+        std::fs::remove_file(dir.join("b")).unwrap();
+        assert_eq!(build(&dir, false).unwrap(), [0, 1, 0]);
+        assert!(dir.join("b").is_file());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn force_reruns_everything() {
+        let dir = setup("force");
+        assert_eq!(build(&dir, true).unwrap(), [1, 1, 1]);
+        assert_eq!(build(&dir, false).unwrap(), [0, 0, 0]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_target_is_rerun() {
+        let dir = temp_dir("failed");
+        let output = dir.join("out");
+        let count = AtomicUsize::new(0);
+        let run = |fail: bool| {
+            let mut graph = BuildGraph::new(dir.join("state.json"));
+            graph.add(
+                Target::new("t", || {
+                    count.fetch_add(1, Ordering::SeqCst);
+                    std::fs::write(&output, "out")?;
+                    ensure!(!fail, "失败");
+                    Ok(())
+                })
+                .with_output(&output),
+            );
+            graph.run()
+        };
+        // 失败前已经写出了输出，也不能被当作最新
+        assert!(run(true).is_err());
+        assert!(run(false).is_ok());
+        assert!(run(false).is_ok());
+        assert_eq!(count.into_inner(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cycle_and_missing_dep_are_errors() {
+        let dir = temp_dir("cycle");
+        let count = AtomicUsize::new(0);
+        let target = |name: &str, dep: &str| {
+            Target::new(name, || {
+                count.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .with_dep(dep)
+        };
+
+        let mut graph = BuildGraph::new(dir.join("state.json"));
+        graph.add(target("a", "b"));
+        graph.add(target("b", "a"));
+        let err = graph.run().unwrap_err();
+        assert!(err.to_string().contains("循环依赖"), "{err}");
+
+        let mut graph = BuildGraph::new(dir.join("state.json"));
+        graph.add(target("a", "missing"));
+        let err = graph.run().unwrap_err();
+        assert!(err.to_string().contains("missing"), "{err}");
+
+        assert_eq!(count.into_inner(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
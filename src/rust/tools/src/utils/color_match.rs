@@ -0,0 +1,374 @@
+//! 将图像中调色板里没有的颜色映射到感知上最接近的调色板颜色
+//!
+//! 颜色距离在 CIELAB 空间中计算。4bpp 图像的每个图块先选出整体误差最小的一组 16 色调色板，
+//! 再在这组调色板和共用的透明色中为每个像素挑选颜色。调色板中已有的颜色保持不变，
+//! 抖动只作用于需要映射的像素。
+
+use image::{Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+use sfbase::GBAColor;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorMatching {
+    /// 只接受调色板中已有的颜色
+    #[default]
+    Exact,
+    /// 映射到最接近的颜色
+    Nearest,
+    /// 映射到最接近的颜色，并使用 4x4 Bayer 有序抖动
+    Ordered,
+    /// 映射到最接近的颜色，并使用 Floyd-Steinberg 误差扩散抖动
+    ErrorDiffusion,
+}
+
+/// 以像素为单位的矩形区域，位置和大小都是 8 的倍数
+#[derive(Debug, Clone, Copy)]
+pub struct TileRegion {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+pub struct RemapResult {
+    pub image: RgbImage,
+    /// 每个像素是否被映射为其他颜色，按行排列
+    pub remapped: Vec<bool>,
+}
+
+impl RemapResult {
+    pub fn remapped_count(&self) -> usize {
+        self.remapped.iter().filter(|x| **x).count()
+    }
+
+    /// 生成映射报告图像，被映射的像素显示为洋红色，其余像素变暗显示为灰色
+    pub fn report(&self, original: &RgbImage) -> RgbImage {
+        let mut report = RgbImage::new(original.width(), original.height());
+        for (i, (x, y, p)) in original.enumerate_pixels().enumerate() {
+            let color = if self.remapped[i] {
+                Rgb([0xFF, 0x00, 0xFF])
+            } else {
+                let luma = (p.0[0] as u32 * 299 + p.0[1] as u32 * 587 + p.0[2] as u32 * 114) / 1000;
+                let gray = (luma / 2 + 32) as u8;
+                Rgb([gray, gray, gray])
+            };
+            report.put_pixel(x, y, color);
+        }
+        report
+    }
+}
+
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// 有序抖动的偏移范围，约为 GBA 颜色四级
+const ORDERED_SPREAD: f32 = 32.0;
+
+fn srgb_to_linear(c: f32) -> f32 {
+    let c = c / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn rgb_to_lab(rgb: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(|x| srgb_to_linear(x.clamp(0.0, 255.0)));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+fn rgb_f32(p: &Rgb<u8>) -> [f32; 3] {
+    p.0.map(|x| x as f32)
+}
+
+struct PaletteColor {
+    color: GBAColor,
+    rgb: Rgb<u8>,
+    lab: [f32; 3],
+}
+
+/// 每个图块可以使用的调色板索引
+fn tile_candidates(
+    img: &RgbImage,
+    palette: &[PaletteColor],
+    bank_size: Option<usize>,
+    tx: usize,
+    ty: usize,
+) -> Vec<usize> {
+    let Some(bank_size) = bank_size else {
+        return (0..palette.len()).collect();
+    };
+
+    let banks = (0..palette.len().div_ceil(bank_size))
+        .map(|bank| {
+            let start = bank * bank_size + 1;
+            std::iter::once(0)
+                .chain(start..palette.len().min(start + bank_size - 1))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let cost = |candidates: &Vec<usize>| {
+        let mut cost = 0.0;
+        for y in 0..8 {
+            for x in 0..8 {
+                let p = img.get_pixel((tx * 8 + x) as _, (ty * 8 + y) as _);
+                let color = GBAColor::from(p);
+                if candidates.iter().any(|i| palette[*i].color == color) {
+                    continue;
+                }
+                let lab = rgb_to_lab(rgb_f32(p));
+                cost += candidates
+                    .iter()
+                    .map(|i| distance(&lab, &palette[*i].lab))
+                    .fold(f32::MAX, f32::min);
+            }
+        }
+        cost
+    };
+    banks
+        .into_iter()
+        .map(|x| (cost(&x), x))
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|x| x.1)
+        .unwrap_or_default()
+}
+
+/// 将区域内调色板中没有的颜色映射为最接近的调色板颜色，区域外的像素保持不变
+///
+/// `bank_size` 为 `Some(16)` 时按 4bpp 图块处理，调色板的颜色 0 为所有调色板共用的透明色
+pub fn remap_to_palette(
+    img: &RgbImage,
+    regions: &[TileRegion],
+    palette: &[GBAColor],
+    bank_size: Option<usize>,
+    matching: ColorMatching,
+) -> RemapResult {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let mut result = RemapResult {
+        image: img.clone(),
+        remapped: vec![false; width * height],
+    };
+    if matching == ColorMatching::Exact || palette.is_empty() {
+        return result;
+    }
+
+    let palette = palette
+        .iter()
+        .map(|color| {
+            let rgb: Rgb<u8> = (*color).into();
+            PaletteColor {
+                color: *color,
+                rgb,
+                lab: rgb_to_lab(rgb_f32(&rgb)),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // 每个像素可以使用的调色板索引，区域外为 None
+    let (tiles_x, tiles_y) = (width.div_ceil(8), height.div_ceil(8));
+    let mut candidates: Vec<Option<Vec<usize>>> = vec![None; tiles_x * tiles_y];
+    for region in regions {
+        for ty in region.y / 8..(region.y + region.height) / 8 {
+            for tx in region.x / 8..(region.x + region.width) / 8 {
+                if tx < tiles_x && ty < tiles_y && candidates[ty * tiles_x + tx].is_none() {
+                    candidates[ty * tiles_x + tx] =
+                        Some(tile_candidates(img, &palette, bank_size, tx, ty));
+                }
+            }
+        }
+    }
+
+    let mut errors = vec![[0f32; 3]; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let Some(candidates) = &candidates[(y / 8) * tiles_x + x / 8] else {
+                continue;
+            };
+            let p = img.get_pixel(x as _, y as _);
+            let color = GBAColor::from(p);
+            if candidates.iter().any(|i| palette[*i].color == color) {
+                continue;
+            }
+
+            let mut target = rgb_f32(p);
+            match matching {
+                ColorMatching::Ordered => {
+                    let offset = (BAYER_4X4[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5;
+                    target = target.map(|c| c + offset * ORDERED_SPREAD);
+                }
+                ColorMatching::ErrorDiffusion => {
+                    let error = errors[y * width + x];
+                    target = [0, 1, 2].map(|c| target[c] + error[c]);
+                }
+                _ => {}
+            }
+
+            let lab = rgb_to_lab(target);
+            let nearest = candidates
+                .iter()
+                .map(|i| &palette[*i])
+                .min_by(|a, b| distance(&lab, &a.lab).total_cmp(&distance(&lab, &b.lab)))
+                .unwrap();
+            result.image.put_pixel(x as _, y as _, nearest.rgb);
+            result.remapped[y * width + x] = true;
+
+            if matching == ColorMatching::ErrorDiffusion {
+                let error = [0, 1, 2].map(|c| target[c] - nearest.rgb.0[c] as f32);
+                let mut spread = |dx: isize, dy: usize, weight: f32| {
+                    let nx = x as isize + dx;
+                    if (0..width as isize).contains(&nx) && y + dy < height {
+                        let e = &mut errors[(y + dy) * width + nx as usize];
+                        for c in 0..3 {
+                            e[c] += error[c] * weight;
+                        }
+                    }
+                };
+                spread(1, 0, 7.0 / 16.0);
+                spread(-1, 1, 3.0 / 16.0);
+                spread(0, 1, 5.0 / 16.0);
+                spread(1, 1, 1.0 / 16.0);
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(r: u8, g: u8, b: u8) -> GBAColor {
+        GBAColor::from(&Rgb([r, g, b]))
+    }
+
+    fn whole(img: &RgbImage) -> Vec<TileRegion> {
+        vec![TileRegion {
+            x: 0,
+            y: 0,
+            width: img.width() as usize,
+            height: img.height() as usize,
+        }]
+    }
+
+    #[test]
+    fn exact_colors_keep_their_index() {
+        let palette = [color(0, 0, 0), color(248, 0, 0), color(0, 248, 0)];
+        let img = RgbImage::from_fn(8, 8, |x, _| palette[x as usize % 3].into());
+        for matching in [
+            ColorMatching::Nearest,
+            ColorMatching::Ordered,
+            ColorMatching::ErrorDiffusion,
+        ] {
+            let result = remap_to_palette(&img, &whole(&img), &palette, None, matching);
+            assert_eq!(result.remapped_count(), 0);
+            for (x, _, p) in result.image.enumerate_pixels() {
+                assert_eq!(GBAColor::from(p), palette[x as usize % 3]);
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_picks_lab_neighbour() {
+        let palette = [
+            color(0, 0, 0),
+            color(248, 0, 0),
+            color(0, 0, 248),
+            color(248, 248, 248),
+        ];
+        let mut img = RgbImage::from_pixel(8, 8, palette[0].into());
+        img.put_pixel(0, 0, Rgb([220, 30, 20]));
+        img.put_pixel(1, 0, Rgb([20, 40, 210]));
+        img.put_pixel(2, 0, Rgb([230, 235, 225]));
+        img.put_pixel(3, 0, Rgb([30, 25, 30]));
+        let result = remap_to_palette(&img, &whole(&img), &palette, None, ColorMatching::Nearest);
+        let expected = [1, 2, 3, 0];
+        for (x, index) in expected.into_iter().enumerate() {
+            assert_eq!(
+                GBAColor::from(result.image.get_pixel(x as _, 0)),
+                palette[index],
+                "pixel {x}"
+            );
+        }
+        assert_eq!(result.remapped_count(), 4);
+    }
+
+    #[test]
+    fn outside_regions_and_exact_matching_are_untouched() {
+        let palette = [color(0, 0, 0), color(248, 0, 0)];
+        let img = RgbImage::from_pixel(16, 8, Rgb([100, 150, 200]));
+        let result = remap_to_palette(&img, &whole(&img), &palette, None, ColorMatching::Exact);
+        assert_eq!(result.image, img);
+        assert_eq!(result.remapped_count(), 0);
+
+        let left = [TileRegion {
+            x: 0,
+            y: 0,
+            width: 8,
+            height: 8,
+        }];
+        let result = remap_to_palette(&img, &left, &palette, None, ColorMatching::Nearest);
+        assert_eq!(result.remapped_count(), 64);
+        for (x, y, p) in result.image.enumerate_pixels() {
+            assert_eq!(x < 8, p != img.get_pixel(x, y), "pixel ({x},{y})");
+        }
+    }
+
+    #[test]
+    fn tile_stays_within_one_bank() {
+        let mut palette = vec![color(0, 0, 0); 32];
+        palette[1] = color(248, 0, 0);
+        palette[2] = color(0, 248, 0);
+        palette[17] = color(0, 0, 248);
+        palette[18] = color(128, 0, 0);
+        let bank1 = [0, 17, 18].map(|i| palette[i]);
+
+        // 图块大部分是第 1 组的蓝色，接近红色的像素只能用第 1 组的暗红色，不能借用第 0 组的红色
+        let mut img = RgbImage::from_pixel(8, 8, palette[17].into());
+        img.put_pixel(3, 3, Rgb([240, 16, 16]));
+        img.put_pixel(4, 4, Rgb([8, 8, 8]));
+        let result = remap_to_palette(
+            &img,
+            &whole(&img),
+            &palette,
+            Some(16),
+            ColorMatching::Nearest,
+        );
+        assert_eq!(GBAColor::from(result.image.get_pixel(3, 3)), palette[18]);
+        for p in result.image.pixels() {
+            assert!(bank1.contains(&GBAColor::from(p)), "{p:?} is not in bank 1");
+        }
+    }
+
+    #[test]
+    fn report_marks_remapped_pixels() {
+        let palette = [color(0, 0, 0), color(248, 248, 248)];
+        let mut img = RgbImage::from_pixel(8, 8, palette[1].into());
+        img.put_pixel(2, 5, Rgb([200, 10, 10]));
+        img.put_pixel(7, 0, Rgb([10, 10, 200]));
+        let result = remap_to_palette(&img, &whole(&img), &palette, None, ColorMatching::Nearest);
+        let report = result.report(&img);
+        let magenta = Rgb([0xFF, 0x00, 0xFF]);
+        for (x, y, p) in report.enumerate_pixels() {
+            let remapped = (x, y) == (2, 5) || (x, y) == (7, 0);
+            assert_eq!(result.remapped[(y * 8 + x) as usize], remapped);
+            assert_eq!(*p == magenta, remapped, "pixel ({x},{y})");
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! 将 TrueType/OpenType 矢量字体或 BDF 点阵字体栅格化为 `.sfont` 字形集
+//!
+//! 矢量字体按覆盖率阈值二值化，BDF 字体的 `ENCODING` 视为 Unicode 码位。
+//! 字形的基线放在单元格顶部向下 `ascent` 像素处，可以用基线偏移上下调整，超出单元格的部分会被裁掉。
+
+use std::{collections::BTreeSet, path::Path};
+
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use anyhow::{bail, ensure, Context};
+
+use super::{
+    sfont::{Sfont, SfontGlyph, PIXEL_FOREGROUND},
+    tbl::parse_hex_bytes,
+};
+
+#[derive(Debug, Clone)]
+pub struct RasterizeOptions {
+    pub cell_width: u8,
+    pub cell_height: u8,
+    /// 矢量字体的像素大小，默认与单元格高度相同
+    pub font_size: Option<f32>,
+    /// 基线偏移，正数向下
+    pub baseline_shift: i32,
+    /// 水平偏移，正数向右
+    pub x_shift: i32,
+    /// 固定字宽，为 `None` 时使用字体自身的字宽
+    pub fixed_width: Option<u8>,
+    /// 矢量字体像素覆盖率达到此值时视为有笔画
+    pub threshold: f32,
+    pub shadow: bool,
+    pub bold: bool,
+    /// 只栅格化这些字符，为 `None` 时栅格化字体中的所有字符
+    pub chars: Option<BTreeSet<char>>,
+}
+
+impl RasterizeOptions {
+    pub fn new(cell_width: u8, cell_height: u8) -> Self {
+        Self {
+            cell_width,
+            cell_height,
+            font_size: None,
+            baseline_shift: 0,
+            x_shift: 0,
+            fixed_width: None,
+            threshold: 0.5,
+            shadow: false,
+            bold: false,
+            chars: None,
+        }
+    }
+
+    pub fn with_font_size(mut self, font_size: f32) -> Self {
+        self.font_size = Some(font_size);
+        self
+    }
+
+    pub fn with_baseline_shift(mut self, baseline_shift: i32) -> Self {
+        self.baseline_shift = baseline_shift;
+        self
+    }
+
+    pub fn with_x_shift(mut self, x_shift: i32) -> Self {
+        self.x_shift = x_shift;
+        self
+    }
+
+    pub fn with_fixed_width(mut self, fixed_width: u8) -> Self {
+        self.fixed_width = Some(fixed_width);
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// 添加阴影，`bold` 为真时使用加粗阴影
+    pub fn with_shadow(mut self, bold: bool) -> Self {
+        self.shadow = true;
+        self.bold = bold;
+        self
+    }
+
+    pub fn with_chars(mut self, chars: BTreeSet<char>) -> Self {
+        self.chars = Some(chars);
+        self
+    }
+
+    fn wants(&self, c: char) -> bool {
+        !c.is_control() && self.chars.as_ref().is_none_or(|x| x.contains(&c))
+    }
+
+    fn glyph_width(&self, advance: i32) -> u8 {
+        self.fixed_width
+            .unwrap_or_else(|| advance.clamp(0, self.cell_width as i32) as u8)
+    }
+
+    fn put_pixel(&self, glyph: &mut SfontGlyph, x: i32, y: i32) {
+        let (x, y) = (x + self.x_shift, y + self.baseline_shift);
+        if (0..self.cell_width as i32).contains(&x) && (0..self.cell_height as i32).contains(&y) {
+            glyph.pixels[y as usize * self.cell_width as usize + x as usize] = PIXEL_FOREGROUND;
+        }
+    }
+}
+
+fn rasterize_outline_font(data: Vec<u8>, options: &RasterizeOptions) -> anyhow::Result<Sfont> {
+    let font = FontVec::try_from_vec(data).context("无法解析矢量字体")?;
+    let scale = PxScale::from(options.font_size.unwrap_or(options.cell_height as f32));
+    let scaled = font.as_scaled(scale);
+    let ascent = scaled.ascent().round() as i32;
+
+    let mut sfont = Sfont::new(options.cell_width, options.cell_height);
+    let mut glyph_ids = font
+        .codepoint_ids()
+        .filter(|(_, c)| options.wants(*c))
+        .collect::<Vec<_>>();
+    glyph_ids.sort_by_key(|x| x.1);
+    glyph_ids.dedup_by_key(|x| x.1);
+
+    for (glyph_id, c) in glyph_ids {
+        let advance = scaled.h_advance(glyph_id).round() as i32;
+        let mut glyph = SfontGlyph::new(
+            c as u32,
+            options.glyph_width(advance),
+            options.cell_width,
+            options.cell_height,
+        );
+        let positioned =
+            glyph_id.with_scale_and_position(scale, ab_glyph::point(0.0, ascent as f32));
+        if let Some(outlined) = font.outline_glyph(positioned) {
+            let bounds = outlined.px_bounds();
+            let mut covered = Vec::new();
+            outlined.draw(|x, y, coverage| {
+                if coverage >= options.threshold {
+                    covered.push((
+                        bounds.min.x as i32 + x as i32,
+                        bounds.min.y as i32 + y as i32,
+                    ));
+                }
+            });
+            for (x, y) in covered {
+                options.put_pixel(&mut glyph, x, y);
+            }
+        }
+        sfont.glyphs.push(glyph);
+    }
+
+    Ok(sfont)
+}
+
+fn parse_numbers(line: &str, count: usize) -> anyhow::Result<Vec<i32>> {
+    let numbers = line
+        .split_whitespace()
+        .skip(1)
+        .take(count)
+        .map(|x| x.parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("无法解析 BDF 行 {line:?}"))?;
+    ensure!(numbers.len() == count, "BDF 行 {line:?} 缺少参数");
+    Ok(numbers)
+}
+
+fn rasterize_bdf_font(content: &str, options: &RasterizeOptions) -> anyhow::Result<Sfont> {
+    let mut sfont = Sfont::new(options.cell_width, options.cell_height);
+    let mut ascent = None;
+    let mut bounding_box_ascent = 0;
+
+    let mut lines = content.lines().map(|x| x.trim());
+    while let Some(line) = lines.next() {
+        let keyword = line.split_whitespace().next().unwrap_or_default();
+        match keyword {
+            "FONTBOUNDINGBOX" => {
+                let numbers = parse_numbers(line, 4)?;
+                bounding_box_ascent = numbers[1] + numbers[3];
+            }
+            "FONT_ASCENT" => ascent = Some(parse_numbers(line, 1)?[0]),
+            "STARTCHAR" => {
+                let ascent = ascent.unwrap_or(bounding_box_ascent);
+                let mut code = None;
+                let mut advance = 0;
+                let mut bbx = [0; 4];
+                let mut glyph = None;
+                while let Some(line) = lines.next() {
+                    let keyword = line.split_whitespace().next().unwrap_or_default();
+                    match keyword {
+                        "ENCODING" => code = Some(parse_numbers(line, 1)?[0]),
+                        "DWIDTH" => advance = parse_numbers(line, 1)?[0],
+                        "BBX" => bbx.copy_from_slice(&parse_numbers(line, 4)?),
+                        "BITMAP" => {
+                            let c = code
+                                .and_then(|x| u32::try_from(x).ok())
+                                .and_then(char::from_u32);
+                            let [width, height, x_offset, y_offset] = bbx;
+                            let mut current = c.filter(|&c| options.wants(c)).map(|c| {
+                                SfontGlyph::new(
+                                    c as u32,
+                                    options.glyph_width(advance),
+                                    options.cell_width,
+                                    options.cell_height,
+                                )
+                            });
+                            let top = ascent - (y_offset + height);
+                            for row in 0..height {
+                                let Some(hex) = lines.next() else {
+                                    bail!("BDF 字符 {code:?} 的点阵数据不完整");
+                                };
+                                let Some(current) = current.as_mut() else {
+                                    continue;
+                                };
+                                let bits = parse_hex_bytes(hex)
+                                    .with_context(|| format!("BDF 字符 {code:?} 的点阵数据无效"))?;
+                                for col in 0..width {
+                                    let byte = bits.get(col as usize / 8).copied().unwrap_or(0);
+                                    if byte & (0x80 >> (col % 8)) != 0 {
+                                        options.put_pixel(current, x_offset + col, top + row);
+                                    }
+                                }
+                            }
+                            glyph = current;
+                        }
+                        "ENDCHAR" => break,
+                        _ => {}
+                    }
+                }
+                if let Some(glyph) = glyph {
+                    sfont.glyphs.push(glyph);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    sfont.glyphs.sort_by_key(|x| x.code);
+    sfont.glyphs.dedup_by_key(|x| x.code);
+    Ok(sfont)
+}
+
+/// 栅格化字体文件，根据扩展名区分 BDF 与 TrueType/OpenType 字体
+pub fn rasterize_font(path: impl AsRef<Path>, options: &RasterizeOptions) -> anyhow::Result<Sfont> {
+    let path = path.as_ref();
+    ensure!(
+        options.cell_width > 0 && options.cell_height > 0,
+        "单元格大小不能为 0"
+    );
+    let data = std::fs::read(path).with_context(|| format!("无法读取字体 {}", path.display()))?;
+    let is_bdf = path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("bdf"));
+    let mut sfont = if is_bdf {
+        let content = String::from_utf8_lossy(&data);
+        rasterize_bdf_font(&content, options)
+    } else {
+        rasterize_outline_font(data, options)
+    }
+    .with_context(|| format!("无法栅格化字体 {}", path.display()))?;
+
+    ensure!(
+        !sfont.glyphs.is_empty(),
+        "字体 {} 中没有需要的字符",
+        path.display()
+    );
+    if options.shadow {
+        sfont.add_shadow(options.bold);
+    }
+    Ok(sfont)
+}
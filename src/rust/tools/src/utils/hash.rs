@@ -0,0 +1,27 @@
+//! 计算文件的 CRC32、MD5 和 SHA-1，用于发布清单和校验 ROM
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileHashes {
+    pub size: u64,
+    pub crc32: String,
+    pub md5: String,
+    pub sha1: String,
+}
+
+impl FileHashes {
+    pub fn new(data: &[u8]) -> Self {
+        Self {
+            size: data.len() as u64,
+            crc32: format!("{:08x}", crc32fast::hash(data)),
+            md5: format!("{:x}", md5::compute(data)),
+            sha1: format!("{:x}", Sha1::digest(data)),
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        Ok(Self::new(&std::fs::read(path)?))
+    }
+}
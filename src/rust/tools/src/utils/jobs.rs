@@ -0,0 +1,184 @@
+//! 并行执行一组任务，限制同时运行的任务数量，并汇总所有失败的任务
+//!
+//! 每个任务都有一个名称（通常是归档文件名），任务失败或崩溃都不会中断其他任务，
+//! 全部结束后打印失败的任务及原因，只要有任务失败就返回错误。
+
+use std::{collections::VecDeque, process::Command, sync::Mutex, thread::available_parallelism};
+
+use anyhow::*;
+
+type Job<'a> = Box<dyn FnOnce() -> anyhow::Result<()> + Send + 'a>;
+
+pub struct JobRunner<'a> {
+    name: String,
+    concurrency: usize,
+    jobs: Vec<(String, Job<'a>)>,
+}
+
+impl<'a> JobRunner<'a> {
+    /// `name` 用于进度和汇总输出，默认同时运行的任务数为 CPU 线程数
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            concurrency: available_parallelism().map_or(4, |x| x.get()),
+            jobs: Vec::new(),
+        }
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn add(
+        &mut self,
+        label: impl Into<String>,
+        job: impl FnOnce() -> anyhow::Result<()> + Send + 'a,
+    ) {
+        self.jobs.push((label.into(), Box::new(job)));
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// 执行所有任务，有任务失败时在全部结束后返回错误
+    pub fn run(self) -> anyhow::Result<()> {
+        let total = self.jobs.len();
+        if total == 0 {
+            return Ok(());
+        }
+        let queue = Mutex::new(self.jobs.into_iter().collect::<VecDeque<_>>());
+        let finished = Mutex::new(0usize);
+        let failures = Mutex::new(Vec::new());
+
+        std::thread::scope(|s| {
+            for _ in 0..self.concurrency.min(total) {
+                s.spawn(|| loop {
+                    let Some((label, job)) = queue.lock().unwrap().pop_front() else {
+                        break;
+                    };
+                    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job))
+                        .unwrap_or_else(|panic| {
+                            let message = panic
+                                .downcast_ref::<&str>()
+                                .map(|x| x.to_string())
+                                .or_else(|| panic.downcast_ref::<String>().cloned())
+                                .unwrap_or_default();
+                            Err(anyhow!("任务崩溃：{message}"))
+                        });
+
+                    let mut finished = finished.lock().unwrap();
+                    *finished += 1;
+                    match result {
+                        Result::Ok(()) => println!("[{}/{total}] {label}", *finished),
+                        Err(err) => {
+                            eprintln!("[{}/{total}] {label} 失败：{err:#}", *finished);
+                            failures.lock().unwrap().push((label, err));
+                        }
+                    }
+                });
+            }
+        });
+
+        let failures = failures.into_inner().unwrap();
+        if failures.is_empty() {
+            println!("{}：{total} 个任务全部完成", self.name);
+            return Ok(());
+        }
+        eprintln!(
+            "{}：{total} 个任务中有 {} 个失败：",
+            self.name,
+            failures.len()
+        );
+        for (label, err) in &failures {
+            eprintln!("  - {label}：{err:#}");
+        }
+        bail!(
+            "{}：{total} 个任务中有 {} 个失败：{}",
+            self.name,
+            failures.len(),
+            failures
+                .iter()
+                .map(|x| x.0.as_str())
+                .collect::<Vec<_>>()
+                .join("、")
+        )
+    }
+}
+
+/// 运行命令并等待结束，命令无法启动或者退出码不为 0 时返回错误
+pub fn run_command(command: &mut Command) -> anyhow::Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command
+        .status()
+        .with_context(|| format!("无法运行 {program}"))?;
+    ensure!(status.success(), "{program} 运行失败，{status}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    #[test]
+    fn failures_are_collected_and_concurrency_is_bounded() {
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let finished = AtomicUsize::new(0);
+        let mut runner = JobRunner::new("测试").with_concurrency(3);
+        for i in 0..12 {
+            let (running, max_running, finished) = (&running, &max_running, &finished);
+            runner.add(format!("job-{i}"), move || {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                finished.fetch_add(1, Ordering::SeqCst);
+                match i % 4 {
+                    1 => bail!("任务 {i} 失败"),
+                    3 => panic!("任务 {i} 崩溃"),
+                    _ => Ok(()),
+                }
+            });
+        }
+        assert_eq!(runner.len(), 12);
+
+        let err = runner.run().unwrap_err().to_string();
+        let mut labels = err
+            .rsplit_once('：')
+            .unwrap()
+            .1
+            .split('、')
+            .collect::<Vec<_>>();
+        labels.sort_by_key(|x| x[4..].parse::<usize>().unwrap());
+        assert_eq!(
+            labels,
+            ["job-1", "job-3", "job-5", "job-7", "job-9", "job-11"],
+            "{err}"
+        );
+        assert!(err.contains("12 个任务中有 6 个失败"), "{err}");
+        // 失败的任务不会中断其他任务
+        assert_eq!(finished.into_inner(), 12);
+        let max_running = max_running.into_inner();
+        assert!((1..=3).contains(&max_running), "{max_running}");
+    }
+
+    #[test]
+    fn succeeding_jobs_return_ok() {
+        let mut runner = JobRunner::new("测试").with_concurrency(2);
+        for i in 0..5 {
+            runner.add(format!("job-{i}"), || Ok(()));
+        }
+        assert!(runner.run().is_ok());
+        assert!(JobRunner::new("测试").run().is_ok());
+    }
+}
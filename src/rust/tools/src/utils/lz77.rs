@@ -0,0 +1,255 @@
+//! GBA/NDS BIOS 兼容的 LZ77（类型 `0x10`）压缩与解压缩
+//!
+//! 数据格式为 4 字节头部（低 8 位为 `0x10`，高 24 位为解压后大小），
+//! 之后每 8 个块前有一个标志字节，从最高位开始，为 1 时表示该块为回溯引用。
+
+use anyhow::{ensure, Context};
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 0xF + MIN_MATCH;
+const MAX_DISP: usize = 0x1000;
+// BIOS 的 VRAM 解压缩函数按 16 位写入，回溯距离为 1 时会读到尚未写入的数据
+const MIN_DISP: usize = 2;
+const MAX_CHAIN: usize = 256;
+
+/// 判断数据是否拥有 LZ77 压缩头部
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.len() >= 4 && data[0] == 0x10
+}
+
+/// 读取 LZ77 头部中记录的解压后大小
+pub fn decompressed_size(data: &[u8]) -> anyhow::Result<usize> {
+    ensure!(is_compressed(data), "数据不是 LZ77 压缩格式");
+    Ok(u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize >> 8)
+}
+
+pub fn decompress(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let size = decompressed_size(data)?;
+    let mut output = Vec::with_capacity(size);
+    let mut pos = 4;
+
+    while output.len() < size {
+        let flags = *data.get(pos).context("LZ77 数据提前结束")?;
+        pos += 1;
+        for bit in (0..8).rev() {
+            if output.len() >= size {
+                break;
+            }
+            if flags & (1 << bit) == 0 {
+                output.push(*data.get(pos).context("LZ77 数据提前结束")?);
+                pos += 1;
+            } else {
+                let b0 = *data.get(pos).context("LZ77 数据提前结束")? as usize;
+                let b1 = *data.get(pos + 1).context("LZ77 数据提前结束")? as usize;
+                pos += 2;
+                let len = (b0 >> 4) + MIN_MATCH;
+                let disp = (((b0 & 0xF) << 8) | b1) + 1;
+                ensure!(
+                    disp <= output.len(),
+                    "LZ77 回溯距离 {} 超出已解压数据长度 {}",
+                    disp,
+                    output.len()
+                );
+                let start = output.len() - disp;
+                for i in 0..len.min(size - output.len()) {
+                    output.push(output[start + i]);
+                }
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+fn hash3(data: &[u8], pos: usize) -> usize {
+    ((data[pos] as usize) << 8 ^ (data[pos + 1] as usize) << 4 ^ data[pos + 2] as usize) & 0xFFFF
+}
+
+/// 使用贪婪匹配压缩数据，输出可安全地直接解压至显存
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() / 2 + 8);
+    output.extend_from_slice(&(((data.len() as u32) << 8) | 0x10).to_le_bytes());
+
+    let mut head = vec![usize::MAX; 0x10000];
+    let mut prev = vec![usize::MAX; data.len()];
+    let insert = |head: &mut Vec<usize>, prev: &mut Vec<usize>, pos: usize| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash3(data, pos);
+            prev[pos] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let flag_pos = output.len();
+        output.push(0);
+        for bit in (0..8).rev() {
+            if pos >= data.len() {
+                break;
+            }
+
+            let mut best_len = 0;
+            let mut best_disp = 0;
+            if pos + MIN_MATCH <= data.len() {
+                let max_len = MAX_MATCH.min(data.len() - pos);
+                let mut candidate = head[hash3(data, pos)];
+                let mut chain = 0;
+                while candidate != usize::MAX && chain < MAX_CHAIN {
+                    let disp = pos - candidate;
+                    if disp > MAX_DISP {
+                        break;
+                    }
+                    if disp >= MIN_DISP {
+                        let len = (0..max_len)
+                            .take_while(|&i| data[candidate + i] == data[pos + i])
+                            .count();
+                        if len > best_len {
+                            best_len = len;
+                            best_disp = disp;
+                            if len == max_len {
+                                break;
+                            }
+                        }
+                    }
+                    candidate = prev[candidate];
+                    chain += 1;
+                }
+            }
+
+            if best_len >= MIN_MATCH {
+                output[flag_pos] |= 1 << bit;
+                let len = best_len - MIN_MATCH;
+                let disp = best_disp - 1;
+                output.push(((len << 4) | (disp >> 8)) as u8);
+                output.push((disp & 0xFF) as u8);
+                for i in pos..pos + best_len {
+                    insert(&mut head, &mut prev, i);
+                }
+                pos += best_len;
+            } else {
+                output.push(data[pos]);
+                insert(&mut head, &mut prev, pos);
+                pos += 1;
+            }
+        }
+    }
+
+    while output.len() % 4 != 0 {
+        output.push(0);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 生成可复现的伪随机数据，`zero_ratio` 为每 16 字节中为 0 的字节数
+    fn sample_data(len: usize, zero_ratio: u32) -> Vec<u8> {
+        let mut seed = 0x1234_5678u32;
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                if (seed >> 16) % 16 < zero_ratio {
+                    0
+                } else {
+                    (seed >> 8) as u8
+                }
+            })
+            .collect()
+    }
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let compressed = compress(data);
+        assert!(is_compressed(&compressed));
+        assert_eq!(compressed.len() % 4, 0);
+        assert_eq!(decompressed_size(&compressed).unwrap(), data.len());
+        assert_eq!(decompress(&compressed).unwrap(), data);
+        compressed
+    }
+
+    /// 依次返回压缩数据中每个回溯引用的距离
+    fn back_references(compressed: &[u8]) -> Vec<usize> {
+        let size = decompressed_size(compressed).unwrap();
+        let mut refs = Vec::new();
+        let (mut pos, mut written) = (4, 0);
+        while written < size {
+            let flags = compressed[pos];
+            pos += 1;
+            for bit in (0..8).rev() {
+                if written >= size {
+                    break;
+                }
+                if flags & (1 << bit) == 0 {
+                    pos += 1;
+                    written += 1;
+                } else {
+                    let (b0, b1) = (compressed[pos] as usize, compressed[pos + 1] as usize);
+                    pos += 2;
+                    refs.push((((b0 & 0xF) << 8) | b1) + 1);
+                    written += (b0 >> 4) + MIN_MATCH;
+                }
+            }
+        }
+        refs
+    }
+
+    #[test]
+    fn round_trip_small_inputs() {
+        for len in 0..=40 {
+            round_trip(&sample_data(len, 8));
+        }
+    }
+
+    #[test]
+    fn round_trip_random_data() {
+        round_trip(&sample_data(0x10000, 0));
+    }
+
+    #[test]
+    fn round_trip_repetitive_data() {
+        let data = sample_data(0x8000, 14);
+        let compressed = round_trip(&data);
+        assert!(compressed.len() < data.len() / 2);
+
+        let data = vec![0xAB; 0x2345];
+        let compressed = round_trip(&data);
+        assert!(compressed.len() < data.len() / 4);
+    }
+
+    #[test]
+    fn round_trip_beyond_window() {
+        // 重复的内容相隔超过回溯窗口，不能被引用
+        let block = sample_data(MAX_DISP + 0x100, 0);
+        let data = [block.clone(), block].concat();
+        round_trip(&data);
+        let data = [sample_data(MAX_DISP, 0), sample_data(0x40, 0)].concat();
+        round_trip(&data);
+    }
+
+    #[test]
+    fn back_references_are_safe_for_vram() {
+        for data in [
+            vec![0; 0x1000],
+            sample_data(0x4000, 12),
+            sample_data(0x1001, 15),
+        ] {
+            let refs = back_references(&compress(&data));
+            assert!(!refs.is_empty());
+            assert!(refs
+                .iter()
+                .all(|&disp| (MIN_DISP..=MAX_DISP).contains(&disp)));
+        }
+    }
+
+    #[test]
+    fn decompress_rejects_invalid_data() {
+        assert!(decompress(&[0x11, 0, 0, 0]).is_err());
+        // 回溯距离超出已解压数据
+        assert!(decompress(&[0x10, 4, 0, 0, 0x80, 0x10, 0x05]).is_err());
+        // 数据提前结束
+        assert!(decompress(&[0x10, 8, 0, 0, 0x00, 1, 2]).is_err());
+    }
+}
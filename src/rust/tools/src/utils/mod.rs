@@ -1,126 +1,178 @@
 use std::{
+    collections::HashMap,
     path::{Path, PathBuf},
     process::Command,
 };
 
-use anyhow::ensure;
+use anyhow::{ensure, Context};
 
+pub mod build_graph;
 pub mod fs;
+pub mod jobs;
 pub mod path;
+pub mod project;
 pub mod tile_img;
 pub mod buildin_palette;
-
-pub struct ToolsRunner {
-    textpet_path: PathBuf,
-    armips_path: PathBuf,
-    sfarctool_path: PathBuf,
-    sfspatcher_path: PathBuf,
-    ndstool_path: PathBuf,
-    sfont_gen_path: PathBuf,
-    textpet_checker_path: PathBuf,
+pub mod color_match;
+pub mod splash;
+pub mod lz77;
+pub mod hash;
+pub mod patch;
+pub mod vcdiff;
+pub mod nds_rom;
+pub mod rom_check;
+pub mod sfarc;
+pub mod sfont;
+pub mod font_raster;
+pub mod tbl;
+pub mod text_archive;
+
+/// 构建过程中调用的外部工具
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Armips,
+    Sfspatcher,
+    SfontGen,
+    TextpetChecker,
 }
 
-impl ToolsRunner {
-    pub fn new(root_path: Option<&Path>) -> anyhow::Result<Self> {
-        let root_path = root_path
-            .map(|x| x.to_path_buf())
-            .unwrap_or_else(|| std::env::current_dir().unwrap());
-        let textpet_path = root_path.join("./tools/textpet.exe");
-        let armips_path = root_path.join("./tools/armips.exe");
-        let sfarctool_path = root_path.join("./tools/sfarctool.exe");
-        let sfspatcher_path = root_path.join("./tools/sfspatcher.exe");
-        let ndstool_path = root_path.join("./tools/ndstool.exe");
-        let sfont_gen_path = root_path.join("./tools/sfont-gen.exe");
-        let textpet_checker_path = root_path.join("./tools/textpet-checker.exe");
-
-        if !textpet_path.is_file() {
-            anyhow::bail!("无法找到工具 textpet.exe，请检查本程序所在文件夹是否存在 tools 文件夹并存放了对应的工具文件");
-        }
-
-        if !armips_path.is_file() {
-            anyhow::bail!("无法找到工具 armips.exe，请检查本程序所在文件夹是否存在 tools 文件夹并存放了对应的工具文件");
-        }
-
-        if !sfarctool_path.is_file() {
-            anyhow::bail!("无法找到工具 sfarctool.exe，请检查本程序所在文件夹是否存在 tools 文件夹并存放了对应的工具文件");
-        }
-
-        if !sfspatcher_path.is_file() {
-            anyhow::bail!("无法找到工具 sfspatcher.exe，请检查本程序所在文件夹是否存在 tools 文件夹并存放了对应的工具文件");
+impl Tool {
+    pub const ALL: [Tool; 4] = [
+        Tool::Armips,
+        Tool::Sfspatcher,
+        Tool::SfontGen,
+        Tool::TextpetChecker,
+    ];
+
+    /// 工具的名称，同时也是配置文件中的键名和可执行文件名（不含扩展名）
+    pub fn name(self) -> &'static str {
+        match self {
+            Tool::Armips => "armips",
+            Tool::Sfspatcher => "sfspatcher",
+            Tool::SfontGen => "sfont-gen",
+            Tool::TextpetChecker => "textpet-checker",
         }
+    }
 
-        if !ndstool_path.is_file() {
-            anyhow::bail!("无法找到工具 ndstool.exe，请检查本程序所在文件夹是否存在 tools 文件夹并存放了对应的工具文件");
-        }
+    /// 用于指定工具路径的环境变量名，例如 `RNR2CN_SFONT_GEN`
+    pub fn env_var(self) -> String {
+        format!("RNR2CN_{}", self.name().to_uppercase().replace('-', "_"))
+    }
+}
 
-        if !sfont_gen_path.is_file() {
-            anyhow::bail!("无法找到工具 sfont-gen.exe，请检查本程序所在文件夹是否存在 tools 文件夹并存放了对应的工具文件");
-        }
+/// 项目配置文件名，工具路径写在其中的 `[tools]` 表里
+pub const PROJECT_CONFIG_FILE: &str = "rnr2cn.toml";
+/// 在非 Windows 系统上运行 `.exe` 工具所用的 wine 的环境变量名
+pub const WINE_ENV_VAR: &str = "RNR2CN_WINE";
+
+/// 外部工具的启动器
+///
+/// 工具在第一次使用时才会查找，依次尝试：配置文件 `rnr2cn.toml` 的 `[tools]` 表、
+/// 环境变量（见 [`Tool::env_var`]）、项目的 `tools` 文件夹、`PATH`。
+/// 在非 Windows 系统上，`.exe` 工具会通过 wine 运行，wine 的路径可以写在 `[tools]` 表的 `wine` 键、
+/// 环境变量 `RNR2CN_WINE` 中，或者从 `PATH` 中查找。
+pub struct ToolsRunner {
+    root_path: PathBuf,
+    configured: HashMap<String, PathBuf>,
+}
 
-        if !textpet_checker_path.is_file() {
-            anyhow::bail!("无法找到工具 textpet-checker.exe，请检查本程序所在文件夹是否存在 tools 文件夹并存放了对应的工具文件");
+impl ToolsRunner {
+    pub fn from_config(root_path: impl AsRef<Path>, config: &project::ProjectConfig) -> Self {
+        Self {
+            root_path: root_path.as_ref().to_path_buf(),
+            configured: config.tools.clone(),
         }
-
-        Ok(Self {
-            textpet_path,
-            armips_path,
-            sfarctool_path,
-            sfspatcher_path,
-            ndstool_path,
-            sfont_gen_path,
-            textpet_checker_path,
-        })
     }
 
-    pub fn textpet(&self) -> Command {
-        Command::new(&self.textpet_path)
+    fn find_configured(&self, name: &str, env_var: &str) -> Option<PathBuf> {
+        self.configured
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var_os(env_var).map(PathBuf::from))
+            .map(|tool_path| {
+                // 相对路径优先相对于项目根目录查找
+                let joined = self.root_path.join(&tool_path);
+                if joined.is_file() {
+                    joined
+                } else {
+                    path::locate_path(tool_path)
+                }
+            })
     }
 
-    pub fn armips(&self) -> Command {
-        Command::new(&self.armips_path)
-    }
+    /// 查找工具的可执行文件
+    pub fn locate(&self, tool: Tool) -> anyhow::Result<PathBuf> {
+        if let Some(tool_path) = self.find_configured(tool.name(), &tool.env_var()) {
+            ensure!(
+                tool_path.is_file(),
+                "工具 {} 的路径 {} 不存在，请检查配置文件 {PROJECT_CONFIG_FILE} 或环境变量 {}",
+                tool.name(),
+                tool_path.display(),
+                tool.env_var()
+            );
+            return Ok(tool_path);
+        }
 
-    pub fn sfarctool(&self) -> Command {
-        Command::new(&self.sfarctool_path)
+        // tools/bin/<系统名> 下为当前系统编译的工具优先于 tools 下的工具
+        let tools_dir = self.root_path.join("tools");
+        let native_dir = tools_dir.join("bin").join(std::env::consts::OS);
+        [
+            native_dir.join(tool.name()),
+            native_dir.join(format!("{}.exe", tool.name())),
+            tools_dir.join(tool.name()),
+            tools_dir.join(format!("{}.exe", tool.name())),
+        ]
+        .into_iter()
+        .chain([
+            path::locate_path(tool.name()),
+            path::locate_path(format!("{}.exe", tool.name())),
+        ])
+        .find(|x| x.is_file())
+        .with_context(|| {
+            format!(
+                "无法找到工具 {name}，请在配置文件 {PROJECT_CONFIG_FILE} 的 [tools] 表中设置 {name} 的路径、设置环境变量 {env_var}，或者将其放入 tools 文件夹或 PATH 中",
+                name = tool.name(),
+                env_var = tool.env_var()
+            )
+        })
     }
 
-    pub fn sfspatcher(&self) -> Command {
-        Command::new(&self.sfspatcher_path)
+    /// 创建运行工具的命令，找不到工具时才会报错
+    pub fn command(&self, tool: Tool) -> anyhow::Result<Command> {
+        let tool_path = self.locate(tool)?;
+        let is_exe = tool_path
+            .extension()
+            .is_some_and(|x| x.eq_ignore_ascii_case("exe"));
+        if cfg!(not(target_os = "windows")) && is_exe {
+            let wine_path = self
+                .find_configured("wine", WINE_ENV_VAR)
+                .unwrap_or_else(|| path::locate_path("wine"));
+            ensure!(
+                wine_path.is_file(),
+                "工具 {} 是 Windows 程序，需要安装 wine，或者在配置文件 {PROJECT_CONFIG_FILE} 的 [tools] 表中设置 wine 的路径、设置环境变量 {WINE_ENV_VAR}",
+                tool_path.display()
+            );
+            let mut command = Command::new(wine_path);
+            command.arg(tool_path);
+            Ok(command)
+        } else {
+            Ok(Command::new(tool_path))
+        }
     }
 
-    pub fn ndstool(&self) -> Command {
-        Command::new(&self.ndstool_path)
+    pub fn armips(&self) -> anyhow::Result<Command> {
+        self.command(Tool::Armips)
     }
 
-    pub fn textpet_checker(&self) -> Command {
-        Command::new(&self.textpet_checker_path)
+    pub fn sfspatcher(&self) -> anyhow::Result<Command> {
+        self.command(Tool::Sfspatcher)
     }
 
-    pub fn ndstool_extract(
-        &self,
-        nds_file: impl AsRef<Path>,
-        dir_path: impl AsRef<Path>,
-    ) -> anyhow::Result<()> {
-        let dir_path = dir_path.as_ref();
-        std::fs::create_dir_all(dir_path)?;
-
-        let mut cmd = self.ndstool();
-        cmd.arg("-x").arg(nds_file.as_ref());
-        cmd.arg("-9").arg(dir_path.join("arm9.bin"));
-        cmd.arg("-7").arg(dir_path.join("arm7.bin"));
-        cmd.arg("-y9").arg(dir_path.join("y9.bin"));
-        cmd.arg("-y7").arg(dir_path.join("y7.bin"));
-        cmd.arg("-d").arg(dir_path.join("data"));
-        cmd.arg("-y").arg(dir_path.join("overlay"));
-        cmd.arg("-t").arg(dir_path.join("banner.bin"));
-        cmd.arg("-h").arg(dir_path.join("header.bin"));
-
-        ensure!(cmd.status()?.success(), "ndstool 执行解包游戏文件失败");
-
-        Ok(())
+    pub fn textpet_checker(&self) -> anyhow::Result<Command> {
+        self.command(Tool::TextpetChecker)
     }
 
-    pub fn sfont_gen(&self) -> Command {
-        Command::new(&self.sfont_gen_path)
+    pub fn sfont_gen(&self) -> anyhow::Result<Command> {
+        self.command(Tool::SfontGen)
     }
 }
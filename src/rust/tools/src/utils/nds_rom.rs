@@ -0,0 +1,1006 @@
+//! NDS 游戏 ROM 的解包与重新打包，输出布局与 ndstool 一致
+//!
+//! 解包后的文件夹包含 `arm9.bin`、`arm7.bin`、`y9.bin`（ARM9 覆盖表）、`y7.bin`（ARM7 覆盖表）、
+//! `overlay/`（ARM9 覆盖文件）、`overlay7/`（ARM7 覆盖文件）、`data/`、`banner.bin`、`header.bin`，
+//! 以及原始的 `fnt.bin` 和 `fat.bin`。没有图标标题的 ROM 不会导出 `banner.bin`。
+//! 重新打包时 `data/` 文件夹与原版文件名表一致则原样使用文件名表和文件编号，
+//! 否则按原版的顺序重新生成文件名表；文件分配表总是重新生成，并修正头部中的偏移、大小和校验值。
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Context};
+
+pub const HEADER_SIZE: usize = 0x200;
+const HEADER_AREA_SIZE: usize = 0x4000;
+const ROM_ALIGN: usize = 0x200;
+const ARM9_FOOTER_MAGIC: u32 = 0xDEC0_0621;
+const ARM9_FOOTER_SIZE: usize = 12;
+const OVERLAY_ENTRY_SIZE: usize = 0x20;
+const FIRST_DIR_ID: u16 = 0xF000;
+/// 文件名表目录的最大嵌套层数，防止损坏的文件名表循环引用
+const MAX_DIR_DEPTH: usize = 64;
+
+fn read_u16(data: &[u8], pos: usize) -> anyhow::Result<u16> {
+    let bytes = data
+        .get(pos..pos + 2)
+        .with_context(|| format!("读取位置 {pos:#X} 超出数据范围 {:#X}", data.len()))?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> anyhow::Result<u32> {
+    let bytes = data
+        .get(pos..pos + 4)
+        .with_context(|| format!("读取位置 {pos:#X} 超出数据范围 {:#X}", data.len()))?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn write_u16(data: &mut [u8], pos: usize, value: u16) {
+    data[pos..pos + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(data: &mut [u8], pos: usize, value: u32) {
+    data[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn align(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+/// NDS 头部和安全区使用的 CRC16（多项式 0xA001，初始值 0xFFFF）
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &b in data {
+        crc ^= b as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// 头部中与文件布局有关的字段
+#[derive(Debug, Clone)]
+pub struct NdsHeader {
+    pub raw: Vec<u8>,
+}
+
+impl NdsHeader {
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        ensure!(
+            data.len() >= HEADER_SIZE,
+            "头部数据长度不足 {HEADER_SIZE} 字节"
+        );
+        Ok(Self {
+            raw: data[..HEADER_SIZE].to_vec(),
+        })
+    }
+
+    /// 头部的长度固定为 [`HEADER_SIZE`]，字段不会越界
+    fn field_u32(&self, pos: usize) -> usize {
+        u32::from_le_bytes(self.raw[pos..pos + 4].try_into().unwrap()) as usize
+    }
+
+    pub fn title(&self) -> String {
+        String::from_utf8_lossy(&self.raw[0x00..0x0C])
+            .trim_end_matches('\0')
+            .to_owned()
+    }
+
+    pub fn game_code(&self) -> String {
+        String::from_utf8_lossy(&self.raw[0x0C..0x10]).into_owned()
+    }
+
+    pub fn arm9_offset(&self) -> usize {
+        self.field_u32(0x20)
+    }
+
+    pub fn arm9_size(&self) -> usize {
+        self.field_u32(0x2C)
+    }
+
+    pub fn arm7_offset(&self) -> usize {
+        self.field_u32(0x30)
+    }
+
+    pub fn arm7_size(&self) -> usize {
+        self.field_u32(0x3C)
+    }
+
+    pub fn fnt_offset(&self) -> usize {
+        self.field_u32(0x40)
+    }
+
+    pub fn fnt_size(&self) -> usize {
+        self.field_u32(0x44)
+    }
+
+    pub fn fat_offset(&self) -> usize {
+        self.field_u32(0x48)
+    }
+
+    pub fn fat_size(&self) -> usize {
+        self.field_u32(0x4C)
+    }
+
+    pub fn arm9_overlay_offset(&self) -> usize {
+        self.field_u32(0x50)
+    }
+
+    pub fn arm9_overlay_size(&self) -> usize {
+        self.field_u32(0x54)
+    }
+
+    pub fn arm7_overlay_offset(&self) -> usize {
+        self.field_u32(0x58)
+    }
+
+    pub fn arm7_overlay_size(&self) -> usize {
+        self.field_u32(0x5C)
+    }
+
+    pub fn banner_offset(&self) -> usize {
+        self.field_u32(0x68)
+    }
+
+    /// 头部记录的实际使用的 ROM 大小，裁剪后的 ROM 通常正好是这个大小
+    pub fn rom_used_size(&self) -> usize {
+        self.field_u32(0x80)
+    }
+
+    pub fn header_crc(&self) -> u16 {
+        u16::from_le_bytes([self.raw[0x15E], self.raw[0x15F]])
+    }
+
+    pub fn calc_header_crc(&self) -> u16 {
+        crc16(&self.raw[..0x15E])
+    }
+}
+
+fn banner_size(banner: &[u8]) -> anyhow::Result<usize> {
+    Ok(match read_u16(banner, 0)? {
+        0x0002 => 0x940,
+        0x0003 => 0xA40,
+        0x0103 => 0x23C0,
+        _ => 0x840,
+    })
+}
+
+/// 覆盖表中的一项，目前只需要覆盖编号和文件编号
+#[derive(Debug, Clone, Copy)]
+struct OverlayEntry {
+    overlay_id: u32,
+    file_id: u32,
+}
+
+fn parse_overlay_table(data: &[u8]) -> anyhow::Result<Vec<OverlayEntry>> {
+    data.chunks_exact(OVERLAY_ENTRY_SIZE)
+        .map(|x| {
+            Ok(OverlayEntry {
+                overlay_id: read_u32(x, 0x00)?,
+                file_id: read_u32(x, 0x18)?,
+            })
+        })
+        .collect()
+}
+
+/// ARM9 和 ARM7 的覆盖文件分别放在 `overlay/` 和 `overlay7/` 中，文件名相同
+fn overlay_file_name(overlay_id: u32) -> String {
+    format!("overlay_{overlay_id:04}.bin")
+}
+
+/// 文件分配表中文件的起止位置
+fn fat_range(fat: &[u8], file_id: usize) -> anyhow::Result<(usize, usize)> {
+    let start = read_u32(fat, file_id * 8).with_context(|| format!("文件编号 {file_id} 越界"))?;
+    let end = read_u32(fat, file_id * 8 + 4).with_context(|| format!("文件编号 {file_id} 越界"))?;
+    Ok((start as usize, end as usize))
+}
+
+/// 读取整个 ROM 文件中指定范围的数据，超出范围时报错
+fn rom_slice(rom: &[u8], offset: usize, size: usize) -> anyhow::Result<&[u8]> {
+    rom.get(offset..offset + size).with_context(|| {
+        format!(
+            "ROM 数据范围 {:#X}..{:#X} 超出文件大小 {:#X}，文件可能已被裁剪或损坏",
+            offset,
+            offset + size,
+            rom.len()
+        )
+    })
+}
+
+/// 文件名表中的一项，路径相对于 `data/`
+enum FntEntry {
+    Dir(PathBuf),
+    File { id: usize, path: PathBuf },
+}
+
+impl FntEntry {
+    fn path(&self) -> &Path {
+        match self {
+            FntEntry::Dir(path) | FntEntry::File { path, .. } => path,
+        }
+    }
+}
+
+/// 按文件名表中的顺序递归列出目录和文件
+fn walk_fnt(
+    fnt: &[u8],
+    dir_id: u16,
+    dir_path: &Path,
+    depth: usize,
+    entries: &mut Vec<FntEntry>,
+) -> anyhow::Result<()> {
+    ensure!(dir_id >= FIRST_DIR_ID, "文件名表目录编号 {dir_id:#X} 无效");
+    ensure!(
+        depth <= MAX_DIR_DEPTH,
+        "文件名表目录嵌套超过 {MAX_DIR_DEPTH} 层，可能存在循环引用"
+    );
+    let main_entry = (dir_id - FIRST_DIR_ID) as usize * 8;
+    ensure!(
+        main_entry + 8 <= fnt.len(),
+        "文件名表目录项 {dir_id:#X} 越界"
+    );
+    let mut pos = read_u32(fnt, main_entry)? as usize;
+    let mut file_id = read_u16(fnt, main_entry + 4)? as usize;
+
+    loop {
+        let entry_type = *fnt.get(pos).context("文件名表数据提前结束")?;
+        pos += 1;
+        if entry_type == 0 {
+            break;
+        }
+        let name_len = (entry_type & 0x7F) as usize;
+        let name = fnt
+            .get(pos..pos + name_len)
+            .context("文件名表数据提前结束")?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        ensure!(
+            name != "." && name != ".." && !name.contains(['/', '\\']),
+            "文件名表中的文件名 {name:?} 无效"
+        );
+        pos += name_len;
+        let path = dir_path.join(name);
+
+        if entry_type & 0x80 != 0 {
+            let sub_dir_id = read_u16(fnt, pos).context("文件名表数据提前结束")?;
+            pos += 2;
+            entries.push(FntEntry::Dir(path.clone()));
+            walk_fnt(fnt, sub_dir_id, &path, depth + 1, entries)?;
+        } else {
+            entries.push(FntEntry::File { id: file_id, path });
+            file_id += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// 解包 ROM 文件到指定文件夹，替代 `ndstool -x`
+pub fn extract_rom(nds_file: impl AsRef<Path>, dir_path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let nds_file = nds_file.as_ref();
+    let dir_path = dir_path.as_ref();
+    let rom = std::fs::read(nds_file)
+        .with_context(|| format!("无法读取 ROM 文件 {}", nds_file.display()))?;
+    let header = NdsHeader::from_bytes(&rom)?;
+    std::fs::create_dir_all(dir_path)?;
+
+    std::fs::write(dir_path.join("header.bin"), &header.raw)?;
+
+    // ARM9 之后可能跟随 12 字节的 NitroCode 尾部信息，与 ndstool 一样一并导出
+    let mut arm9_size = header.arm9_size();
+    let footer_pos = header.arm9_offset() + arm9_size;
+    if rom.len() >= footer_pos + ARM9_FOOTER_SIZE
+        && read_u32(&rom, footer_pos)? == ARM9_FOOTER_MAGIC
+    {
+        arm9_size += ARM9_FOOTER_SIZE;
+    }
+    std::fs::write(
+        dir_path.join("arm9.bin"),
+        rom_slice(&rom, header.arm9_offset(), arm9_size)?,
+    )?;
+    std::fs::write(
+        dir_path.join("arm7.bin"),
+        rom_slice(&rom, header.arm7_offset(), header.arm7_size())?,
+    )?;
+
+    let y9 = rom_slice(
+        &rom,
+        header.arm9_overlay_offset(),
+        header.arm9_overlay_size(),
+    )?;
+    let y7 = rom_slice(
+        &rom,
+        header.arm7_overlay_offset(),
+        header.arm7_overlay_size(),
+    )?;
+    std::fs::write(dir_path.join("y9.bin"), y9)?;
+    std::fs::write(dir_path.join("y7.bin"), y7)?;
+
+    let fnt = rom_slice(&rom, header.fnt_offset(), header.fnt_size())?;
+    let fat = rom_slice(&rom, header.fat_offset(), header.fat_size())?;
+    std::fs::write(dir_path.join("fnt.bin"), fnt)?;
+    std::fs::write(dir_path.join("fat.bin"), fat)?;
+
+    for (table, overlay_dir) in [(y9, "overlay"), (y7, "overlay7")] {
+        let overlay_dir = dir_path.join(overlay_dir);
+        std::fs::create_dir_all(&overlay_dir)?;
+        for entry in parse_overlay_table(table)? {
+            let (start, end) = fat_range(fat, entry.file_id as usize)
+                .with_context(|| format!("覆盖文件 {} 的文件编号越界", entry.overlay_id))?;
+            std::fs::write(
+                overlay_dir.join(overlay_file_name(entry.overlay_id)),
+                rom_slice(&rom, start, end.saturating_sub(start))?,
+            )?;
+        }
+    }
+
+    if header.banner_offset() != 0 {
+        let banner_head = rom_slice(&rom, header.banner_offset(), 2)?;
+        let size = banner_size(banner_head)?;
+        std::fs::write(
+            dir_path.join("banner.bin"),
+            rom_slice(&rom, header.banner_offset(), size)?,
+        )?;
+    }
+
+    let data_path = dir_path.join("data");
+    std::fs::create_dir_all(&data_path)?;
+    let mut entries = Vec::new();
+    walk_fnt(fnt, FIRST_DIR_ID, Path::new(""), 0, &mut entries)?;
+    for entry in &entries {
+        match entry {
+            FntEntry::Dir(path) => std::fs::create_dir_all(data_path.join(path))?,
+            FntEntry::File { id, path } => {
+                let (start, end) = fat_range(fat, *id)
+                    .with_context(|| format!("文件 {} 不在文件分配表中", path.display()))?;
+                ensure!(end >= start, "文件 {} 的文件分配表项无效", path.display());
+                std::fs::write(data_path.join(path), rom_slice(&rom, start, end - start)?)
+                    .with_context(|| format!("无法写出文件 {}", path.display()))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// 用于生成文件名表的目录树
+#[derive(Debug, Default)]
+struct FntDir {
+    name: String,
+    files: Vec<(String, PathBuf)>,
+    dirs: Vec<FntDir>,
+}
+
+impl FntDir {
+    /// 读取 `path` 文件夹，`relative` 为它相对于 `data/` 的路径
+    ///
+    /// 原版文件名表中已有的项按 `order` 中的顺序排列，新增的项按文件名排在后面。
+    fn read(
+        name: String,
+        path: &Path,
+        relative: &Path,
+        order: &HashMap<PathBuf, usize>,
+    ) -> anyhow::Result<Self> {
+        let mut dir = FntDir {
+            name,
+            ..Default::default()
+        };
+        let mut entries = std::fs::read_dir(path)
+            .with_context(|| format!("无法读取文件夹 {}", path.display()))?
+            .flatten()
+            .map(|x| (relative.join(x.file_name()), x))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(relative, x)| {
+            (
+                order.get(relative).copied().unwrap_or(usize::MAX),
+                x.file_name(),
+            )
+        });
+        for (relative, entry) in entries {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            ensure!(
+                !name.is_empty() && name.len() <= 0x7F,
+                "文件名 {name} 长度无效，ROM 文件名长度需在 1 到 127 字节之间"
+            );
+            if entry.file_type()?.is_dir() {
+                dir.dirs
+                    .push(FntDir::read(name, &entry.path(), &relative, order)?);
+            } else {
+                dir.files.push((name, entry.path()));
+            }
+        }
+        Ok(dir)
+    }
+}
+
+/// 递归列出文件夹中的目录和文件，路径相对于 `root`
+fn list_dir(
+    root: &Path,
+    relative: &Path,
+    result: &mut BTreeSet<(PathBuf, bool)>,
+) -> anyhow::Result<()> {
+    let path = root.join(relative);
+    for entry in std::fs::read_dir(&path)
+        .with_context(|| format!("无法读取文件夹 {}", path.display()))?
+        .flatten()
+    {
+        let relative = relative.join(entry.file_name());
+        let is_dir = entry.file_type()?.is_dir();
+        if is_dir {
+            list_dir(root, &relative, result)?;
+        }
+        result.insert((relative, is_dir));
+    }
+    Ok(())
+}
+
+/// `data` 文件夹中的目录和文件与原版文件名表完全一致时，返回文件编号和对应的文件
+fn match_original_fnt(
+    entries: &[FntEntry],
+    data: &Path,
+) -> anyhow::Result<Option<Vec<(usize, PathBuf)>>> {
+    let expected = entries
+        .iter()
+        .map(|x| (x.path().to_path_buf(), matches!(x, FntEntry::Dir(_))))
+        .collect::<BTreeSet<_>>();
+    let mut actual = BTreeSet::new();
+    list_dir(data, Path::new(""), &mut actual)?;
+    if expected != actual {
+        return Ok(None);
+    }
+    Ok(Some(
+        entries
+            .iter()
+            .filter_map(|x| match x {
+                FntEntry::File { id, path } => Some((*id, data.join(path))),
+                FntEntry::Dir(_) => None,
+            })
+            .collect(),
+    ))
+}
+
+/// 按先序遍历展开后的目录，记录父目录和子目录的编号
+struct FlatDir<'a> {
+    dir: &'a FntDir,
+    parent: u16,
+    children: Vec<u16>,
+}
+
+fn flatten_dirs<'a>(dir: &'a FntDir, parent: u16, result: &mut Vec<FlatDir<'a>>) -> u16 {
+    let id = FIRST_DIR_ID + result.len() as u16;
+    let index = result.len();
+    result.push(FlatDir {
+        dir,
+        parent,
+        children: Vec::with_capacity(dir.dirs.len()),
+    });
+    for sub_dir in &dir.dirs {
+        let child_id = flatten_dirs(sub_dir, id, result);
+        result[index].children.push(child_id);
+    }
+    id
+}
+
+/// 生成文件名表，返回文件名表数据及按文件编号排列的数据文件路径
+fn build_fnt(root: &FntDir, first_file_id: u16) -> (Vec<u8>, Vec<PathBuf>) {
+    let mut dirs = Vec::new();
+    flatten_dirs(root, 0, &mut dirs);
+
+    let main_table_size = dirs.len() * 8;
+    let mut main_table = Vec::with_capacity(main_table_size);
+    let mut sub_tables = Vec::new();
+    let mut files = Vec::new();
+    let mut file_id = first_file_id;
+
+    for (index, flat) in dirs.iter().enumerate() {
+        // 根目录的父目录字段存放的是目录总数
+        let parent = if index == 0 {
+            dirs.len() as u16
+        } else {
+            flat.parent
+        };
+        main_table.extend_from_slice(&((main_table_size + sub_tables.len()) as u32).to_le_bytes());
+        main_table.extend_from_slice(&file_id.to_le_bytes());
+        main_table.extend_from_slice(&parent.to_le_bytes());
+
+        for (name, path) in &flat.dir.files {
+            sub_tables.push(name.len() as u8);
+            sub_tables.extend_from_slice(name.as_bytes());
+            files.push(path.clone());
+            file_id += 1;
+        }
+        for (sub_dir, child_id) in flat.dir.dirs.iter().zip(flat.children.iter()) {
+            sub_tables.push(0x80 | sub_dir.name.len() as u8);
+            sub_tables.extend_from_slice(sub_dir.name.as_bytes());
+            sub_tables.extend_from_slice(&child_id.to_le_bytes());
+        }
+        sub_tables.push(0);
+    }
+
+    main_table.extend_from_slice(&sub_tables);
+    (main_table, files)
+}
+
+/// 重新打包 ROM，替代 `ndstool -c`
+///
+/// 数据按 ndstool 的顺序排列：头部、ARM9、ARM9 覆盖表与覆盖文件、ARM7、ARM7 覆盖表与覆盖文件、
+/// 文件名表、文件分配表、图标标题、数据文件，各部分按 0x200 字节对齐。
+#[derive(Debug, Default)]
+pub struct NdsRomBuilder {
+    header: PathBuf,
+    arm9: PathBuf,
+    arm7: PathBuf,
+    y9: PathBuf,
+    y7: PathBuf,
+    overlay: PathBuf,
+    overlay7: PathBuf,
+    data: PathBuf,
+    fnt: PathBuf,
+    banner: PathBuf,
+}
+
+impl NdsRomBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 使用同一个解包文件夹中的所有文件
+    pub fn with_dir(self, dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref();
+        self.with_header(dir.join("header.bin"))
+            .with_arm9(dir.join("arm9.bin"))
+            .with_arm7(dir.join("arm7.bin"))
+            .with_y9(dir.join("y9.bin"))
+            .with_y7(dir.join("y7.bin"))
+            .with_overlay(dir.join("overlay"))
+            .with_overlay7(dir.join("overlay7"))
+            .with_data(dir.join("data"))
+            .with_fnt(dir.join("fnt.bin"))
+            .with_banner(dir.join("banner.bin"))
+    }
+
+    pub fn with_header(mut self, path: impl AsRef<Path>) -> Self {
+        self.header = path.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn with_arm9(mut self, path: impl AsRef<Path>) -> Self {
+        self.arm9 = path.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn with_arm7(mut self, path: impl AsRef<Path>) -> Self {
+        self.arm7 = path.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn with_y9(mut self, path: impl AsRef<Path>) -> Self {
+        self.y9 = path.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn with_y7(mut self, path: impl AsRef<Path>) -> Self {
+        self.y7 = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// ARM9 覆盖文件所在的文件夹
+    pub fn with_overlay(mut self, path: impl AsRef<Path>) -> Self {
+        self.overlay = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// ARM7 覆盖文件所在的文件夹
+    pub fn with_overlay7(mut self, path: impl AsRef<Path>) -> Self {
+        self.overlay7 = path.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn with_data(mut self, path: impl AsRef<Path>) -> Self {
+        self.data = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// 原版文件名表，文件不存在时按文件名排列 `data/` 中的文件
+    pub fn with_fnt(mut self, path: impl AsRef<Path>) -> Self {
+        self.fnt = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// 图标标题，文件不存在时不写入图标标题，头部中的偏移为 0
+    pub fn with_banner(mut self, path: impl AsRef<Path>) -> Self {
+        self.banner = path.as_ref().to_path_buf();
+        self
+    }
+
+    fn read(path: &Path) -> anyhow::Result<Vec<u8>> {
+        std::fs::read(path).with_context(|| format!("无法读取 {}", path.display()))
+    }
+
+    pub fn build(&self) -> anyhow::Result<Vec<u8>> {
+        let mut header = NdsHeader::from_bytes(&Self::read(&self.header)?)?;
+        let arm9 = Self::read(&self.arm9)?;
+        let arm7 = Self::read(&self.arm7)?;
+        let y9 = Self::read(&self.y9)?;
+        let y7 = Self::read(&self.y7)?;
+        let banner = if self.banner.is_file() {
+            Some(Self::read(&self.banner)?)
+        } else {
+            None
+        };
+        let y9_entries = parse_overlay_table(&y9)?;
+        let y7_entries = parse_overlay_table(&y7)?;
+
+        let overlay_count = y9_entries
+            .iter()
+            .chain(y7_entries.iter())
+            .map(|x| x.file_id as usize + 1)
+            .max()
+            .unwrap_or_default();
+
+        let original_fnt = if self.fnt.is_file() {
+            Some(Self::read(&self.fnt)?)
+        } else {
+            None
+        };
+        let mut original_entries = Vec::new();
+        if let Some(fnt) = &original_fnt {
+            walk_fnt(fnt, FIRST_DIR_ID, Path::new(""), 0, &mut original_entries)
+                .with_context(|| format!("原版文件名表 {} 无效", self.fnt.display()))?;
+        }
+        let matched = match &original_fnt {
+            Some(_) => match_original_fnt(&original_entries, &self.data)?,
+            None => None,
+        };
+        let (fnt, mut data_files) = match (original_fnt, matched) {
+            (Some(fnt), Some(files)) => (fnt, files),
+            _ => {
+                let order = original_entries
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| (x.path().to_path_buf(), i))
+                    .collect::<HashMap<_, _>>();
+                let root = FntDir::read(String::new(), &self.data, Path::new(""), &order)?;
+                let (fnt, files) = build_fnt(&root, overlay_count as u16);
+                let files = files
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, x)| (overlay_count + i, x))
+                    .collect();
+                (fnt, files)
+            }
+        };
+        data_files.sort_by_key(|x| x.0);
+        ensure!(
+            data_files.first().is_none_or(|x| x.0 >= overlay_count),
+            "文件名表中的文件编号与覆盖文件重叠"
+        );
+        let file_count = data_files.last().map_or(overlay_count, |x| x.0 + 1);
+        let mut fat = vec![0u8; file_count * 8];
+
+        let mut rom = header.raw.clone();
+        rom.resize(HEADER_AREA_SIZE, 0);
+
+        let pad = |rom: &mut Vec<u8>| {
+            let aligned = align(rom.len(), ROM_ALIGN);
+            rom.resize(aligned, 0xFF);
+        };
+
+        // ARM9，尾部信息不计入大小
+        let arm9_offset = header.arm9_offset().max(HEADER_AREA_SIZE);
+        rom.resize(arm9_offset, 0);
+        let mut arm9_size = arm9.len();
+        if arm9.len() >= ARM9_FOOTER_SIZE
+            && read_u32(&arm9, arm9.len() - ARM9_FOOTER_SIZE)? == ARM9_FOOTER_MAGIC
+        {
+            arm9_size -= ARM9_FOOTER_SIZE;
+        }
+        rom.extend_from_slice(&arm9);
+        write_u32(&mut header.raw, 0x20, arm9_offset as u32);
+        write_u32(&mut header.raw, 0x2C, arm9_size as u32);
+
+        let mut place_overlays = |rom: &mut Vec<u8>,
+                                  table: &[u8],
+                                  entries: &[OverlayEntry],
+                                  overlay_dir: &Path,
+                                  table_pos: usize| {
+            pad(rom);
+            let table_offset = rom.len();
+            rom.extend_from_slice(table);
+            write_u32(
+                &mut header.raw,
+                table_pos,
+                if table.is_empty() {
+                    0
+                } else {
+                    table_offset as u32
+                },
+            );
+            write_u32(&mut header.raw, table_pos + 4, table.len() as u32);
+            for entry in entries {
+                let path = overlay_dir.join(overlay_file_name(entry.overlay_id));
+                let data = Self::read(&path)?;
+                pad(rom);
+                let start = rom.len();
+                rom.extend_from_slice(&data);
+                let file_id = entry.file_id as usize;
+                write_u32(&mut fat, file_id * 8, start as u32);
+                write_u32(&mut fat, file_id * 8 + 4, rom.len() as u32);
+            }
+            anyhow::Ok(())
+        };
+
+        place_overlays(&mut rom, &y9, &y9_entries, &self.overlay, 0x50)?;
+
+        pad(&mut rom);
+        let arm7_offset = rom.len();
+        rom.extend_from_slice(&arm7);
+
+        place_overlays(&mut rom, &y7, &y7_entries, &self.overlay7, 0x58)?;
+
+        write_u32(&mut header.raw, 0x30, arm7_offset as u32);
+        write_u32(&mut header.raw, 0x3C, arm7.len() as u32);
+
+        pad(&mut rom);
+        let fnt_offset = rom.len();
+        rom.extend_from_slice(&fnt);
+        write_u32(&mut header.raw, 0x40, fnt_offset as u32);
+        write_u32(&mut header.raw, 0x44, fnt.len() as u32);
+
+        pad(&mut rom);
+        let fat_offset = rom.len();
+        rom.resize(fat_offset + fat.len(), 0);
+        write_u32(&mut header.raw, 0x48, fat_offset as u32);
+        write_u32(&mut header.raw, 0x4C, fat.len() as u32);
+
+        let banner_offset = match &banner {
+            Some(banner) => {
+                pad(&mut rom);
+                let offset = rom.len();
+                rom.extend_from_slice(banner);
+                offset
+            }
+            None => 0,
+        };
+        write_u32(&mut header.raw, 0x68, banner_offset as u32);
+
+        for (file_id, path) in &data_files {
+            let data = Self::read(path)?;
+            pad(&mut rom);
+            let start = rom.len();
+            rom.extend_from_slice(&data);
+            let file_id = *file_id;
+            write_u32(&mut fat, file_id * 8, start as u32);
+            write_u32(&mut fat, file_id * 8 + 4, rom.len() as u32);
+        }
+        rom[fat_offset..fat_offset + fat.len()].copy_from_slice(&fat);
+
+        let used_size = rom.len();
+        write_u32(&mut header.raw, 0x80, used_size as u32);
+
+        // 卡带容量为 128KiB << n
+        let mut capacity = 0u8;
+        while (0x20000usize << capacity) < used_size {
+            capacity += 1;
+        }
+        header.raw[0x14] = capacity;
+
+        if arm9_offset == HEADER_AREA_SIZE && rom.len() >= 0x8000 {
+            write_u16(&mut header.raw, 0x6C, crc16(&rom[0x4000..0x8000]));
+        }
+        let header_crc = header.calc_header_crc();
+        write_u16(&mut header.raw, 0x15E, header_crc);
+        rom[..HEADER_SIZE].copy_from_slice(&header.raw);
+
+        Ok(rom)
+    }
+
+    pub fn build_to(&self, output: impl AsRef<Path>) -> anyhow::Result<()> {
+        let output = output.as_ref();
+        let rom = self.build()?;
+        std::fs::write(output, rom)
+            .with_context(|| format!("无法写出 ROM 文件 {}", output.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 根目录中只有一个子目录 `a`，编号为 `sub_dir_id`
+    fn fnt_with_sub_dir(sub_dir_id: u16) -> Vec<u8> {
+        let mut fnt = Vec::new();
+        fnt.extend_from_slice(&8u32.to_le_bytes());
+        fnt.extend_from_slice(&0u16.to_le_bytes());
+        fnt.extend_from_slice(&1u16.to_le_bytes());
+        fnt.extend_from_slice(&[0x81, b'a']);
+        fnt.extend_from_slice(&sub_dir_id.to_le_bytes());
+        fnt.push(0);
+        fnt
+    }
+
+    #[test]
+    fn corrupt_fnt_is_an_error() {
+        for sub_dir_id in [FIRST_DIR_ID, 0x0001] {
+            let fnt = fnt_with_sub_dir(sub_dir_id);
+            assert!(walk_fnt(&fnt, FIRST_DIR_ID, Path::new(""), 0, &mut Vec::new()).is_err());
+        }
+        // 子目录编号被截断
+        let fnt = fnt_with_sub_dir(FIRST_DIR_ID);
+        assert!(walk_fnt(&fnt[..12], FIRST_DIR_ID, Path::new(""), 0, &mut Vec::new()).is_err());
+    }
+
+    /// 一个目录的子表，`entries` 中目录项带有子目录编号
+    fn sub_table(entries: &[(&str, Option<u16>)]) -> Vec<u8> {
+        let mut table = Vec::new();
+        for (name, sub_dir_id) in entries {
+            match sub_dir_id {
+                Some(id) => {
+                    table.push(0x80 | name.len() as u8);
+                    table.extend_from_slice(name.as_bytes());
+                    table.extend_from_slice(&id.to_le_bytes());
+                }
+                None => {
+                    table.push(name.len() as u8);
+                    table.extend_from_slice(name.as_bytes());
+                }
+            }
+        }
+        table.push(0);
+        table
+    }
+
+    /// 不按文件名排序的文件名表：根目录为 `b.bin`、`z/`、`a.bin`、`m/`，
+    /// 子目录的编号与先序遍历的顺序也不同
+    fn unsorted_fnt(first_file_id: u16) -> Vec<u8> {
+        let tables = [
+            (
+                sub_table(&[
+                    ("b.bin", None),
+                    ("z", Some(0xF002)),
+                    ("a.bin", None),
+                    ("m", Some(0xF001)),
+                ]),
+                first_file_id,
+                3u16,
+            ),
+            (sub_table(&[("d.bin", None)]), first_file_id + 3, 0xF000),
+            (sub_table(&[("c.bin", None)]), first_file_id + 2, 0xF000),
+        ];
+        let mut main_table = Vec::new();
+        let mut sub_tables = Vec::new();
+        for (table, file_id, parent) in &tables {
+            main_table
+                .extend_from_slice(&((tables.len() * 8 + sub_tables.len()) as u32).to_le_bytes());
+            main_table.extend_from_slice(&file_id.to_le_bytes());
+            main_table.extend_from_slice(&parent.to_le_bytes());
+            sub_tables.extend_from_slice(table);
+        }
+        main_table.extend_from_slice(&sub_tables);
+        main_table
+    }
+
+    fn overlay_table(overlay_id: u32, file_id: u32) -> Vec<u8> {
+        let mut table = vec![0u8; OVERLAY_ENTRY_SIZE];
+        write_u32(&mut table, 0x00, overlay_id);
+        write_u32(&mut table, 0x18, file_id);
+        table
+    }
+
+    /// 生成一个没有图标标题的解包文件夹，ARM9 和 ARM7 各有一个覆盖文件
+    fn write_unpacked(dir: &Path) {
+        let _ = std::fs::remove_dir_all(dir);
+        for sub_dir in ["overlay", "overlay7", "data/z", "data/m"] {
+            std::fs::create_dir_all(dir.join(sub_dir)).unwrap();
+        }
+        let mut header = vec![0u8; HEADER_SIZE];
+        header[..4].copy_from_slice(b"TEST");
+        header[0x0C..0x10].copy_from_slice(b"TSTJ");
+        std::fs::write(dir.join("header.bin"), header).unwrap();
+        std::fs::write(dir.join("arm9.bin"), [9u8; 0x300]).unwrap();
+        std::fs::write(dir.join("arm7.bin"), [7u8; 0x100]).unwrap();
+        // ARM9 覆盖文件的文件编号在 ARM7 之后
+        std::fs::write(dir.join("y9.bin"), overlay_table(3, 1)).unwrap();
+        std::fs::write(dir.join("y7.bin"), overlay_table(3, 0)).unwrap();
+        std::fs::write(dir.join("overlay/overlay_0003.bin"), b"arm9 overlay").unwrap();
+        std::fs::write(dir.join("overlay7/overlay_0003.bin"), b"arm7 overlay").unwrap();
+        std::fs::write(dir.join("fnt.bin"), unsorted_fnt(2)).unwrap();
+        for (name, data) in [
+            ("b.bin", &b"bbbb"[..]),
+            ("a.bin", b"a"),
+            ("z/c.bin", b"cc"),
+            ("m/d.bin", b""),
+        ] {
+            std::fs::write(dir.join("data").join(name), data).unwrap();
+        }
+    }
+
+    fn fat_order(rom: &[u8]) -> Vec<(u32, u32)> {
+        let header = NdsHeader::from_bytes(rom).unwrap();
+        let fat = &rom[header.fat_offset()..header.fat_offset() + header.fat_size()];
+        (0..fat.len() / 8)
+            .map(|i| {
+                (
+                    read_u32(fat, i * 8).unwrap(),
+                    read_u32(fat, i * 8 + 4).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn extract_and_rebuild_unchanged() {
+        let base = std::env::temp_dir().join(format!("nds-rom-round-trip-{}", std::process::id()));
+        let (source, extracted) = (base.join("source"), base.join("extracted"));
+        write_unpacked(&source);
+        let rom = NdsRomBuilder::new().with_dir(&source).build().unwrap();
+
+        let header = NdsHeader::from_bytes(&rom).unwrap();
+        assert_eq!(header.banner_offset(), 0);
+        assert_eq!(header.calc_header_crc(), header.header_crc());
+        let fnt = &rom[header.fnt_offset()..header.fnt_offset() + header.fnt_size()];
+        assert_eq!(fnt, unsorted_fnt(2));
+
+        let rom_path = base.join("test.nds");
+        std::fs::write(&rom_path, &rom).unwrap();
+        extract_rom(&rom_path, &extracted).unwrap();
+        assert!(!extracted.join("banner.bin").exists());
+        for file in [
+            "fnt.bin",
+            "data/a.bin",
+            "data/m/d.bin",
+            "overlay/overlay_0003.bin",
+        ] {
+            assert_eq!(
+                std::fs::read(extracted.join(file)).unwrap(),
+                std::fs::read(source.join(file)).unwrap(),
+                "{file}"
+            );
+        }
+        assert_eq!(
+            std::fs::read(extracted.join("overlay7/overlay_0003.bin")).unwrap(),
+            b"arm7 overlay"
+        );
+
+        let rebuilt = NdsRomBuilder::new().with_dir(&extracted).build().unwrap();
+        let rebuilt_header = NdsHeader::from_bytes(&rebuilt).unwrap();
+        assert_eq!(rebuilt_header.header_crc(), header.header_crc());
+        assert_eq!(fat_order(&rebuilt), fat_order(&rom));
+        assert!(rebuilt == rom);
+
+        // 新增文件时重新生成文件名表，原有的项保持原版的顺序
+        std::fs::write(extracted.join("data/0.bin"), b"new").unwrap();
+        let grown = NdsRomBuilder::new().with_dir(&extracted).build().unwrap();
+        let header = NdsHeader::from_bytes(&grown).unwrap();
+        let fnt = &grown[header.fnt_offset()..header.fnt_offset() + header.fnt_size()];
+        let mut entries = Vec::new();
+        walk_fnt(fnt, FIRST_DIR_ID, Path::new(""), 0, &mut entries).unwrap();
+        let files = entries
+            .iter()
+            .filter_map(|x| match x {
+                FntEntry::File { id, path } => {
+                    Some((*id, path.to_string_lossy().replace('\\', "/")))
+                }
+                FntEntry::Dir(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            [
+                (2, "b.bin".to_string()),
+                (3, "a.bin".to_string()),
+                (4, "0.bin".to_string()),
+                (5, "z/c.bin".to_string()),
+                (6, "m/d.bin".to_string()),
+            ]
+        );
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+//! 发布补丁的格式和发布清单 `release.json`
+
+use serde::{Deserialize, Serialize};
+
+use super::{hash::FileHashes, vcdiff};
+
+/// 发布清单文件名，与补丁放在同一文件夹下
+pub const RELEASE_MANIFEST_FILE: &str = "release.json";
+
+/// IPS 格式的偏移只有 24 位，无法修补超过 16 MiB 的 ROM
+const IPS_MAX_SIZE: usize = 0x100_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatchFormat {
+    Bps,
+    /// 与 xdelta3 兼容的 VCDIFF
+    Vcdiff,
+    Ips,
+}
+
+impl PatchFormat {
+    pub const ALL: [PatchFormat; 3] = [PatchFormat::Bps, PatchFormat::Vcdiff, PatchFormat::Ips];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            PatchFormat::Bps => "bps",
+            PatchFormat::Vcdiff => "xdelta",
+            PatchFormat::Ips => "ips",
+        }
+    }
+
+    /// 生成从 `source` 到 `target` 的补丁，ROM 大小超出格式的限制时返回 `None`
+    pub fn create(self, source: &[u8], target: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(Some(match self {
+            PatchFormat::Bps => {
+                let mut bps = flips::BpsDeltaBuilder::new();
+                bps.source(source);
+                bps.target(target);
+                bps.build()?.as_ref().to_vec()
+            }
+            PatchFormat::Vcdiff => vcdiff::encode(source, target),
+            PatchFormat::Ips => {
+                if source.len().max(target.len()) > IPS_MAX_SIZE {
+                    return Ok(None);
+                }
+                let mut ips = flips::IpsBuilder::new();
+                ips.source(source);
+                ips.target(target);
+                ips.build()?.as_ref().to_vec()
+            }
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleasePatch {
+    pub format: PatchFormat,
+    /// 补丁文件名
+    pub file: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseRom {
+    /// 游戏版本，`ninja` 或 `saurian`
+    pub version: String,
+    /// 修补后的 ROM 文件名
+    pub file: String,
+    /// 原版 ROM 的哈希
+    pub source: FileHashes,
+    /// 修补后 ROM 的哈希
+    pub target: FileHashes,
+    pub patches: Vec<ReleasePatch>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseManifest {
+    /// 发布版本号
+    pub release: String,
+    pub roms: Vec<ReleaseRom>,
+}
@@ -0,0 +1,246 @@
+//! 项目的目录结构和配置文件 `rnr2cn.toml`
+//!
+//! 所有命令都通过 [`Project`] 获取路径、配置和外部工具，不再各自拼接 `_workspace` 等路径。
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::*;
+use serde::Deserialize;
+
+use super::{jobs::run_command, ToolsRunner, PROJECT_CONFIG_FILE};
+
+/// 项目中用到的目录，都位于项目根目录下
+#[derive(Debug, Clone)]
+pub struct ProjectPaths {
+    pub root: PathBuf,
+    /// 原版 ROM，`_rom`
+    pub roms: PathBuf,
+    /// setup 解包的原版游戏文件，`_workspace`
+    pub workspace: PathBuf,
+    /// 解包后的游戏归档，`_workspace/unpacked_bins`
+    pub unpacked_bins: PathBuf,
+    /// 原版游戏脚本，`_workspace/mess_tpl`
+    pub mess_tpl: PathBuf,
+    /// 打包生成的 ROM 和符号表，`_build`
+    pub build: PathBuf,
+    /// 中间文件，`_temp`
+    pub temp: PathBuf,
+    /// genfont 生成的字库，`_temp/fonts`
+    pub fonts: PathBuf,
+    pub images: PathBuf,
+    /// 翻译后的游戏脚本，`tpl`
+    pub tpl: PathBuf,
+    pub tools: PathBuf,
+    /// 码表和文本插件，`tools/plugins`
+    pub plugins: PathBuf,
+    pub asm: PathBuf,
+}
+
+impl ProjectPaths {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref().to_path_buf();
+        let workspace = root.join("_workspace");
+        let temp = root.join("_temp");
+        let tools = root.join("tools");
+        Self {
+            roms: root.join("_rom"),
+            unpacked_bins: workspace.join("unpacked_bins"),
+            mess_tpl: workspace.join("mess_tpl"),
+            workspace,
+            build: root.join("_build"),
+            fonts: temp.join("fonts"),
+            temp,
+            images: root.join("images"),
+            tpl: root.join("tpl"),
+            plugins: tools.join("plugins"),
+            tools,
+            asm: root.join("src/asm"),
+            root,
+        }
+    }
+
+    /// 某个版本解包后的原版游戏文件，例如 `_workspace/ninja`
+    pub fn version_workspace(&self, version: &str) -> PathBuf {
+        self.workspace.join(version)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReleaseConfig {
+    /// 正式版本的版本号，用于发布文件名和 release.json
+    pub version: String,
+}
+
+impl Default for ReleaseConfig {
+    fn default() -> Self {
+        Self {
+            version: "Ver.2".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TranslatorPackConfig {
+    /// 默认生成翻译工具包的目标平台，见 `rnr2cn translator-pack --help`
+    pub targets: Vec<String>,
+}
+
+impl Default for TranslatorPackConfig {
+    fn default() -> Self {
+        Self {
+            targets: vec![
+                "x86_64-pc-windows-msvc".to_string(),
+                "x86_64-unknown-linux-gnu".to_string(),
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CheckTextConfig {
+    /// 消息框每行的最大宽度（像素）
+    pub line_width: u32,
+    /// 显示头像时每行的最大宽度（像素）
+    pub mugshot_line_width: u32,
+    /// 每个消息框的最大行数
+    pub box_lines: usize,
+    /// 特定脚本的规则，按顺序使用第一条匹配的规则
+    pub rules: Vec<CheckTextRule>,
+    /// 通过打印缓冲区输出文本的指令名前缀，arm9 绘制缓冲区中的文本时每个字符固定 11px
+    pub print_commands: Vec<String>,
+    /// print 指令输出的字符数的估计值
+    pub print_chars: u32,
+    /// 按指令名覆盖 `print_chars`
+    pub print_lengths: HashMap<String, u32>,
+}
+
+impl Default for CheckTextConfig {
+    fn default() -> Self {
+        Self {
+            line_width: 216,
+            mugshot_line_width: 168,
+            box_lines: 3,
+            rules: Vec::new(),
+            print_commands: vec!["print".to_string()],
+            print_chars: 8,
+            // 这两个指令在 SF2 中不输出文本
+            print_lengths: HashMap::from([
+                ("printColor".to_string(), 0),
+                ("printColorBuffered".to_string(), 0),
+            ]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CheckTextRule {
+    /// 适用的脚本，`归档名` 或 `归档名:脚本序号`，例如 `mess_120`、`mess_121:3`
+    pub scripts: Vec<String>,
+    /// 每个字符固定 11px，对应渲染方式 0x400/0x600 的文本
+    pub fixed_width: bool,
+    /// 覆盖 [`CheckTextConfig::line_width`] 和 `mugshot_line_width`
+    pub line_width: Option<u32>,
+    /// 覆盖 [`CheckTextConfig::box_lines`]，0 表示不检查行数
+    pub box_lines: Option<usize>,
+    /// 不检查这些脚本
+    pub skip: bool,
+}
+
+impl CheckTextRule {
+    pub fn matches(&self, archive: &str, script: usize) -> bool {
+        self.scripts.iter().any(|x| match x.split_once(':') {
+            Some((name, index)) => name == archive && index.trim().parse().ok() == Some(script),
+            None => x == archive,
+        })
+    }
+}
+
+/// 配置文件 `rnr2cn.toml` 的内容，文件不存在时使用默认配置
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// 外部工具的路径，见 [`ToolsRunner`]
+    pub tools: HashMap<String, PathBuf>,
+    pub release: ReleaseConfig,
+    pub translator_pack: TranslatorPackConfig,
+    pub check_text: CheckTextConfig,
+}
+
+impl ProjectConfig {
+    pub fn load(root: impl AsRef<Path>) -> Result<Self> {
+        let path = root.as_ref().join(PROJECT_CONFIG_FILE);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("无法读取配置文件 {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("无法解析配置文件 {}", path.display()))
+    }
+}
+
+/// 命令运行时的上下文
+pub struct Project {
+    pub paths: ProjectPaths,
+    pub config: ProjectConfig,
+    pub tools: ToolsRunner,
+    /// 只显示将要执行的操作，不修改任何文件
+    pub dry_run: bool,
+    /// 显示运行的外部命令和跳过的构建步骤
+    pub verbose: bool,
+}
+
+impl Project {
+    pub fn open(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref();
+        let config = ProjectConfig::load(root)?;
+        Ok(Self {
+            paths: ProjectPaths::new(root),
+            tools: ToolsRunner::from_config(root, &config),
+            config,
+            dry_run: false,
+            verbose: false,
+        })
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// 运行外部命令，dry-run 时只打印命令行
+    pub fn run(&self, command: &mut Command) -> Result<()> {
+        if self.dry_run || self.verbose {
+            let args = command
+                .get_args()
+                .map(|x| format!("{:?}", x.to_string_lossy()))
+                .collect::<Vec<_>>()
+                .join(" ");
+            println!("> {} {args}", command.get_program().to_string_lossy());
+        }
+        if self.dry_run {
+            return Ok(());
+        }
+        run_command(command)
+    }
+
+    /// dry-run 时打印将要执行的操作并返回 `true`，调用方应跳过该操作
+    pub fn skip_in_dry_run(&self, action: impl std::fmt::Display) -> bool {
+        if self.dry_run {
+            println!("dry-run：将{action}");
+        }
+        self.dry_run
+    }
+}
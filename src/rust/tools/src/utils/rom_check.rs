@@ -0,0 +1,604 @@
+//! 识别并检查 `_rom` 文件夹下的原版 ROM
+//!
+//! ROM 按头部的游戏代码识别版本，文件名不对或者互相调换时也能找到。
+//! 每个版本的识别信息写在项目根目录下的 `roms.toml` 中。
+//! 翻译工具包不附带 ROM，其中的 `roms.toml` 记录了制作工具包时使用的 ROM 的 SHA-1，
+//! setup 据此检查用户自己的 dump。
+
+use std::{
+    collections::BTreeMap,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    hash::FileHashes,
+    nds_rom::{NdsHeader, HEADER_SIZE},
+};
+
+/// 原版 ROM 识别信息文件，位于项目根目录下
+pub const KNOWN_ROMS_FILE: &str = "roms.toml";
+/// 需要的游戏版本，ROM 默认以 `<版本>.nds` 命名
+pub const ROM_VERSIONS: [&str; 2] = ["ninja", "saurian"];
+/// `roms.toml` 开头的注释
+const KNOWN_ROMS_HEADER: &str = "原版 ROM 的识别信息，setup 会据此识别并检查 _rom 文件夹下的 ROM
+
+每个版本的字段：
+  name       显示用的版本名
+  game_code  ROM 头部 0x0C 处的 4 字节游戏代码
+  title      ROM 头部 0x00 处的游戏标题，不填则不检查
+  sha1       已知完好的 dump 的 SHA-1，可以有多个
+             为空时 setup 和 pack 不接受任何 ROM，确认 dump 未经裁剪和修改后，
+             运行 rnr2cn setup --trust 记录它的 title 和 sha1";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownRom {
+    /// 显示用的版本名
+    pub name: String,
+    pub game_code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// 已知完好的 dump 的 SHA-1
+    #[serde(default)]
+    pub sha1: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct KnownRoms(BTreeMap<String, KnownRom>);
+
+impl KnownRoms {
+    pub fn load(root_path: impl AsRef<Path>) -> Result<Self> {
+        let path = root_path.as_ref().join(KNOWN_ROMS_FILE);
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("无法读取原版 ROM 识别信息 {}", path.display()))?;
+        let roms: BTreeMap<String, KnownRom> = toml::from_str(&content)
+            .with_context(|| format!("无法解析原版 ROM 识别信息 {}", path.display()))?;
+        for version in ROM_VERSIONS {
+            ensure!(
+                roms.contains_key(version),
+                "{} 中缺少 [{version}] 的识别信息",
+                path.display()
+            );
+        }
+        Ok(Self(roms))
+    }
+
+    pub fn get(&self, version: &str) -> Option<&KnownRom> {
+        self.0.get(version)
+    }
+
+    /// 把 ROM 的 SHA-1 加入已知完好的 dump
+    pub fn add_sha1(&mut self, version: &str, sha1: &str) {
+        if let Some(info) = self.0.get_mut(version) {
+            if !info.sha1.iter().any(|x| x.eq_ignore_ascii_case(sha1)) {
+                info.sha1.push(sha1.to_lowercase());
+            }
+        }
+    }
+
+    /// 记录已确认完好的 dump 的 SHA-1，没有设置标题时同时记录标题
+    fn trust(&mut self, report: &RomReport) {
+        let Some(version) = &report.version else {
+            return;
+        };
+        self.add_sha1(version, &report.sha1);
+        if let Some(info) = self.0.get_mut(version) {
+            info.title.get_or_insert_with(|| report.title.clone());
+        }
+    }
+
+    /// 写入识别信息文件，`header` 为文件开头的注释
+    pub fn save(&self, path: impl AsRef<Path>, header: &str) -> Result<()> {
+        let path = path.as_ref();
+        let content = header
+            .lines()
+            .map(|x| format!("# {x}").trim_end().to_string() + "\n")
+            .collect::<String>()
+            + "\n"
+            + &toml::to_string(&self.0)?;
+        std::fs::write(path, content)
+            .with_context(|| format!("无法写入原版 ROM 识别信息 {}", path.display()))
+    }
+
+    fn find_by_game_code(&self, game_code: &str) -> Option<(&String, &KnownRom)> {
+        self.0.iter().find(|(_, x)| x.game_code == game_code)
+    }
+}
+
+/// 一个 ROM 文件的检查结果
+#[derive(Debug, Clone)]
+pub struct RomReport {
+    pub path: PathBuf,
+    /// 按游戏代码识别出的版本
+    pub version: Option<String>,
+    pub game_code: String,
+    pub title: String,
+    pub sha1: String,
+    /// 导致 ROM 不能使用的问题
+    pub problems: Vec<String>,
+    pub warnings: Vec<String>,
+    /// `roms.toml` 中没有这个版本的已知 SHA-1，ROM 只检查了头部
+    pub unverified: bool,
+}
+
+impl RomReport {
+    fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// 导致 ROM 不能使用的问题，`trust` 为 `true` 时接受没有已知 SHA-1 可以比较的 ROM
+    fn blocking_problems(&self, trust: bool) -> Vec<String> {
+        let mut problems = self.problems.clone();
+        if self.unverified && !trust {
+            problems.push(format!(
+                "{KNOWN_ROMS_FILE} 中没有这个版本的已知 SHA-1，无法确认 ROM 未经损坏或修改，\
+                 确认 dump 完好后运行 rnr2cn setup --trust 记录它的 SHA-1"
+            ));
+        }
+        problems
+    }
+
+    pub fn describe(&self, known: &KnownRoms) -> String {
+        let version = self
+            .version
+            .as_deref()
+            .and_then(|x| known.get(x))
+            .map_or("未知版本", |x| x.name.as_str());
+        let mut description = format!(
+            "{}：游戏代码 {:?}（{version}），标题 {:?}，SHA-1 {}",
+            self.file_name(),
+            self.game_code,
+            self.title,
+            self.sha1
+        );
+        for problem in self.blocking_problems(false) {
+            description += &format!("\n    {problem}");
+        }
+        description
+    }
+}
+
+/// 检查一个 ROM 文件的头部、大小和哈希
+pub fn check_rom(known: &KnownRoms, path: impl AsRef<Path>) -> Result<RomReport> {
+    let path = path.as_ref();
+    let rom =
+        std::fs::read(path).with_context(|| format!("无法读取 ROM 文件 {}", path.display()))?;
+    let mut report = RomReport {
+        path: path.to_path_buf(),
+        version: None,
+        game_code: String::new(),
+        title: String::new(),
+        sha1: FileHashes::new(&rom).sha1,
+        problems: Vec::new(),
+        warnings: Vec::new(),
+        unverified: false,
+    };
+    if rom.len() < HEADER_SIZE {
+        report
+            .problems
+            .push(format!("文件只有 {} 字节，不是 NDS ROM", rom.len()));
+        return Ok(report);
+    }
+
+    let header = NdsHeader::from_bytes(&rom)?;
+    report.game_code = header.game_code();
+    report.title = header.title();
+    if header.calc_header_crc() != header.header_crc() {
+        report
+            .problems
+            .push("头部校验值错误，ROM 可能已损坏或者不是 NDS ROM".to_string());
+    }
+    let used_size = header.rom_used_size();
+    if rom.len() < used_size {
+        report.problems.push(format!(
+            "文件大小 {} 字节小于头部记录的 {used_size} 字节，ROM 不完整",
+            rom.len()
+        ));
+    }
+
+    let Some((version, info)) = known.find_by_game_code(&report.game_code) else {
+        return Ok(report);
+    };
+    report.version = Some(version.clone());
+    if let Some(title) = &info.title {
+        if *title != report.title {
+            report.problems.push(format!(
+                "游戏标题为 {:?}，{}应为 {title:?}，可能是修改过的 ROM",
+                report.title, info.name
+            ));
+        }
+    }
+    let trimmed = rom.len() == used_size;
+    if info.sha1.is_empty() {
+        report.unverified = true;
+        if trimmed {
+            report
+                .problems
+                .push("ROM 经过裁剪（trim），请使用完整的 dump".to_string());
+        }
+    } else if !info
+        .sha1
+        .iter()
+        .any(|x| x.eq_ignore_ascii_case(&report.sha1))
+    {
+        report.problems.push(if trimmed {
+            "SHA-1 与已知完好的 dump 不一致，ROM 经过裁剪（trim），请使用完整的 dump".to_string()
+        } else {
+            "SHA-1 与已知完好的 dump 不一致，可能是损坏或修改过的 ROM".to_string()
+        });
+    }
+    Ok(report)
+}
+
+/// 检查文件夹下的所有 `.nds` 文件，按文件名排序
+pub fn check_roms_in_dir(known: &KnownRoms, dir: impl AsRef<Path>) -> Result<Vec<RomReport>> {
+    let dir = dir.as_ref();
+    let mut files = std::fs::read_dir(dir)
+        .with_context(|| format!("无法读取 ROM 文件夹 {}", dir.display()))?
+        .flatten()
+        .map(|x| x.path())
+        .filter(|x| {
+            x.is_file()
+                && x.extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("nds"))
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files.iter().map(|x| check_rom(known, x)).collect()
+}
+
+/// 从检查结果中为每个版本选出使用的 ROM
+///
+/// 优先使用以 `<版本>.nds` 命名且没有问题的文件，同等条件下使用靠前的文件。
+/// `trust` 为 `true` 时接受 `roms.toml` 中没有已知 SHA-1 的版本的 ROM。
+/// 返回每个版本使用的 ROM，以及找不到可用 ROM 的版本和原因。
+pub fn select_roms(
+    known: &KnownRoms,
+    reports: &[RomReport],
+    trust: bool,
+) -> (BTreeMap<String, PathBuf>, BTreeMap<String, String>) {
+    let mut roms = BTreeMap::new();
+    let mut errors = BTreeMap::new();
+    for version in ROM_VERSIONS {
+        let info = known.get(version).unwrap();
+        let mut candidates = reports
+            .iter()
+            .filter(|x| x.version.as_deref() == Some(version))
+            .collect::<Vec<_>>();
+        let default_name = format!("{version}.nds");
+        candidates.sort_by_key(|x| {
+            (
+                !x.blocking_problems(trust).is_empty(),
+                x.file_name() != default_name,
+            )
+        });
+
+        let Some(report) = candidates.first() else {
+            errors.insert(
+                version.to_string(),
+                format!(
+                    "找不到{}（游戏代码 {}）的 ROM，请放在 _rom/{default_name}",
+                    info.name, info.game_code
+                ),
+            );
+            continue;
+        };
+        let problems = report.blocking_problems(trust);
+        if !problems.is_empty() {
+            errors.insert(
+                version.to_string(),
+                format!(
+                    "{}的 ROM {} 不能使用：{}",
+                    info.name,
+                    report.file_name(),
+                    problems.join("；")
+                ),
+            );
+            continue;
+        }
+        for warning in &report.warnings {
+            println!("警告：{}：{warning}", report.file_name());
+        }
+        if report.file_name() != default_name {
+            println!("将 {} 识别为{}的 ROM", report.file_name(), info.name);
+        }
+        roms.insert(version.to_string(), report.path.clone());
+    }
+    (roms, errors)
+}
+
+/// 列出缺少的版本和每个文件的检查结果，`searched` 说明检查了哪些文件
+fn roms_error(
+    known: &KnownRoms,
+    reports: &[RomReport],
+    errors: &BTreeMap<String, String>,
+    searched: &str,
+) -> Error {
+    let files = if reports.is_empty() {
+        "  （没有找到 .nds 文件）".to_string()
+    } else {
+        reports
+            .iter()
+            .map(|x| format!("  {}", x.describe(known)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    anyhow!(
+        "原版 ROM 检查失败：\n{}\n{searched}：\n{files}",
+        errors
+            .values()
+            .map(|x| format!("  - {x}"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+/// 检查 `<root_path>/_rom` 下的所有 ROM，找出每个版本使用的文件
+///
+/// 任何版本找不到可用的 ROM 时报错并列出每个文件的检查结果。
+pub fn locate_roms(root_path: impl AsRef<Path>) -> Result<BTreeMap<String, PathBuf>> {
+    let root_path = root_path.as_ref();
+    let known = KnownRoms::load(root_path)?;
+    let rom_dir = root_path.join("_rom");
+    let reports = check_roms_in_dir(&known, &rom_dir)?;
+    let (roms, errors) = select_roms(&known, &reports, false);
+    if !errors.is_empty() {
+        return Err(roms_error(
+            &known,
+            &reports,
+            &errors,
+            &format!("{} 下的 ROM", rom_dir.display()),
+        ));
+    }
+    Ok(roms)
+}
+
+/// setup 使用的 ROM 查找方式，适合用户自己准备 ROM 的场合
+///
+/// 指定了 `files` 时只检查这些文件，否则查找 `_rom` 和项目根目录下的 `.nds` 文件。
+/// 仍然缺少的版本在终端中提示用户输入 ROM 的路径。`trust` 见 [`select_roms`]。
+pub fn find_roms(
+    root_path: impl AsRef<Path>,
+    files: &[PathBuf],
+    trust: bool,
+) -> Result<BTreeMap<String, PathBuf>> {
+    let root_path = root_path.as_ref();
+    let known = KnownRoms::load(root_path)?;
+    let (reports, searched) = if files.is_empty() {
+        let mut reports = Vec::new();
+        for dir in [root_path.join("_rom"), root_path.to_path_buf()] {
+            if dir.is_dir() {
+                reports.extend(check_roms_in_dir(&known, &dir)?);
+            }
+        }
+        (reports, "_rom 和项目根目录下的 ROM")
+    } else {
+        let reports = files
+            .iter()
+            .map(|x| check_rom(&known, x))
+            .collect::<Result<Vec<_>>>()?;
+        (reports, "指定的 ROM")
+    };
+    let (mut roms, mut errors) = select_roms(&known, &reports, trust);
+
+    if !errors.is_empty() && std::io::stdin().is_terminal() {
+        for (version, error) in std::mem::take(&mut errors) {
+            println!("{error}");
+            match prompt_rom(&known, &version, trust)? {
+                Some(path) => {
+                    roms.insert(version, path);
+                }
+                None => {
+                    errors.insert(version, error);
+                }
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(roms_error(&known, &reports, &errors, searched));
+    }
+    Ok(roms)
+}
+
+/// 在终端中提示输入某个版本的 ROM 路径并检查，直接回车时返回 `None`
+fn prompt_rom(known: &KnownRoms, version: &str, trust: bool) -> Result<Option<PathBuf>> {
+    let info = known.get(version).unwrap();
+    loop {
+        print!(
+            "请输入{}（游戏代码 {}）的 ROM 路径，可以把文件拖放到此窗口，直接回车跳过：",
+            info.name, info.game_code
+        );
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        // 拖放文件时路径可能带引号
+        let path = line.trim().trim_matches(|c| c == '"' || c == '\'');
+        if path.is_empty() {
+            return Ok(None);
+        }
+        let report = match check_rom(known, path) {
+            Result::Ok(report) => report,
+            Err(err) => {
+                println!("{err:#}");
+                continue;
+            }
+        };
+        if report.version.as_deref() != Some(version) || !report.blocking_problems(trust).is_empty()
+        {
+            println!(
+                "不能作为{}的 ROM 使用：{}",
+                info.name,
+                report.describe(known)
+            );
+            continue;
+        }
+        for warning in &report.warnings {
+            println!("警告：{warning}");
+        }
+        return Ok(Some(report.path));
+    }
+}
+
+/// 把 ROM 的 SHA-1 和标题记录到 `<root_path>/roms.toml`，用于收录已确认完好的 dump
+pub fn trust_roms(root_path: impl AsRef<Path>, roms: &BTreeMap<String, PathBuf>) -> Result<()> {
+    let root_path = root_path.as_ref();
+    let mut known = KnownRoms::load(root_path)?;
+    for rom in roms.values() {
+        let report = check_rom(&known, rom)?;
+        println!("记录 {} 的 SHA-1 {}", rom.display(), report.sha1);
+        known.trust(&report);
+    }
+    known.save(root_path.join(KNOWN_ROMS_FILE), KNOWN_ROMS_HEADER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::nds_rom::crc16;
+
+    const ROM_SIZE: usize = 0x1000;
+    const USED_SIZE: usize = 0x800;
+
+    /// 生成只有头部的 ROM，头部之后按游戏代码填充，使不同版本的 SHA-1 不同
+    fn make_rom(game_code: &str) -> Vec<u8> {
+        let mut rom = vec![game_code.as_bytes()[3]; ROM_SIZE];
+        rom[..HEADER_SIZE].fill(0);
+        rom[..9].copy_from_slice(b"TEST GAME");
+        rom[0x0C..0x10].copy_from_slice(game_code.as_bytes());
+        rom[0x80..0x84].copy_from_slice(&(USED_SIZE as u32).to_le_bytes());
+        let crc = crc16(&rom[..0x15E]);
+        rom[0x15E..0x160].copy_from_slice(&crc.to_le_bytes());
+        rom
+    }
+
+    fn known_roms(with_sha1: bool) -> KnownRoms {
+        let rom = |name: &str, game_code: &str| KnownRom {
+            name: name.to_string(),
+            game_code: game_code.to_string(),
+            title: Some("TEST GAME".to_string()),
+            sha1: if with_sha1 {
+                vec![FileHashes::new(&make_rom(game_code)).sha1]
+            } else {
+                Vec::new()
+            },
+        };
+        KnownRoms(BTreeMap::from([
+            ("ninja".to_string(), rom("忍者版", "YRVJ")),
+            ("saurian".to_string(), rom("恐龙版", "YRWJ")),
+        ]))
+    }
+
+    /// 在临时文件夹中写入 ROM 并检查
+    fn check(test: &str, files: &[(&str, Vec<u8>)], known: &KnownRoms) -> Vec<RomReport> {
+        let dir =
+            std::env::temp_dir().join(format!("rom-check-test-{test}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, data) in files {
+            std::fs::write(dir.join(name), data).unwrap();
+        }
+        let reports = check_roms_in_dir(known, &dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        reports
+    }
+
+    #[test]
+    fn swapped_files_are_identified_by_game_code() {
+        let known = known_roms(true);
+        let reports = check(
+            "swapped",
+            &[
+                ("ninja.nds", make_rom("YRWJ")),
+                ("saurian.nds", make_rom("YRVJ")),
+            ],
+            &known,
+        );
+        let (roms, errors) = select_roms(&known, &reports, false);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert!(roms["ninja"].ends_with("saurian.nds"));
+        assert!(roms["saurian"].ends_with("ninja.nds"));
+    }
+
+    #[test]
+    fn wrong_game_code_is_not_used() {
+        let known = known_roms(true);
+        let reports = check(
+            "game-code",
+            &[
+                ("ninja.nds", make_rom("ABCJ")),
+                ("saurian.nds", make_rom("YRWJ")),
+            ],
+            &known,
+        );
+        assert_eq!(reports[0].version, None);
+        let (roms, errors) = select_roms(&known, &reports, false);
+        assert!(errors["ninja"].contains("YRVJ"));
+        assert!(roms.contains_key("saurian"));
+    }
+
+    #[test]
+    fn trimmed_dump_is_rejected() {
+        let mut trimmed = make_rom("YRVJ");
+        trimmed.truncate(USED_SIZE);
+        for with_sha1 in [true, false] {
+            let known = known_roms(with_sha1);
+            let reports = check(
+                &format!("trimmed-{with_sha1}"),
+                &[("ninja.nds", trimmed.clone())],
+                &known,
+            );
+            assert!(reports[0].problems.iter().any(|x| x.contains("裁剪")));
+            let (_, errors) = select_roms(&known, &reports, true);
+            assert!(errors["ninja"].contains("裁剪"), "{errors:?}");
+        }
+    }
+
+    #[test]
+    fn bad_header_crc_is_rejected() {
+        let mut rom = make_rom("YRVJ");
+        rom[0x15E] ^= 0xFF;
+        let known = known_roms(true);
+        let reports = check("crc", &[("ninja.nds", rom)], &known);
+        assert!(reports[0]
+            .problems
+            .iter()
+            .any(|x| x.contains("头部校验值错误")));
+        let (roms, errors) = select_roms(&known, &reports, false);
+        assert!(!roms.contains_key("ninja"));
+        assert!(errors["ninja"].contains("头部校验值错误"));
+    }
+
+    #[test]
+    fn unknown_sha1_needs_trust() {
+        let known = known_roms(false);
+        let reports = check(
+            "trust",
+            &[
+                ("ninja.nds", make_rom("YRVJ")),
+                ("saurian.nds", make_rom("YRWJ")),
+            ],
+            &known,
+        );
+        let (roms, errors) = select_roms(&known, &reports, false);
+        assert!(roms.is_empty());
+        assert!(errors["ninja"].contains("--trust"));
+
+        let (roms, errors) = select_roms(&known, &reports, true);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(roms.len(), 2);
+
+        let mut known = known;
+        known.trust(&reports[0]);
+        let reports = check("trusted", &[("ninja.nds", make_rom("YRVJ"))], &known);
+        assert!(!reports[0].unverified && reports[0].problems.is_empty());
+    }
+}
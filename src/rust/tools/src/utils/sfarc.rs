@@ -0,0 +1,334 @@
+//! 流星之洛克人系列 `data/datbin/*.bin` 归档文件的读写
+//!
+//! 归档开头为文件表，每项 8 字节：数据偏移 `u32` 和数据大小 `u32`，
+//! 大小的最高位为 1 时表示该项数据经过 LZ77 压缩。文件表的项数由第一个数据偏移决定，
+//! 偏移和大小都为 0 的项为空项。各项数据按 4 字节对齐存放。
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context};
+
+use super::lz77;
+
+const COMPRESSED_FLAG: u32 = 0x8000_0000;
+const ENTRY_ALIGN: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SfArchiveEntry {
+    /// 解压后的数据
+    data: Vec<u8>,
+    compressed: bool,
+    // 读取时归档中原始存放的数据，数据未改动时重新打包会直接使用，以保证输出一致
+    raw: Option<Vec<u8>>,
+}
+
+impl SfArchiveEntry {
+    pub fn new(data: Vec<u8>, compressed: bool) -> Self {
+        Self {
+            data,
+            compressed,
+            raw: None,
+        }
+    }
+
+    /// 解压后的数据
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// 替换数据，之后重新打包时会重新压缩
+    pub fn set_data(&mut self, data: Vec<u8>) {
+        self.data = data;
+        self.raw = None;
+    }
+
+    fn stored_data(&self) -> Vec<u8> {
+        match &self.raw {
+            Some(raw) => raw.clone(),
+            None if self.compressed => lz77::compress(&self.data),
+            None => self.data.clone(),
+        }
+    }
+
+    /// 数据在归档中实际占用的大小
+    pub fn stored_size(&self) -> usize {
+        match &self.raw {
+            Some(raw) => raw.len(),
+            None => self.stored_data().len(),
+        }
+    }
+}
+
+/// 打包时相比参考归档变大了的项
+#[derive(Debug, Clone)]
+pub struct GrownEntry {
+    pub index: usize,
+    pub old_size: usize,
+    pub new_size: usize,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SfArchive {
+    pub entries: Vec<Option<SfArchiveEntry>>,
+}
+
+impl SfArchive {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let data =
+            std::fs::read(path).with_context(|| format!("无法读取归档文件 {}", path.display()))?;
+        Self::from_bytes(&data).with_context(|| format!("无法解析归档文件 {}", path.display()))
+    }
+
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        let read_u32 = |pos: usize| -> anyhow::Result<u32> {
+            let bytes = data.get(pos..pos + 4).context("归档文件表超出文件范围")?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+
+        let mut table_end = data.len();
+        let mut pos = 0;
+        let mut entries = Vec::new();
+        while pos + 8 <= table_end {
+            let offset = read_u32(pos)? as usize;
+            let size = read_u32(pos + 4)?;
+            pos += 8;
+
+            if offset == 0 && size == 0 {
+                entries.push(None);
+                continue;
+            }
+
+            table_end = table_end.min(offset);
+            let compressed = size & COMPRESSED_FLAG != 0;
+            let size = (size & !COMPRESSED_FLAG) as usize;
+            let raw = data
+                .get(offset..offset + size)
+                .with_context(|| format!("第 {} 项数据超出文件范围", entries.len()))?
+                .to_vec();
+            let entry_data = if compressed {
+                lz77::decompress(&raw)
+                    .with_context(|| format!("无法解压第 {} 项数据", entries.len()))?
+            } else {
+                raw.clone()
+            };
+            entries.push(Some(SfArchiveEntry {
+                data: entry_data,
+                compressed,
+                raw: Some(raw),
+            }));
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let table_size = self.entries.len() * 8;
+        let mut table = Vec::with_capacity(table_size);
+        let mut body = Vec::new();
+
+        for entry in &self.entries {
+            match entry {
+                Some(entry) => {
+                    let stored = entry.stored_data();
+                    let offset = table_size + body.len();
+                    let mut size = stored.len() as u32;
+                    if entry.compressed {
+                        size |= COMPRESSED_FLAG;
+                    }
+                    table.extend_from_slice(&(offset as u32).to_le_bytes());
+                    table.extend_from_slice(&size.to_le_bytes());
+                    body.extend_from_slice(&stored);
+                    while body.len() % ENTRY_ALIGN != 0 {
+                        body.push(0);
+                    }
+                }
+                None => {
+                    table.extend_from_slice(&0u32.to_le_bytes());
+                    table.extend_from_slice(&0u32.to_le_bytes());
+                }
+            }
+        }
+
+        table.extend_from_slice(&body);
+        table
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_bytes())
+            .with_context(|| format!("无法写入归档文件 {}", path.display()))
+    }
+
+    /// 生成解包后的文件名，例如 `capcomlogo_local_00.bin`，序号至少两位
+    pub fn entry_file_name(stem: &str, index: usize, count: usize) -> String {
+        let width = count.saturating_sub(1).to_string().len().max(2);
+        format!("{stem}_{index:0width$}.bin")
+    }
+
+    /// 将所有非空项解包到文件夹中，数据为空的项也会写出空文件，以便重新打包时保留
+    pub fn extract(&self, stem: &str, dir: impl AsRef<Path>) -> anyhow::Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        for (i, entry) in self.entries.iter().enumerate() {
+            if let Some(entry) = entry {
+                let file_name = Self::entry_file_name(stem, i, self.entries.len());
+                std::fs::write(dir.join(&file_name), &entry.data)
+                    .with_context(|| format!("无法写出归档项 {file_name}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 从解包文件夹中读取所有项，文件名需以 `_序号` 结尾
+    ///
+    /// 如果提供了参考归档，项数、压缩标记都会沿用参考归档，内容未改动的项会直接使用原始数据；
+    /// 否则新出现的项根据 `compress` 决定是否压缩。
+    pub fn from_dir(
+        dir: impl AsRef<Path>,
+        reference: Option<&SfArchive>,
+        compress: bool,
+    ) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        let mut files = Vec::<(usize, PathBuf)>::new();
+        for entry in std::fs::read_dir(dir)
+            .with_context(|| format!("无法读取解包文件夹 {}", dir.display()))?
+            .flatten()
+        {
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+            let stem = path
+                .file_stem()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let index = stem
+                .rsplit_once('_')
+                .and_then(|(_, index)| index.parse::<usize>().ok());
+            if let Some(index) = index {
+                files.push((index, path));
+            }
+        }
+
+        let count = files
+            .iter()
+            .map(|x| x.0 + 1)
+            .max()
+            .unwrap_or_default()
+            .max(reference.map(|x| x.entries.len()).unwrap_or_default());
+        let mut entries = vec![None; count];
+
+        for (index, path) in files {
+            let data = std::fs::read(&path)
+                .with_context(|| format!("无法读取归档项 {}", path.display()))?;
+            let reference_entry = reference
+                .and_then(|x| x.entries.get(index))
+                .and_then(|x| x.as_ref());
+            entries[index] = Some(match reference_entry {
+                Some(reference_entry) if reference_entry.data == data => reference_entry.clone(),
+                Some(reference_entry) => SfArchiveEntry::new(data, reference_entry.compressed),
+                None => SfArchiveEntry::new(data, compress),
+            });
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// 列出相比参考归档占用空间变大了的项
+    pub fn grown_entries(&self, reference: &SfArchive) -> Vec<GrownEntry> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| {
+                let new_size = entry.as_ref()?.stored_size();
+                let old_size = reference
+                    .entries
+                    .get(index)
+                    .and_then(|x| x.as_ref())
+                    .map(|x| x.stored_size())
+                    .unwrap_or_default();
+                (new_size > old_size).then_some(GrownEntry {
+                    index,
+                    old_size,
+                    new_size,
+                })
+            })
+            .collect()
+    }
+}
+
+/// 解包归档文件到文件夹，文件夹内的文件以归档文件名（不含扩展名）加序号命名
+pub fn extract_archive(input: impl AsRef<Path>, output: impl AsRef<Path>) -> anyhow::Result<()> {
+    let input = input.as_ref();
+    let stem = input
+        .file_stem()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    SfArchive::open(input)?.extract(&stem, output)
+}
+
+/// 将解包文件夹重新打包为归档文件，返回相比参考归档变大了的项
+pub fn pack_archive(
+    input: impl AsRef<Path>,
+    reference: Option<impl AsRef<Path>>,
+    output: impl AsRef<Path>,
+) -> anyhow::Result<Vec<GrownEntry>> {
+    let reference = reference.map(SfArchive::open).transpose()?;
+    let archive = SfArchive::from_dir(input, reference.as_ref(), true)?;
+    ensure!(!archive.entries.is_empty(), "没有可以打包的归档项");
+    archive.save(output)?;
+    Ok(reference
+        .map(|reference| archive.grown_entries(&reference))
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_archive() -> SfArchive {
+        let text = b"sfarc round trip ".repeat(64);
+        SfArchive {
+            entries: vec![
+                Some(SfArchiveEntry::new(text.clone(), true)),
+                None,
+                Some(SfArchiveEntry::new(Vec::new(), false)),
+                Some(SfArchiveEntry::new(vec![1, 2, 3], false)),
+                Some(SfArchiveEntry::new(text, false)),
+                None,
+            ],
+        }
+    }
+
+    #[test]
+    fn extract_and_repack_unchanged() {
+        let bytes = sample_archive().to_bytes();
+        let reference = SfArchive::from_bytes(&bytes).unwrap();
+        assert_eq!(reference.entries.len(), 6);
+
+        let dir = std::env::temp_dir().join(format!("sfarc-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        reference.extract("test", &dir).unwrap();
+        let repacked = SfArchive::from_dir(&dir, Some(&reference), true).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(repacked.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn set_data_discards_raw() {
+        let mut archive = SfArchive::from_bytes(&sample_archive().to_bytes()).unwrap();
+        let entry = archive.entries[0].as_mut().unwrap();
+        entry.set_data(b"changed".to_vec());
+
+        let archive = SfArchive::from_bytes(&archive.to_bytes()).unwrap();
+        let entry = archive.entries[0].as_ref().unwrap();
+        assert_eq!(entry.data(), b"changed");
+        assert!(entry.compressed());
+    }
+}
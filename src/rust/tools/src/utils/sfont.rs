@@ -0,0 +1,148 @@
+//! `gen-font` 使用的 `.sfont` 字形集
+//!
+//! 文件开头为单元格宽 `u8`、单元格高 `u8` 和字形数 `u32`，之后每个字形为
+//! Unicode 码位 `u32`、字宽 `u8`、字高 `u8`、每行像素数 `u8`（等于单元格宽），
+//! 再跟随单元格宽乘高个 2 位像素，每字节从低位开始存放，按行排列。
+//! 像素值 1 为字形本身，2 为阴影。
+
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+
+pub const PIXEL_EMPTY: u8 = 0;
+pub const PIXEL_FOREGROUND: u8 = 1;
+pub const PIXEL_SHADOW: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SfontGlyph {
+    pub code: u32,
+    /// 字宽，即绘制后光标前进的像素数
+    pub width: u8,
+    pub height: u8,
+    /// 单元格内的像素，按行排列
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Sfont {
+    pub cell_width: u8,
+    pub cell_height: u8,
+    pub glyphs: Vec<SfontGlyph>,
+}
+
+impl SfontGlyph {
+    pub fn new(code: u32, width: u8, cell_width: u8, cell_height: u8) -> Self {
+        Self {
+            code,
+            width,
+            height: cell_height,
+            pixels: vec![PIXEL_EMPTY; cell_width as usize * cell_height as usize],
+        }
+    }
+}
+
+impl Sfont {
+    pub fn new(cell_width: u8, cell_height: u8) -> Self {
+        Self {
+            cell_width,
+            cell_height,
+            glyphs: Vec::new(),
+        }
+    }
+
+    fn cell_size(&self) -> usize {
+        self.cell_width as usize * self.cell_height as usize
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let data =
+            std::fs::read(path).with_context(|| format!("无法读取字形集 {}", path.display()))?;
+        Self::from_bytes(&data).with_context(|| format!("无法解析字形集 {}", path.display()))
+    }
+
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        ensure!(data.len() >= 6, "字形集文件过短");
+        let mut font = Self::new(data[0], data[1]);
+        let count = u32::from_le_bytes([data[2], data[3], data[4], data[5]]) as usize;
+        let packed_size = font.cell_size().div_ceil(4);
+        let mut pos = 6;
+        for i in 0..count {
+            let record = data
+                .get(pos..pos + 7 + packed_size)
+                .with_context(|| format!("第 {i} 个字形超出文件范围"))?;
+            let stride = record[6];
+            ensure!(
+                stride == font.cell_width,
+                "第 {i} 个字形的行宽 {stride} 与单元格宽 {} 不一致",
+                font.cell_width
+            );
+            let pixels = (0..font.cell_size())
+                .map(|j| (record[7 + j / 4] >> ((j % 4) * 2)) & 3)
+                .collect();
+            font.glyphs.push(SfontGlyph {
+                code: u32::from_le_bytes([record[0], record[1], record[2], record[3]]),
+                width: record[4],
+                height: record[5],
+                pixels,
+            });
+            pos += 7 + packed_size;
+        }
+        Ok(font)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let packed_size = self.cell_size().div_ceil(4);
+        let mut output = Vec::with_capacity(6 + self.glyphs.len() * (7 + packed_size));
+        output.push(self.cell_width);
+        output.push(self.cell_height);
+        output.extend_from_slice(&(self.glyphs.len() as u32).to_le_bytes());
+        for glyph in &self.glyphs {
+            output.extend_from_slice(&glyph.code.to_le_bytes());
+            output.push(glyph.width);
+            output.push(glyph.height);
+            output.push(self.cell_width);
+            let mut packed = vec![0u8; packed_size];
+            for (j, pixel) in glyph.pixels.iter().enumerate() {
+                packed[j / 4] |= (pixel & 3) << ((j % 4) * 2);
+            }
+            output.extend_from_slice(&packed);
+        }
+        output
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_bytes())
+            .with_context(|| format!("无法写入字形集 {}", path.display()))
+    }
+
+    /// 在字形右下方添加阴影，`bold` 为真时阴影同时向右、向下扩展，对应 `.shadow.bold` 字形集
+    pub fn add_shadow(&mut self, bold: bool) {
+        let offsets: &[(i32, i32)] = if bold {
+            &[(1, 1), (1, 0), (0, 1)]
+        } else {
+            &[(1, 1)]
+        };
+        let (w, h) = (self.cell_width as i32, self.cell_height as i32);
+        for glyph in &mut self.glyphs {
+            let source = glyph.pixels.clone();
+            for y in 0..h {
+                for x in 0..w {
+                    if source[(y * w + x) as usize] != PIXEL_FOREGROUND {
+                        continue;
+                    }
+                    for (dx, dy) in offsets {
+                        let (tx, ty) = (x + dx, y + dy);
+                        if tx < w && ty < h {
+                            let target = &mut glyph.pixels[(ty * w + tx) as usize];
+                            if *target == PIXEL_EMPTY {
+                                *target = PIXEL_SHADOW;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
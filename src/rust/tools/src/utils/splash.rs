@@ -0,0 +1,155 @@
+//! 启动页面配置，描述游戏启动时依次显示的启动画面
+//!
+//! 配置文件位于 `images/splash-pages.yaml`，不存在时只显示 `splash-screen.{version}.png`。
+//! 打包时每个页面的图像会以去掉版本名的文件名放入 ROM 的 `data` 文件夹，
+//! 并生成 `splash-pages.bin` 供 arm9 的 splash_screen.rs 读取，格式见该文件。
+
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+/// 启动页面配置文件，位于 `images` 文件夹下
+pub const SPLASH_PAGES_FILE: &str = "splash-pages.yaml";
+/// 放入 ROM 的启动页面描述文件名
+pub const SPLASH_PAGES_BIN: &str = "splash-pages.bin";
+
+const PAGES_MAGIC: &[u8; 4] = b"SPLP";
+const PAGES_VERSION: u8 = 1;
+const PAGE_NAME_SIZE: usize = 32;
+const MAX_PAGES: usize = 16;
+
+const PAGE_FLAG_ANY_KEY: u8 = 1 << 0;
+
+fn default_fade() -> u8 {
+    16
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SplashPage {
+    /// `images` 文件夹下的源图像文件名，`{version}` 会被替换为 `ninja` 或 `saurian`
+    pub image: String,
+    /// 淡入帧数，为 0 时立即显示
+    #[serde(default = "default_fade")]
+    pub fade_in: u8,
+    /// 淡出帧数，为 0 时立即切换
+    #[serde(default = "default_fade")]
+    pub fade_out: u8,
+    /// 自动切换到下一页前等待的帧数，60 帧为 1 秒，不填时一直等待按键
+    #[serde(default)]
+    pub timeout: Option<u16>,
+    /// 按任意键跳过，否则只响应 A 键
+    #[serde(default)]
+    pub skip_on_any_key: bool,
+}
+
+impl SplashPage {
+    /// 指定版本的源图像路径
+    pub fn source_image(&self, images_path: impl AsRef<Path>, version: &str) -> PathBuf {
+        images_path
+            .as_ref()
+            .join(self.image.replace("{version}", version))
+    }
+
+    /// rnr2cn gen-splash-screen 生成的图块数据路径
+    pub fn output_bin(&self, images_path: impl AsRef<Path>, version: &str) -> PathBuf {
+        self.source_image(images_path, version)
+            .with_extension("bin")
+    }
+
+    /// 放入 ROM 时使用的文件名，去掉了文件名中的版本
+    pub fn rom_name(&self) -> String {
+        let name = self
+            .image
+            .replace(".{version}", "")
+            .replace("{version}", "");
+        Path::new(&name)
+            .with_extension("bin")
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SplashPages {
+    pub pages: Vec<SplashPage>,
+}
+
+impl Default for SplashPages {
+    fn default() -> Self {
+        Self {
+            pages: vec![SplashPage {
+                image: "splash-screen.{version}.png".into(),
+                fade_in: default_fade(),
+                fade_out: default_fade(),
+                timeout: None,
+                skip_on_any_key: false,
+            }],
+        }
+    }
+}
+
+impl SplashPages {
+    /// 读取 `images` 文件夹下的启动页面配置，配置文件不存在时返回默认配置
+    pub fn load(images_path: impl AsRef<Path>) -> Result<Self> {
+        let path = images_path.as_ref().join(SPLASH_PAGES_FILE);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let pages: Self = serde_yaml::from_reader(
+            std::fs::File::open(&path)
+                .with_context(|| format!("无法打开启动页面配置 {}", path.display()))?,
+        )
+        .with_context(|| format!("无法解析启动页面配置 {}", path.display()))?;
+
+        ensure!(!pages.pages.is_empty(), "启动页面配置中没有任何页面");
+        ensure!(
+            pages.pages.len() <= MAX_PAGES,
+            "启动页面最多 {MAX_PAGES} 页，配置中有 {} 页",
+            pages.pages.len()
+        );
+        for (i, page) in pages.pages.iter().enumerate() {
+            let rom_name = page.rom_name();
+            ensure!(
+                !rom_name.is_empty() && rom_name.len() < PAGE_NAME_SIZE,
+                "第 {} 页的文件名 {rom_name:?} 无效，最长 {} 字节",
+                i + 1,
+                PAGE_NAME_SIZE - 1
+            );
+            ensure!(
+                page.timeout != Some(0),
+                "第 {} 页的 timeout 不能为 0，一直等待按键时请不要填写",
+                i + 1
+            );
+        }
+        Ok(pages)
+    }
+
+    /// 生成放入 ROM 的启动页面描述文件
+    pub fn to_descriptor(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(8 + self.pages.len() * 40);
+        data.extend_from_slice(PAGES_MAGIC);
+        data.push(PAGES_VERSION);
+        data.push(self.pages.len() as u8);
+        data.extend_from_slice(&[0; 2]);
+        for page in &self.pages {
+            let mut name = [0u8; PAGE_NAME_SIZE];
+            let rom_name = page.rom_name();
+            name[..rom_name.len()].copy_from_slice(rom_name.as_bytes());
+            data.extend_from_slice(&name);
+            data.push(page.fade_in);
+            data.push(page.fade_out);
+            data.push(if page.skip_on_any_key {
+                PAGE_FLAG_ANY_KEY
+            } else {
+                0
+            });
+            data.push(0);
+            data.extend_from_slice(&page.timeout.unwrap_or(0).to_le_bytes());
+            data.extend_from_slice(&[0; 2]);
+        }
+        data
+    }
+}
@@ -0,0 +1,291 @@
+//! textpet 使用的 `.tbl` 字符表
+//!
+//! 每行格式为 `十六进制编码=文本`，例如 `E9=\n`、`D12F= `，文本部分不做任何裁剪。
+//! 编码部分是游戏脚本中的原始字节，单字节或 `0xD0..=0xE4` 开头的双字节。
+//! 文本 `=` 在文件中写作 `\=`。
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::{bail, ensure, Context};
+
+/// 双字节编码第一个字节的范围，`0xE4` 开头的编码为原版游戏的固定编码，不用于分配新字符
+const TWO_BYTE_FIRST: std::ops::RangeInclusive<u8> = 0xD0..=0xE3;
+/// 双字节编码第二个字节的取值个数
+const TWO_BYTE_SECOND_COUNT: u32 = 0xE4;
+
+/// 按 `arm9::script::decode_script` 的规则将编码转换为字库中的字符序号
+pub fn code_to_glyph_index(code: &[u8]) -> Option<u32> {
+    match *code {
+        [x] if x < 0xD0 => Some(x as u32),
+        [0xE4, second] => Some(0xE4 + second as u32),
+        [first, second]
+            if TWO_BYTE_FIRST.contains(&first) && (second as u32) < TWO_BYTE_SECOND_COUNT =>
+        {
+            Some((first - 0xD0) as u32 * TWO_BYTE_SECOND_COUNT + second as u32 + 0xD0)
+        }
+        _ => None,
+    }
+}
+
+/// 将字库中的字符序号转换为双字节编码，超出编码空间时返回 `None`
+pub fn glyph_index_to_code(index: u32) -> Option<Vec<u8>> {
+    let offset = index.checked_sub(0xD0)?;
+    let first = 0xD0 + offset / TWO_BYTE_SECOND_COUNT;
+    let second = offset % TWO_BYTE_SECOND_COUNT;
+    (first <= *TWO_BYTE_FIRST.end() as u32).then(|| vec![first as u8, second as u8])
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct TextTable {
+    /// 按文件中的顺序保存的所有项
+    pub entries: Vec<(Vec<u8>, String)>,
+    code_to_text: HashMap<Vec<u8>, usize>,
+    text_to_code: HashMap<String, usize>,
+    max_text_len: usize,
+}
+
+pub fn parse_hex_bytes(hex: &str) -> anyhow::Result<Vec<u8>> {
+    let hex = hex.trim();
+    ensure!(
+        !hex.is_empty() && hex.len().is_multiple_of(2),
+        "十六进制编码 {hex:?} 长度无效"
+    );
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .with_context(|| format!("十六进制编码 {hex:?} 无效"))
+        })
+        .collect()
+}
+
+pub fn to_hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{x:02X}")).collect()
+}
+
+impl TextTable {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("无法读取字符表 {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("无法解析字符表 {}", path.display()))
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let mut table = Self::default();
+        for (line_no, line) in content.lines().enumerate() {
+            let line = line.trim_start_matches('\u{feff}').trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            let (code, text) = line
+                .split_once('=')
+                .with_context(|| format!("第 {} 行缺少等号", line_no + 1))?;
+            let code =
+                parse_hex_bytes(code).with_context(|| format!("第 {} 行编码无效", line_no + 1))?;
+            let text = if text == "\\=" { "=" } else { text };
+            table.push(code, text.to_owned());
+        }
+        Ok(table)
+    }
+
+    /// 添加一项，编码或文本重复时保留最先出现的映射
+    pub fn push(&mut self, code: Vec<u8>, text: String) {
+        let index = self.entries.len();
+        self.code_to_text.entry(code.clone()).or_insert(index);
+        self.text_to_code.entry(text.clone()).or_insert(index);
+        self.max_text_len = self.max_text_len.max(text.chars().count());
+        self.entries.push((code, text));
+    }
+
+    pub fn get_text(&self, code: &[u8]) -> Option<&str> {
+        self.code_to_text
+            .get(code)
+            .map(|&x| self.entries[x].1.as_str())
+    }
+
+    pub fn get_code(&self, text: &str) -> Option<&[u8]> {
+        self.text_to_code
+            .get(text)
+            .map(|&x| self.entries[x].0.as_slice())
+    }
+
+    pub fn contains_text(&self, text: &str) -> bool {
+        self.text_to_code.contains_key(text)
+    }
+
+    /// 文本中最长的一项的字符数，编码时用于最长匹配
+    pub fn max_text_len(&self) -> usize {
+        self.max_text_len
+    }
+
+    /// 从数据开头解码一个字符，优先匹配双字节编码，返回文本和占用的字节数
+    pub fn decode_one(&self, data: &[u8]) -> Option<(&str, usize)> {
+        if data.len() >= 2 {
+            if let Some(text) = self.get_text(&data[..2]) {
+                return Some((text, 2));
+            }
+        }
+        data.first()
+            .and_then(|x| self.get_text(std::slice::from_ref(x)))
+            .map(|x| (x, 1))
+    }
+
+    /// 从文本开头按最长匹配编码一项，返回编码和占用的字符数
+    pub fn encode_one(&self, text: &str) -> Option<(&[u8], usize)> {
+        let char_count = text.chars().count();
+        for len in (1..=self.max_text_len.min(char_count)).rev() {
+            let end = text
+                .char_indices()
+                .nth(len)
+                .map(|x| x.0)
+                .unwrap_or(text.len());
+            if let Some(code) = self.get_code(&text[..end]) {
+                return Some((code, len));
+            }
+        }
+        None
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let mut content = String::with_capacity(self.entries.len() * 8);
+        for (code, text) in &self.entries {
+            content.push_str(&to_hex_string(code));
+            content.push('=');
+            content.push_str(if text == "=" { "\\=" } else { text });
+            content.push('\n');
+        }
+        std::fs::write(path, content).with_context(|| format!("无法写入字符表 {}", path.display()))
+    }
+
+    /// 以本表为基础生成新字符表：保留本表的所有编码，为本表中没有的字符按码位顺序分配新的双字节编码，
+    /// 新编码的字符序号从本表已用的最大序号之后开始，输出按编码长度和编码排序
+    pub fn generate(&self, chars: &BTreeSet<char>) -> anyhow::Result<Self> {
+        let mut used = self
+            .entries
+            .iter()
+            .filter_map(|x| code_to_glyph_index(&x.0))
+            .collect::<HashSet<_>>();
+        let mut next_index = used.iter().max().map(|x| x + 1).unwrap_or(0xD0).max(0xD0);
+
+        let new_chars = chars
+            .iter()
+            .map(|x| x.to_string())
+            .filter(|x| !self.contains_text(x))
+            .collect::<Vec<_>>();
+        let mut entries = self.entries.clone();
+        for (i, text) in new_chars.iter().enumerate() {
+            while used.contains(&next_index) {
+                next_index += 1;
+            }
+            let Some(code) = glyph_index_to_code(next_index) else {
+                bail!(
+                    "字符表编码空间不足：共有 {} 个新字符，只能再分配 {} 个，请减少脚本中使用的字符",
+                    new_chars.len(),
+                    i
+                );
+            };
+            used.insert(next_index);
+            entries.push((code, text.clone()));
+        }
+        entries.sort_by(|a, b| (a.0.len(), &a.0).cmp(&(b.0.len(), &b.0)));
+
+        let mut table = Self::default();
+        for (code, text) in entries {
+            table.push(code, text);
+        }
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "00=　\n01=０\nE9=\\n\nD000=ぜ\nD001=ず\nE400=ぅ\nE401=ぁ\n";
+
+    /// 按 `(first - 0xD0) * 0xE4 + second + 0xD0` 计算双字节编码的字符序号
+    fn expected_index(code: &[u8]) -> u32 {
+        (code[0] as u32 - 0xD0) * 0xE4 + code[1] as u32 + 0xD0
+    }
+
+    fn cjk_chars(count: u32) -> BTreeSet<char> {
+        (0x4E00..0x4E00 + count)
+            .map(|x| char::from_u32(x).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn generate_keeps_base_codes() {
+        let base = TextTable::parse(BASE).unwrap();
+        let chars = "ぜぅ中文字".chars().collect::<BTreeSet<_>>();
+        let table = base.generate(&chars).unwrap();
+
+        for (code, text) in &base.entries {
+            assert_eq!(table.get_code(text), Some(code.as_slice()), "{text}");
+        }
+        // 新字符从本表已用的最大序号之后开始分配
+        let used_max = code_to_glyph_index(&[0xE4, 0x01]).unwrap();
+        let mut indices = Vec::new();
+        for text in ["中", "文", "字"] {
+            let code = table.get_code(text).unwrap();
+            assert_eq!(code.len(), 2, "{text}");
+            assert!(
+                (0xD0..=0xE3).contains(&code[0]) && code[1] < 0xE4,
+                "{code:02X?}"
+            );
+            let index = code_to_glyph_index(code).unwrap();
+            assert_eq!(index, expected_index(code));
+            assert!(index > used_max, "{text}");
+            indices.push(index);
+        }
+        // 按码位顺序分配
+        assert_eq!(indices, [used_max + 1, used_max + 3, used_max + 2]);
+        assert_eq!(table.entries.len(), base.entries.len() + 3);
+    }
+
+    #[test]
+    fn glyph_index_round_trip() {
+        for code in 0..0xD0u8 {
+            assert_eq!(code_to_glyph_index(&[code]), Some(code as u32));
+        }
+        assert_eq!(code_to_glyph_index(&[0xD0]), None);
+        assert_eq!(code_to_glyph_index(&[0xE4, 0x10]), Some(0xE4 + 0x10));
+        assert_eq!(code_to_glyph_index(&[0xD0, 0xE4]), None);
+        assert_eq!(code_to_glyph_index(&[0xE5, 0x00]), None);
+
+        assert_eq!(glyph_index_to_code(0xCF), None);
+        let last = 0xD0 + 20 * 0xE4 - 1;
+        for index in 0xD0..=last {
+            let code = glyph_index_to_code(index).unwrap();
+            assert_eq!(expected_index(&code), index);
+            assert_eq!(code_to_glyph_index(&code), Some(index));
+        }
+        assert_eq!(glyph_index_to_code(last), Some(vec![0xE3, 0xE3]));
+        assert_eq!(glyph_index_to_code(last + 1), None);
+    }
+
+    #[test]
+    fn generate_reports_exhausted_code_space() {
+        let capacity = 20 * 0xE4;
+        let empty = TextTable::default();
+        let table = empty.generate(&cjk_chars(capacity)).unwrap();
+        assert_eq!(table.entries.len(), capacity as usize);
+        assert_eq!(table.entries.last().unwrap().0, [0xE3, 0xE3]);
+
+        let err = empty.generate(&cjk_chars(capacity + 5)).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("编码空间不足"), "{message}");
+        assert!(
+            message.contains(&format!(
+                "共有 {} 个新字符，只能再分配 {capacity} 个",
+                capacity + 5
+            )),
+            "{message}"
+        );
+    }
+}
@@ -1,11 +1,17 @@
-use std::{io::Write, path::PathBuf};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 use anyhow::ensure;
 use image::RgbImage;
 use serde::{Deserialize, Serialize};
 use sfbase::{GBAColor, Tile, Tile4BPP, Tile8BPP};
 
-use super::buildin_palette::BUILDIN_COLOR_PALETTES;
+use super::{
+    buildin_palette::BUILDIN_COLOR_PALETTES,
+    color_match::{self, ColorMatching, TileRegion},
+};
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct TilesetMeta {
@@ -20,20 +26,281 @@ pub struct TilemapMeta {
     pub output_file: PathBuf,
     pub width: usize,
     pub height: usize,
+    /// 使用的图块集序号，不填时图块集只有一个则使用它，否则与图块表一一对应
+    #[serde(default)]
+    pub tileset: Option<usize>,
+    /// 以图块为单位的位置，不填时放在之前所有图块表的下方
+    #[serde(default)]
+    pub position: Option<(usize, usize)>,
+    /// 没有图块表文件，图块集中的图块从左到右、从上到下按顺序排列
+    #[serde(default)]
+    pub sequential: bool,
+}
+
+/// 从图像的区域中提取 4bpp 调色板，每个图块的颜色都放入同一组 16 色调色板，每组的颜色 0 为透明色
+pub fn extract_palette_banks(
+    img: &RgbImage,
+    regions: &[TileRegion],
+    transparent: GBAColor,
+) -> anyhow::Result<Vec<(GBAColor, usize)>> {
+    let mut banks: Vec<Vec<GBAColor>> = Vec::new();
+    let tiles = regions.iter().flat_map(|region| {
+        (region.y / 8..(region.y + region.height) / 8).flat_map(move |ty| {
+            (region.x / 8..(region.x + region.width) / 8).map(move |tx| (tx, ty))
+        })
+    });
+    for (tx, ty) in tiles {
+        let mut colors = Vec::new();
+        for y in 0..8 {
+            for x in 0..8 {
+                let p = GBAColor::from(img.get_pixel((tx * 8 + x) as _, (ty * 8 + y) as _));
+                if p != transparent && !colors.contains(&p) {
+                    colors.push(p);
+                }
+            }
+        }
+        if colors.is_empty() {
+            continue;
+        }
+        ensure!(
+            colors.len() <= 15,
+            "位于 ({},{}) 的图块使用了 {} 种颜色，4bpp 图块最多只能使用 15 种颜色和透明色",
+            tx * 8,
+            ty * 8,
+            colors.len()
+        );
+
+        // 放入新增颜色最少且放得下的一组调色板
+        let best = banks
+            .iter()
+            .enumerate()
+            .map(|(i, bank)| {
+                let missing = colors.iter().filter(|x| !bank.contains(x)).count();
+                (i, bank.len() + missing, missing)
+            })
+            .filter(|x| x.1 <= 15)
+            .min_by_key(|x| x.2);
+        if let Some((i, _, _)) = best {
+            for c in colors {
+                if !banks[i].contains(&c) {
+                    banks[i].push(c);
+                }
+            }
+        } else {
+            ensure!(
+                banks.len() < 16,
+                "位于 ({},{}) 的图块的颜色无法放入任何一组调色板，16 组调色板已用完",
+                tx * 8,
+                ty * 8
+            );
+            banks.push(colors);
+        }
+    }
+    println!("palette banks: {}", banks.len().max(1));
+
+    let mut palette = Vec::with_capacity(banks.len() * 16);
+    for (i, bank) in banks.iter().enumerate() {
+        palette.push((transparent, usize::MAX));
+        palette.extend(bank.iter().map(|x| (*x, 1)));
+        if i + 1 < banks.len() {
+            palette.resize(palette.len().next_multiple_of(16), (transparent, 0));
+        }
+    }
+    if palette.is_empty() {
+        palette.push((transparent, usize::MAX));
+    }
+    Ok(palette)
+}
+
+fn layer_regions(layers: &[TileLayer]) -> Vec<TileRegion> {
+    layers
+        .iter()
+        .map(|layer| TileRegion {
+            x: layer.x * 8,
+            y: layer.y * 8,
+            width: layer.tilemap.width * 8,
+            height: layer.tilemap.height * 8,
+        })
+        .collect()
+}
+
+/// 索引 PNG 图像，像素为调色板索引
+struct IndexedImage {
+    width: usize,
+    height: usize,
+    indices: Vec<u8>,
+    palette: Vec<GBAColor>,
+}
+
+enum SourceImage {
+    Indexed(IndexedImage),
+    /// 真彩色图像，按颜色在调色板中查找索引
+    TrueColor(RgbImage),
+}
+
+impl SourceImage {
+    fn dimensions(&self) -> (usize, usize) {
+        match self {
+            SourceImage::Indexed(x) => (x.width, x.height),
+            SourceImage::TrueColor(x) => (x.width() as usize, x.height() as usize),
+        }
+    }
+}
+
+fn read_source_image(path: &Path) -> anyhow::Result<SourceImage> {
+    let is_png = path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("png"));
+    if !is_png {
+        return Ok(SourceImage::TrueColor(image::open(path)?.into_rgb8()));
+    }
+
+    let decoder = png::Decoder::new(std::io::BufReader::new(std::fs::File::open(path)?));
+    let mut reader = decoder.read_info()?;
+    let info = reader.info();
+    if info.color_type != png::ColorType::Indexed {
+        return Ok(SourceImage::TrueColor(image::open(path)?.into_rgb8()));
+    }
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let bit_depth = info.bit_depth as usize;
+    let palette = info
+        .palette
+        .as_ref()
+        .map(|x| {
+            x.chunks_exact(3)
+                .map(|c| GBAColor::from(&image::Rgb([c[0], c[1], c[2]])))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buf)?;
+    let mask = ((1u16 << bit_depth) - 1) as u8;
+    let mut indices = Vec::with_capacity(width * height);
+    for row in buf.chunks_exact(frame.line_size).take(height) {
+        for x in 0..width {
+            let bit = x * bit_depth;
+            indices.push((row[bit / 8] >> (8 - bit_depth - bit % 8)) & mask);
+        }
+    }
+
+    Ok(SourceImage::Indexed(IndexedImage {
+        width,
+        height,
+        indices,
+        palette,
+    }))
+}
+
+/// 以 8 位索引 PNG 写出图像，调色板按原顺序写入 PLTE
+fn write_indexed_png(
+    path: &Path,
+    width: usize,
+    height: usize,
+    indices: &[u8],
+    palette: &[GBAColor],
+) -> anyhow::Result<()> {
+    let color_count = indices
+        .iter()
+        .max()
+        .map_or(1, |x| *x as usize + 1)
+        .max(palette.len().min(256));
+    let mut plte = Vec::with_capacity(color_count * 3);
+    for i in 0..color_count {
+        let color: image::Rgb<u8> = palette.get(i).copied().unwrap_or_default().into();
+        plte.extend_from_slice(&color.0);
+    }
+
+    let output = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(output, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(plte);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(indices)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// 图块表在图像中的一层
+struct TileLayer<'a> {
+    tilemap: &'a TilemapMeta,
+    tileset: usize,
+    x: usize,
+    y: usize,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct TileImage {
     pub tileset: Vec<TilesetMeta>,
     pub tilemap: Vec<TilemapMeta>,
-    pub first_color_pos: (usize, usize),
     pub palette: Option<PathBuf>,
-    // 将原色表作为参考使用，而不从图块集中提取
+    // 将原色表作为参考使用，写回索引 PNG 时也不修改调色板
     pub palette_reference: bool,
     // 当颜色不存在时使用的默认颜色
     pub default_color_index: Option<u8>,
     pub output: PathBuf,
     pub is_4bpp: bool,
+    // 写回真彩色 PNG 时，调色板中没有的颜色如何映射
+    #[serde(default)]
+    pub color_matching: ColorMatching,
+    // 颜色映射报告图像的输出路径
+    #[serde(default)]
+    pub report: Option<PathBuf>,
+}
+
+/// NDS BG 图块表项，第 0~9 位为图块编号，第 10、11 位为水平、垂直翻转，第 12~15 位为 4bpp 调色板编号
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TilemapEntry {
+    pub tile: usize,
+    pub h_flip: bool,
+    pub v_flip: bool,
+    pub palette_bank: u8,
+}
+
+impl TilemapEntry {
+    pub fn from_raw(raw: u16) -> Self {
+        Self {
+            tile: (raw & 0x3FF) as usize,
+            h_flip: raw & 0x400 != 0,
+            v_flip: raw & 0x800 != 0,
+            palette_bank: (raw >> 12) as u8,
+        }
+    }
+
+    pub fn to_raw(self) -> u16 {
+        debug_assert!(self.tile < 0x400, "tile index {} out of range", self.tile);
+        self.tile as u16
+            | (self.h_flip as u16) << 10
+            | (self.v_flip as u16) << 11
+            | (self.palette_bank as u16 & 0xF) << 12
+    }
+}
+
+fn flip_tile<T: Tile>(tile: &T, h_flip: bool, v_flip: bool) -> T {
+    let mut flipped = T::default();
+    for y in 0..8 {
+        for x in 0..8 {
+            let sx = if h_flip { 7 - x } else { x };
+            let sy = if v_flip { 7 - y } else { y };
+            flipped.set_pixel(x, y, tile.get_pixel(sx, sy));
+        }
+    }
+    flipped
+}
+
+/// 在图块集中查找图块，允许翻转匹配，返回图块位置及水平、垂直翻转
+pub fn find_tile<T: Tile>(tileset: &[T], tile: &T) -> Option<(usize, bool, bool)> {
+    [(false, false), (true, false), (false, true), (true, true)]
+        .into_iter()
+        .find_map(|(h_flip, v_flip)| {
+            let flipped = flip_tile(tile, h_flip, v_flip);
+            tileset
+                .iter()
+                .position(|x| x == &flipped)
+                .map(|pos| (pos, h_flip, v_flip))
+        })
 }
 
 impl TileImage {
@@ -51,11 +318,6 @@ impl TileImage {
         self
     }
 
-    pub fn with_first_color_pos(mut self, x: usize, y: usize) -> Self {
-        self.first_color_pos = (x, y);
-        self
-    }
-
     pub fn with_palette_reference(mut self) -> Self {
         self.palette_reference = true;
         self
@@ -66,6 +328,16 @@ impl TileImage {
         self
     }
 
+    pub fn with_color_matching(mut self, color_matching: ColorMatching) -> Self {
+        self.color_matching = color_matching;
+        self
+    }
+
+    pub fn with_report(mut self, report: impl AsRef<std::path::Path>) -> Self {
+        self.report = Some(report.as_ref().to_path_buf());
+        self
+    }
+
     pub fn with_tileset(
         mut self,
         input_file: impl AsRef<std::path::Path>,
@@ -92,6 +364,49 @@ impl TileImage {
             output_file: input_file.as_ref().to_path_buf(),
             width,
             height,
+            ..Default::default()
+        });
+        self
+    }
+
+    /// 添加图块表，并指定使用的图块集序号和以图块为单位的位置
+    pub fn with_tilemap_layer(
+        mut self,
+        input_file: impl AsRef<std::path::Path>,
+        width: usize,
+        height: usize,
+        tileset: usize,
+        x: usize,
+        y: usize,
+    ) -> Self {
+        self.tilemap.push(TilemapMeta {
+            input_file: input_file.as_ref().to_path_buf(),
+            output_file: input_file.as_ref().to_path_buf(),
+            width,
+            height,
+            tileset: Some(tileset),
+            position: Some((x, y)),
+            ..Default::default()
+        });
+        self
+    }
+
+    /// 添加没有图块表文件的一层，图块集中的图块按顺序排列，写回时也按顺序写出，不合并重复图块
+    pub fn with_tile_sequence(
+        mut self,
+        width: usize,
+        height: usize,
+        tileset: usize,
+        x: usize,
+        y: usize,
+    ) -> Self {
+        self.tilemap.push(TilemapMeta {
+            width,
+            height,
+            tileset: Some(tileset),
+            position: Some((x, y)),
+            sequential: true,
+            ..Default::default()
         });
         self
     }
@@ -118,17 +433,38 @@ impl TileImage {
         Ok(meta)
     }
 
-    fn verify_tilemap_tileset_len(&self) -> anyhow::Result<()> {
-        match (self.tilemap.len(), self.tileset.len()) {
-            (1, _) => {}
-            (_, 1) => {}
-            (a, b) => {
-                if a != b {
-                    anyhow::bail!("tilemap and tileset count mismatch: {} != {}", a, b);
-                }
-            }
+    fn layers(&self) -> anyhow::Result<Vec<TileLayer<'_>>> {
+        let mut layers = Vec::with_capacity(self.tilemap.len());
+        let mut next_y = 0;
+        for (i, map) in self.tilemap.iter().enumerate() {
+            let tileset = match map.tileset {
+                Some(tileset) => tileset,
+                None if self.tileset.len() == 1 => 0,
+                None if self.tileset.len() == self.tilemap.len() => i,
+                None => anyhow::bail!(
+                    "tilemap {} does not specify a tileset (tilemap {} with tileset {})",
+                    map.input_file.display(),
+                    self.tilemap.len(),
+                    self.tileset.len()
+                ),
+            };
+            anyhow::ensure!(
+                tileset < self.tileset.len(),
+                "tilemap {} uses tileset {} but there are only {} tilesets",
+                map.input_file.display(),
+                tileset,
+                self.tileset.len()
+            );
+            let (x, y) = map.position.unwrap_or((0, next_y));
+            next_y = next_y.max(y + map.height);
+            layers.push(TileLayer {
+                tilemap: map,
+                tileset,
+                x,
+                y,
+            });
         }
-        Ok(())
+        Ok(layers)
     }
 
     fn verify_input_meta(&self) -> anyhow::Result<()> {
@@ -140,7 +476,7 @@ impl TileImage {
             self.palette
         );
 
-        self.verify_tilemap_tileset_len()?;
+        self.layers()?;
 
         for set in &self.tileset {
             anyhow::ensure!(
@@ -150,7 +486,7 @@ impl TileImage {
             );
         }
 
-        for map in &self.tilemap {
+        for map in self.tilemap.iter().filter(|x| !x.sequential) {
             anyhow::ensure!(
                 map.input_file.is_file(),
                 "tilemap {} does not exist",
@@ -164,7 +500,7 @@ impl TileImage {
     fn verify_output_meta(&self) -> anyhow::Result<()> {
         anyhow::ensure!(!self.tilemap.is_empty(), "tilemap meta is empty");
 
-        self.verify_tilemap_tileset_len()?;
+        self.layers()?;
 
         anyhow::ensure!(
             self.output.is_file(),
@@ -175,12 +511,12 @@ impl TileImage {
         Ok(())
     }
 
-    fn get_image_size(&self) -> (usize, usize) {
+    fn get_image_size(layers: &[TileLayer]) -> (usize, usize) {
         let mut width = 0;
         let mut height = 0;
-        for map in &self.tilemap {
-            width = width.max(map.width * 8);
-            height += map.height * 8;
+        for layer in layers {
+            width = width.max((layer.x + layer.tilemap.width) * 8);
+            height = height.max((layer.y + layer.tilemap.height) * 8);
         }
         (width, height)
     }
@@ -200,8 +536,9 @@ impl TileImage {
     }
 
     fn read_tileimg_inner<T: Tile>(&self) -> anyhow::Result<()> {
-        let (img_width, img_height) = self.get_image_size();
-        let mut img = RgbImage::new(img_width as _, img_height as _);
+        let layers = self.layers()?;
+        let (img_width, img_height) = Self::get_image_size(&layers);
+        let mut indices = vec![0u8; img_width * img_height];
 
         let palette = self.read_palette()?;
         println!("Read {} colors", palette.len());
@@ -218,87 +555,62 @@ impl TileImage {
             tilesets.push(tileset);
         }
 
-        let mut draw_tile = |tile: &T, gx: usize, gy: usize| {
+        let is_4bpp = self.is_4bpp;
+        let mut draw_tile = |tile: &T, entry: TilemapEntry, gx: usize, gy: usize| {
             for y in 0..8 {
                 for x in 0..8 {
-                    let color = palette
-                        .get(tile.get_pixel(x, y) as usize)
-                        .copied()
-                        .unwrap_or_default();
-                    img.put_pixel((gx + x) as _, (gy + y) as _, color.into());
+                    let sx = if entry.h_flip { 7 - x } else { x };
+                    let sy = if entry.v_flip { 7 - y } else { y };
+                    let value = tile.get_pixel(sx, sy) as usize;
+                    // 4bpp 图块的颜色 0 为透明色，显示为背景色
+                    let index = if is_4bpp && value != 0 {
+                        entry.palette_bank as usize * 16 + value
+                    } else {
+                        value
+                    };
+                    indices[(gy + y) * img_width + gx + x] = index as u8;
                 }
             }
         };
 
-        // 共用调色板，但是图块集和图块表分别存放
-        if self.tilemap.len() == self.tileset.len() {
-            let mut global_y = 0;
-            for ((tilemap_meta, tileset), tileset_meta) in self
-                .tilemap
-                .iter()
-                .zip(tilesets.iter())
-                .zip(self.tileset.iter())
-            {
-                println!("Reading tilemap {}", tilemap_meta.input_file.display());
-                let raw_tilemap = std::fs::read(tilemap_meta.input_file.as_path())?;
-                let mut tilemap = Vec::with_capacity(raw_tilemap.len() / 2);
-                for raw_tile in raw_tilemap.chunks_exact(2) {
-                    tilemap.push(u16::from_le_bytes([raw_tile[0], raw_tile[1]]));
-                }
-                println!("Read {} tiles", tilemap.len());
-
-                let mut x = 0;
-                for tile in tilemap {
-                    if let Some(tile) =
-                        tileset.get((tile as usize).wrapping_sub(tileset_meta.base_tile))
-                    {
-                        draw_tile(tile, x * 8, global_y * 8);
-                    }
-                    x += 1;
-                    if x >= tilemap_meta.width {
-                        x = 0;
-                        global_y += 1;
-                    }
-                }
-            }
-        } else if self.tileset.len() == 1 {
-            let mut global_y = 0;
-            let tileset = &tilesets[0];
-            let tileset_meta = &self.tileset[0];
-            for tilemap_meta in self.tilemap.iter() {
+        for layer in &layers {
+            let tilemap_meta = layer.tilemap;
+            let tileset = &tilesets[layer.tileset];
+            let base_tile = self.tileset[layer.tileset].base_tile;
+            let tile_count = tilemap_meta.width * tilemap_meta.height;
+            let tilemap = if tilemap_meta.sequential {
+                (0..tile_count)
+                    .map(|i| TilemapEntry {
+                        tile: i + base_tile,
+                        ..Default::default()
+                    })
+                    .collect::<Vec<_>>()
+            } else {
                 println!("Reading tilemap {}", tilemap_meta.input_file.display());
                 let raw_tilemap = std::fs::read(tilemap_meta.input_file.as_path())?;
                 let mut tilemap = Vec::with_capacity(raw_tilemap.len() / 2);
                 for raw_tile in raw_tilemap.chunks_exact(2) {
-                    tilemap.push(u16::from_le_bytes([raw_tile[0], raw_tile[1]]));
+                    tilemap.push(TilemapEntry::from_raw(u16::from_le_bytes([
+                        raw_tile[0],
+                        raw_tile[1],
+                    ])));
                 }
                 println!("Read {} tiles", tilemap.len());
-
-                let mut x = 0;
-                for tile in tilemap {
-                    if tile as usize >= tileset_meta.base_tile {
-                        if let Some(tile) =
-                            tileset.get((tile as usize).wrapping_sub(tileset_meta.base_tile))
-                        {
-                            draw_tile(tile, x * 8, global_y * 8);
-                        }
-                    }
-                    x += 1;
-                    if x >= tilemap_meta.width {
-                        x = 0;
-                        global_y += 1;
+                tilemap
+            };
+
+            for (i, entry) in tilemap.into_iter().take(tile_count).enumerate() {
+                let x = layer.x + i % tilemap_meta.width;
+                let y = layer.y + i / tilemap_meta.width;
+                if entry.tile >= base_tile {
+                    if let Some(tile) = tileset.get(entry.tile - base_tile) {
+                        draw_tile(tile, entry, x * 8, y * 8);
                     }
                 }
             }
-        } else {
-            anyhow::bail!(
-                "not implemented for this case (tilemap {} with tileset {})",
-                self.tilemap.len(),
-                self.tileset.len()
-            );
         }
 
-        img.save(&self.output)?;
+        write_indexed_png(&self.output, img_width, img_height, &indices, &palette)?;
 
         Ok(())
     }
@@ -313,188 +625,290 @@ impl TileImage {
         }
     }
 
-    fn save_tileimg_inner<T: Tile>(&self) -> anyhow::Result<()> {
-        let (img_width, img_height) = self.get_image_size();
-        let img = image::open(&self.output)?.into_rgb8();
+    /// 将索引图像中位于 (gx, gy) 的 8x8 像素按索引转换为图块，返回图块及其 4bpp 调色板编号
+    fn encode_indexed_tile<T: Tile>(
+        &self,
+        indexed: &IndexedImage,
+        gx: usize,
+        gy: usize,
+    ) -> anyhow::Result<(T, u8)> {
+        let mut tile = T::default();
+        let mut bank = None;
+        for y in 0..8 {
+            for x in 0..8 {
+                let index = indexed.indices[(gy + y) * indexed.width + gx + x];
+                let value = if self.is_4bpp {
+                    // 每组调色板的颜色 0 都是透明色，不决定调色板编号
+                    if !index.is_multiple_of(16) {
+                        ensure!(
+                            bank.is_none_or(|x| x == index / 16),
+                            "位于 ({},{}) 的图块同时使用了第 {} 组和第 {} 组调色板的颜色，4bpp 图块只能使用一组调色板",
+                            gx,
+                            gy,
+                            bank.unwrap_or_default(),
+                            index / 16
+                        );
+                        bank = Some(index / 16);
+                    }
+                    index % 16
+                } else {
+                    index
+                };
+                tile.set_pixel(x, y, value);
+            }
+        }
+        Ok((tile, bank.unwrap_or_default()))
+    }
 
-        ensure!(
-            img.width() == img_width as u32,
-            "image width is not {}",
-            img_width
-        );
-        ensure!(
-            img.height() == img_height as u32,
-            "image height is not {}",
-            img_height
-        );
+    /// 将索引 PNG 的调色板写回调色板文件，原调色板中超出 PNG 调色板长度的颜色保持不变
+    fn write_indexed_palette(&self, colors: &[GBAColor]) -> anyhow::Result<()> {
+        ensure!(self.palette.is_some(), "palette path is not set");
+        let palette_path = self.palette.as_ref().unwrap();
 
-        let palette = if self.palette_reference {
+        let mut palette = if palette_path.is_file() {
             self.read_palette()?
-                .into_iter()
-                .enumerate()
-                .map(|x| (x.1, x.0))
-                .collect::<Vec<_>>()
         } else {
-            let first_color_pixel = GBAColor::from(
-                img.get_pixel(self.first_color_pos.0 as _, self.first_color_pos.1 as _),
-            );
-            let mut palette = Vec::<(GBAColor, usize)>::with_capacity(256);
-            for p in img.pixels() {
-                let p = GBAColor::from(p);
-                if let Some(p) = palette.iter_mut().find(|x| x.0 == p) {
-                    p.1 = p.1.wrapping_add(1);
-                } else if first_color_pixel == p {
-                    palette.push((p, usize::MAX));
-                } else {
-                    palette.push((p, 1));
-                }
-            }
-            println!("palette size: {}", palette.len());
-            ensure!(
-                palette.len() <= 256,
-                "palette size should not be more than 256"
-            );
-            ensure!(self.palette.is_some(), "palette path is not set");
-
-            let palette_path = self.palette.as_ref().unwrap();
+            Vec::new()
+        };
+        if palette.len() < colors.len() {
+            palette.resize(colors.len(), GBAColor::default());
+        }
+        palette[..colors.len()].copy_from_slice(colors);
+        println!("palette size: {}", palette.len());
 
-            palette.sort_by(|a, b| b.1.cmp(&a.1));
+        let mut palette_output = std::fs::File::create(palette_path)?;
+        for c in &palette {
+            palette_output.write_all(&c.to_le_bytes())?;
+        }
+        palette_output.sync_all()?;
+        Ok(())
+    }
 
-            let mut palette_output = std::fs::File::create(palette_path)?;
+    /// 将图像中位于 (gx, gy) 的 8x8 像素转换为图块，返回图块及其 4bpp 调色板编号
+    ///
+    /// 4bpp 图块使用缺少颜色最少的一组 16 色调色板，颜色 0 为所有调色板共用的透明色，
+    /// 没有调色板能放下图块的所有颜色且未设置默认颜色时报错
+    fn encode_tile<T: Tile>(
+        &self,
+        img: &RgbImage,
+        palette: &[(GBAColor, usize)],
+        gx: usize,
+        gy: usize,
+    ) -> anyhow::Result<(T, u8)> {
+        let colors = (0..64)
+            .map(|i| GBAColor::from(img.get_pixel((gx + i % 8) as _, (gy + i / 8) as _)))
+            .collect::<Vec<_>>();
 
-            for c in &palette {
-                palette_output.write_all(&c.0.to_le_bytes())?;
+        if self.is_4bpp {
+            let transparent = palette.first().map(|x| x.0);
+            let bank_colors = |bank: usize| &palette[bank * 16..palette.len().min(bank * 16 + 16)];
+            let missing_colors = |bank: usize| {
+                let mut missing = Vec::new();
+                for p in &colors {
+                    if Some(*p) != transparent
+                        && !bank_colors(bank).iter().skip(1).any(|x| x.0 == *p)
+                        && !missing.contains(p)
+                    {
+                        missing.push(*p);
+                    }
+                }
+                missing
+            };
+
+            // 选择缺少颜色最少的一组调色板
+            let mut best: Option<(usize, Vec<GBAColor>)> = None;
+            for bank in 0..palette.len().div_ceil(16) {
+                let missing = missing_colors(bank);
+                if best.as_ref().is_none_or(|x| missing.len() < x.1.len()) {
+                    let is_complete = missing.is_empty();
+                    best = Some((bank, missing));
+                    if is_complete {
+                        break;
+                    }
+                }
+            }
+            let Some((bank, missing)) = best else {
+                anyhow::bail!("调色板为空");
+            };
+            if !missing.is_empty() && self.default_color_index.is_none() {
+                anyhow::bail!(
+                    "位于 ({},{}) 的图块的颜色无法放入任何一组调色板，最接近的第 {} 组调色板缺少颜色 {:?}",
+                    gx,
+                    gy,
+                    bank,
+                    missing
+                );
             }
 
-            palette_output.sync_all()?;
-            palette
-        };
-
-        let empty_tile = T::default();
+            let mut tile = T::default();
+            for (i, p) in colors.iter().enumerate() {
+                let pv = if Some(*p) == transparent {
+                    Some(0)
+                } else {
+                    bank_colors(bank)
+                        .iter()
+                        .skip(1)
+                        .position(|x| x.0 == *p)
+                        .map(|x| x as u8 + 1)
+                };
+                tile.set_pixel(
+                    i % 8,
+                    i / 8,
+                    pv.or(self.default_color_index).unwrap_or_default(),
+                );
+            }
+            return Ok((tile, bank as u8));
+        }
 
-        if self.tilemap.len() == self.tileset.len() {
-            let mut global_y = 0;
-            for (tilemap_meta, tileset_meta) in self.tilemap.iter().zip(self.tileset.iter()) {
-                let mut tileset = Vec::with_capacity(tilemap_meta.width * tilemap_meta.height);
-                let mut tilemap: Vec<u16> =
-                    Vec::with_capacity(tilemap_meta.width * tilemap_meta.height);
-
-                for ty in 0..tilemap_meta.height {
-                    for tx in 0..tilemap_meta.width {
-                        let mut tile = T::default();
-                        for y in 0..8 {
-                            for x in 0..8 {
-                                let px = tx * 8 + x;
-                                let py = ty * 8 + y;
-                                let p =
-                                    GBAColor::from(img.get_pixel(px as _, (py + global_y) as _));
-                                let pv = palette.iter().position(|x| x.0 == p);
-                                match pv {
-                                    Some(pv) => {
-                                        tile.set_pixel(x, y, pv as u8);
-                                    }
-                                    None => {
-                                        if let Some(default_color_index) = self.default_color_index
-                                        {
-                                            tile.set_pixel(x, y, default_color_index);
-                                        } else {
-                                            anyhow::bail!("无法获取位于 ({},{}) 的像素颜色值 {:?} 在调色板中的位置", px, py + global_y, p);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        if tile == empty_tile && tileset_meta.base_tile > 0 {
-                            tilemap.push(0);
-                        } else if let Some(pos) = tileset.iter().position(|x| x == &tile) {
-                            tilemap.push((pos + tileset_meta.base_tile) as u16);
-                        } else {
-                            tilemap.push((tileset.len() + tileset_meta.base_tile) as u16);
-                            tileset.push(tile);
-                        }
+        let mut tile = T::default();
+        for (i, p) in colors.iter().enumerate() {
+            let pv = palette.iter().position(|x| x.0 == *p);
+            match pv {
+                Some(pv) => {
+                    tile.set_pixel(i % 8, i / 8, pv as u8);
+                }
+                None => {
+                    if let Some(default_color_index) = self.default_color_index {
+                        tile.set_pixel(i % 8, i / 8, default_color_index);
+                    } else {
+                        anyhow::bail!(
+                            "无法获取位于 ({},{}) 的像素颜色值 {:?} 在调色板中的位置",
+                            gx + i % 8,
+                            gy + i / 8,
+                            p
+                        );
                     }
                 }
+            }
+        }
+        Ok((tile, 0))
+    }
 
-                let mut tilemap_output = std::fs::File::create(&tilemap_meta.input_file)?;
-                for tile in tilemap {
-                    tilemap_output.write_all(&tile.to_le_bytes())?;
-                }
+    fn save_tileimg_inner<T: Tile>(&self) -> anyhow::Result<()> {
+        let layers = self.layers()?;
+        let (img_width, img_height) = Self::get_image_size(&layers);
+        let mut source = read_source_image(&self.output)?;
+        let (width, height) = source.dimensions();
+
+        ensure!(width == img_width, "image width is not {}", img_width);
+        ensure!(height == img_height, "image height is not {}", img_height);
+
+        // 索引 PNG 按索引写回，调色板顺序保持不变；
+        // 真彩色 PNG 按 RGB 匹配原调色板，不重建调色板，避免打乱精灵和淡入淡出依赖的颜色序号
+        let palette = if let SourceImage::Indexed(indexed) = &source {
+            if !self.palette_reference {
+                self.write_indexed_palette(&indexed.palette)?;
+            }
+            Vec::new()
+        } else {
+            self.read_palette()?
+                .into_iter()
+                .enumerate()
+                .map(|x| (x.1, x.0))
+                .collect::<Vec<_>>()
+        };
 
-                let mut tileset_output = std::fs::File::create(&tileset_meta.input_file)?;
-                for tile in tileset {
-                    tileset_output.write_all(tile.as_raw())?;
+        if let SourceImage::TrueColor(img) = &mut source {
+            if self.color_matching != ColorMatching::Exact {
+                let colors = palette.iter().map(|x| x.0).collect::<Vec<_>>();
+                let regions = layer_regions(&layers);
+                let result = color_match::remap_to_palette(
+                    img,
+                    &regions,
+                    &colors,
+                    self.is_4bpp.then_some(16),
+                    self.color_matching,
+                );
+                println!("remapped {} pixels", result.remapped_count());
+                if let Some(report) = &self.report {
+                    if let Some(parent) = report.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    result.report(img).save(report)?;
                 }
+                *img = result.image;
+            }
+        }
 
-                tilemap_output.sync_all()?;
-                tileset_output.sync_all()?;
+        let empty_tile = T::default();
+        let mut tilesets = (0..self.tileset.len())
+            .map(|_| Vec::new())
+            .collect::<Vec<Vec<T>>>();
+
+        for layer in &layers {
+            let tilemap_meta = layer.tilemap;
+            let tileset_meta = &self.tileset[layer.tileset];
+            let tileset = &mut tilesets[layer.tileset];
+            let mut tilemap: Vec<u16> =
+                Vec::with_capacity(tilemap_meta.width * tilemap_meta.height);
+            // 按顺序排列的图块位置就是图块序号，不能和其他图块表共用图块集
+            ensure!(
+                !tilemap_meta.sequential || tileset.is_empty(),
+                "按顺序排列的图块不能与其他图块表共用图块集 {}",
+                tileset_meta.input_file.display()
+            );
 
-                global_y += tilemap_meta.height * 8;
-            }
-        } else if self.tileset.len() == 1 {
-            let mut global_y = 0;
-            let tileset_meta = &self.tileset[0];
-            let mut tileset = Vec::with_capacity(256);
-
-            for tilemap_meta in &self.tilemap {
-                let mut tilemap: Vec<u16> = Vec::with_capacity(256);
-
-                for ty in 0..tilemap_meta.height {
-                    for tx in 0..tilemap_meta.width {
-                        let mut tile = T::default();
-                        for y in 0..8 {
-                            for x in 0..8 {
-                                let px = tx * 8 + x;
-                                let py = ty * 8 + y;
-                                let p =
-                                    GBAColor::from(img.get_pixel(px as _, (py + global_y) as _));
-                                let pv = palette.iter().position(|x| x.0 == p);
-                                match pv {
-                                    Some(pv) => {
-                                        tile.set_pixel(x, y, pv as u8);
-                                    }
-                                    None => {
-                                        if let Some(default_color_index) = self.default_color_index
-                                        {
-                                            tile.set_pixel(x, y, default_color_index);
-                                        } else {
-                                            anyhow::bail!("无法获取位于 ({},{}) 的像素颜色值 {:?} 在调色板中的位置", px, py + global_y, p);
-                                        }
-                                    }
-                                }
-                            }
+            for ty in 0..tilemap_meta.height {
+                for tx in 0..tilemap_meta.width {
+                    let (gx, gy) = ((layer.x + tx) * 8, (layer.y + ty) * 8);
+                    let (tile, palette_bank) = match &source {
+                        SourceImage::Indexed(indexed) => {
+                            self.encode_indexed_tile::<T>(indexed, gx, gy)?
                         }
-                        if tile == empty_tile && tileset_meta.base_tile > 0 {
-                            tilemap.push(0);
-                        } else if let Some(pos) = tileset.iter().position(|x| x == &tile) {
-                            tilemap.push((pos + tileset_meta.base_tile) as u16);
-                        } else {
-                            tilemap.push((tileset.len() + tileset_meta.base_tile) as u16);
-                            tileset.push(tile);
+                        SourceImage::TrueColor(img) => {
+                            self.encode_tile::<T>(img, &palette, gx, gy)?
                         }
+                    };
+                    if tilemap_meta.sequential {
+                        tileset.push(tile);
+                        continue;
                     }
+                    let entry = if tile == empty_tile && tileset_meta.base_tile > 0 {
+                        TilemapEntry::default()
+                    } else if let Some((pos, h_flip, v_flip)) = find_tile(tileset, &tile) {
+                        TilemapEntry {
+                            tile: pos + tileset_meta.base_tile,
+                            h_flip,
+                            v_flip,
+                            palette_bank,
+                        }
+                    } else {
+                        tileset.push(tile);
+                        // 图块表中的图块序号只有 10 位
+                        ensure!(
+                            tileset.len() + tileset_meta.base_tile <= 1024,
+                            "tileset size should not be more than 1024"
+                        );
+                        TilemapEntry {
+                            tile: tileset.len() - 1 + tileset_meta.base_tile,
+                            palette_bank,
+                            ..Default::default()
+                        }
+                    };
+                    tilemap.push(entry.to_raw());
                 }
+            }
 
-                let mut tilemap_output = std::fs::File::create(&tilemap_meta.input_file)?;
-                for tile in tilemap {
-                    tilemap_output.write_all(&tile.to_le_bytes())?;
-                }
-                tilemap_output.sync_all()?;
-
-                global_y += tilemap_meta.height * 8;
+            if tilemap_meta.sequential {
+                continue;
+            }
+            let mut tilemap_output = std::fs::File::create(&tilemap_meta.input_file)?;
+            for tile in tilemap {
+                tilemap_output.write_all(&tile.to_le_bytes())?;
             }
+            tilemap_output.sync_all()?;
+        }
 
-            let mut tileset_output = std::fs::File::create(&self.tileset[0].input_file)?;
+        // 多个图块表共用的图块集在所有图块表写完后再写出，没有被使用的图块集保持不变
+        for (i, (tileset_meta, tileset)) in self.tileset.iter().zip(tilesets).enumerate() {
+            if !layers.iter().any(|x| x.tileset == i) {
+                continue;
+            }
+            let mut tileset_output = std::fs::File::create(&tileset_meta.input_file)?;
             for tile in tileset {
                 tileset_output.write_all(tile.as_raw())?;
             }
-
             tileset_output.sync_all()?;
-        } else {
-            anyhow::bail!(
-                "not implemented for this case (tilemap {} with tileset {})",
-                self.tilemap.len(),
-                self.tileset.len()
-            );
         }
 
         Ok(())
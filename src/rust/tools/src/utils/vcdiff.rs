@@ -0,0 +1,542 @@
+//! 生成与 xdelta3 兼容的 VCDIFF（RFC 3284）差分补丁
+//!
+//! 只使用默认指令表中大小单独编码的 ADD、RUN 和 COPY 指令，COPY 只引用源文件，地址使用 VCD_SELF 模式。
+//! 源文件按块建立哈希索引，目标文件逐字节滚动哈希查找匹配，并优先沿用上一次匹配的偏移，
+//! 这样只修改了少量数据的 ROM 可以生成很小的补丁。
+
+use std::ops::Range;
+
+const VCDIFF_MAGIC: [u8; 4] = [0xD6, 0xC3, 0xC4, 0x00];
+const VCD_SOURCE: u8 = 0x01;
+/// 默认指令表中大小单独编码的 RUN 指令
+const INST_RUN: u8 = 0;
+/// 默认指令表中大小单独编码的 ADD 指令
+const INST_ADD: u8 = 1;
+/// 默认指令表中大小单独编码、地址模式为 VCD_SELF 的 COPY 指令
+const INST_COPY_SELF: u8 = 19;
+
+/// 每个目标窗口的大小，xdelta3 默认最大支持 16 MiB
+const WINDOW_SIZE: usize = 1 << 20;
+const BLOCK_SIZE: usize = 32;
+const HASH_BITS: u32 = 22;
+const HASH_BASE: u32 = 0x0100_0193;
+/// 沿用上一次匹配的偏移时接受的最短匹配长度
+const MIN_CONTINUE_MATCH: usize = 8;
+/// 使用 RUN 指令编码的最短重复字节长度
+const MIN_RUN: usize = 16;
+
+enum Op {
+    Add(Range<usize>),
+    Copy { source: usize, len: usize },
+}
+
+fn write_int(out: &mut Vec<u8>, mut value: usize) {
+    let mut bytes = [0u8; 10];
+    let mut i = bytes.len();
+    loop {
+        i -= 1;
+        bytes[i] = (value & 0x7F) as u8;
+        if i != bytes.len() - 1 {
+            bytes[i] |= 0x80;
+        }
+        value >>= 7;
+        if value == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(&bytes[i..]);
+}
+
+fn block_hash(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |h, b| {
+        h.wrapping_mul(HASH_BASE).wrapping_add(*b as u32)
+    })
+}
+
+fn hash_slot(hash: u32) -> usize {
+    (hash >> (32 - HASH_BITS)) as usize
+}
+
+fn match_len(source: &[u8], target: &[u8]) -> usize {
+    source
+        .iter()
+        .zip(target)
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+struct Encoder<'a> {
+    source: &'a [u8],
+    target: &'a [u8],
+    /// 源文件中每个哈希值对应的第一个块的位置
+    index: Vec<u32>,
+    /// 块最高位的权重，用于滚动哈希
+    out_weight: u32,
+}
+
+impl<'a> Encoder<'a> {
+    fn new(source: &'a [u8], target: &'a [u8]) -> Self {
+        let mut index = vec![u32::MAX; 1 << HASH_BITS];
+        for pos in (0..source.len().saturating_sub(BLOCK_SIZE - 1)).step_by(BLOCK_SIZE) {
+            let slot = &mut index[hash_slot(block_hash(&source[pos..pos + BLOCK_SIZE]))];
+            if *slot == u32::MAX {
+                *slot = pos as u32;
+            }
+        }
+        Self {
+            source,
+            target,
+            index,
+            out_weight: (1..BLOCK_SIZE).fold(1u32, |w, _| w.wrapping_mul(HASH_BASE)),
+        }
+    }
+
+    /// 查找目标窗口中的匹配，生成 ADD 和 COPY 操作
+    fn find_ops(&self, window: Range<usize>) -> Vec<Op> {
+        let (source, target) = (self.source, self.target);
+        let mut ops = Vec::new();
+        let mut pos = window.start;
+        let mut literal_start = pos;
+        let mut last_delta: Option<isize> = None;
+        let mut rolling: Option<(usize, u32)> = None;
+
+        while pos < window.end {
+            let mut found = None;
+            if let Some(delta) = last_delta {
+                let src = pos as isize + delta;
+                if (0..source.len() as isize).contains(&src) {
+                    let src = src as usize;
+                    let len = match_len(&source[src..], &target[pos..window.end]);
+                    if len >= MIN_CONTINUE_MATCH {
+                        found = Some((src, len));
+                    }
+                }
+            }
+            if found.is_none() && pos + BLOCK_SIZE <= window.end {
+                let hash = match rolling {
+                    Some((p, h)) if p + 1 == pos => h
+                        .wrapping_sub((target[p] as u32).wrapping_mul(self.out_weight))
+                        .wrapping_mul(HASH_BASE)
+                        .wrapping_add(target[pos + BLOCK_SIZE - 1] as u32),
+                    _ => block_hash(&target[pos..pos + BLOCK_SIZE]),
+                };
+                rolling = Some((pos, hash));
+                let src = self.index[hash_slot(hash)];
+                if src != u32::MAX {
+                    let src = src as usize;
+                    let len = match_len(&source[src..], &target[pos..window.end]);
+                    if len >= BLOCK_SIZE {
+                        found = Some((src, len));
+                    }
+                }
+            }
+
+            let Some((src, len)) = found else {
+                pos += 1;
+                continue;
+            };
+            // 向前扩展匹配，减少 ADD 的数据
+            let back = (1..=(pos - literal_start).min(src))
+                .take_while(|i| source[src - i] == target[pos - i])
+                .count();
+            if pos - back > literal_start {
+                ops.push(Op::Add(literal_start..pos - back));
+            }
+            ops.push(Op::Copy {
+                source: src - back,
+                len: len + back,
+            });
+            last_delta = Some(src as isize - pos as isize);
+            pos += len;
+            literal_start = pos;
+            rolling = None;
+        }
+        if literal_start < window.end {
+            ops.push(Op::Add(literal_start..window.end));
+        }
+        ops
+    }
+
+    fn encode_window(&self, window: Range<usize>, out: &mut Vec<u8>) {
+        let ops = self.find_ops(window.clone());
+        let segment = ops
+            .iter()
+            .filter_map(|op| match op {
+                Op::Copy { source, len } => Some(*source..source + len),
+                Op::Add(_) => None,
+            })
+            .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end));
+
+        let (mut data, mut inst, mut addr) = (Vec::new(), Vec::new(), Vec::new());
+        for op in &ops {
+            match op {
+                Op::Add(range) => {
+                    // 较长的重复字节使用 RUN 指令
+                    let bytes = &self.target[range.clone()];
+                    let mut literal_start = 0;
+                    let mut pos = 0;
+                    while pos < bytes.len() {
+                        let run = bytes[pos..]
+                            .iter()
+                            .take_while(|b| **b == bytes[pos])
+                            .count();
+                        if run < MIN_RUN {
+                            pos += run;
+                            continue;
+                        }
+                        if pos > literal_start {
+                            inst.push(INST_ADD);
+                            write_int(&mut inst, pos - literal_start);
+                            data.extend_from_slice(&bytes[literal_start..pos]);
+                        }
+                        inst.push(INST_RUN);
+                        write_int(&mut inst, run);
+                        data.push(bytes[pos]);
+                        pos += run;
+                        literal_start = pos;
+                    }
+                    if literal_start < bytes.len() {
+                        inst.push(INST_ADD);
+                        write_int(&mut inst, bytes.len() - literal_start);
+                        data.extend_from_slice(&bytes[literal_start..]);
+                    }
+                }
+                Op::Copy { source, len } => {
+                    inst.push(INST_COPY_SELF);
+                    write_int(&mut inst, *len);
+                    let segment_start = segment.as_ref().map_or(0, |x| x.start);
+                    write_int(&mut addr, source - segment_start);
+                }
+            }
+        }
+
+        let mut delta = Vec::with_capacity(data.len() + inst.len() + addr.len() + 16);
+        write_int(&mut delta, window.len());
+        delta.push(0);
+        write_int(&mut delta, data.len());
+        write_int(&mut delta, inst.len());
+        write_int(&mut delta, addr.len());
+        delta.extend_from_slice(&data);
+        delta.extend_from_slice(&inst);
+        delta.extend_from_slice(&addr);
+
+        if let Some(segment) = segment {
+            out.push(VCD_SOURCE);
+            write_int(out, segment.len());
+            write_int(out, segment.start);
+        } else {
+            out.push(0);
+        }
+        write_int(out, delta.len());
+        out.extend_from_slice(&delta);
+    }
+}
+
+/// 生成从 `source` 到 `target` 的 VCDIFF 补丁
+pub fn encode(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let encoder = Encoder::new(source, target);
+    let mut out = VCDIFF_MAGIC.to_vec();
+    // Hdr_Indicator：不使用二次压缩和自定义指令表
+    out.push(0);
+    for start in (0..target.len()).step_by(WINDOW_SIZE) {
+        encoder.encode_window(start..(start + WINDOW_SIZE).min(target.len()), &mut out);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOOP: u8 = 0;
+    const ADD: u8 = 1;
+    const RUN: u8 = 2;
+    const COPY: u8 = 3;
+    const S_NEAR: usize = 4;
+    const S_SAME: usize = 3;
+
+    /// 按 RFC 3284 5.6 节生成默认指令表，每项为两条指令的（类型，大小，地址模式）
+    fn default_code_table() -> Vec<[(u8, usize, usize); 2]> {
+        let none = (NOOP, 0, 0);
+        let mut table = vec![[(RUN, 0, 0), none]];
+        table.extend((0..=17).map(|size| [(ADD, size, 0), none]));
+        for mode in 0..9 {
+            table.push([(COPY, 0, mode), none]);
+            table.extend((4..=18).map(|size| [(COPY, size, mode), none]));
+        }
+        for mode in 0..6 {
+            for add in 1..=4 {
+                table.extend((4..=6).map(|size| [(ADD, add, 0), (COPY, size, mode)]));
+            }
+        }
+        for mode in 6..9 {
+            table.extend((1..=4).map(|add| [(ADD, add, 0), (COPY, 4, mode)]));
+        }
+        table.extend((0..9).map(|mode| [(COPY, 4, mode), (ADD, 1, 0)]));
+        assert_eq!(table.len(), 256);
+        table
+    }
+
+    struct Reader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> Reader<'a> {
+        fn byte(&mut self) -> u8 {
+            self.pos += 1;
+            self.data[self.pos - 1]
+        }
+
+        fn int(&mut self) -> usize {
+            let mut value = 0;
+            loop {
+                let b = self.byte();
+                value = value << 7 | (b & 0x7F) as usize;
+                if b & 0x80 == 0 {
+                    return value;
+                }
+            }
+        }
+
+        fn bytes(&mut self, len: usize) -> &'a [u8] {
+            self.pos += len;
+            &self.data[self.pos - len..self.pos]
+        }
+    }
+
+    /// 各类指令的数量
+    #[derive(Default, Debug)]
+    struct Stats {
+        add: usize,
+        run: usize,
+        copy: usize,
+    }
+
+    /// 测试用的 VCDIFF 解码器，支持默认指令表和全部地址模式，不支持二次压缩和自定义指令表
+    fn decode(source: &[u8], patch: &[u8]) -> (Vec<u8>, Stats) {
+        let table = default_code_table();
+        let mut reader = Reader {
+            data: patch,
+            pos: 0,
+        };
+        assert_eq!(reader.bytes(4), VCDIFF_MAGIC);
+        assert_eq!(reader.byte(), 0, "不支持的 Hdr_Indicator");
+
+        let mut output = Vec::new();
+        let mut stats = Stats::default();
+        while reader.pos < patch.len() {
+            let win_indicator = reader.byte();
+            let segment = match win_indicator & 0x03 {
+                0 => Vec::new(),
+                VCD_SOURCE | 0x02 => {
+                    let (len, pos) = (reader.int(), reader.int());
+                    let data = if win_indicator & VCD_SOURCE != 0 {
+                        source
+                    } else {
+                        &output
+                    };
+                    data[pos..pos + len].to_vec()
+                }
+                _ => panic!("无效的 Win_Indicator {win_indicator:#X}"),
+            };
+            let delta_len = reader.int();
+            let delta_end = reader.pos + delta_len;
+            let target_len = reader.int();
+            assert_eq!(reader.byte(), 0, "不支持二次压缩");
+            let (data_len, inst_len, addr_len) = (reader.int(), reader.int(), reader.int());
+            let mut data = Reader {
+                data: reader.bytes(data_len),
+                pos: 0,
+            };
+            let mut inst = Reader {
+                data: reader.bytes(inst_len),
+                pos: 0,
+            };
+            let mut addr = Reader {
+                data: reader.bytes(addr_len),
+                pos: 0,
+            };
+            assert_eq!(reader.pos, delta_end);
+
+            let mut window = Vec::with_capacity(target_len);
+            let mut near = [0usize; S_NEAR];
+            let mut next_slot = 0;
+            let mut same = [0usize; S_SAME * 256];
+            while inst.pos < inst.data.len() {
+                for (kind, size, mode) in table[inst.byte() as usize] {
+                    if kind == NOOP {
+                        continue;
+                    }
+                    let size = if size == 0 { inst.int() } else { size };
+                    match kind {
+                        ADD => {
+                            window.extend_from_slice(data.bytes(size));
+                            stats.add += 1;
+                        }
+                        RUN => {
+                            let b = data.byte();
+                            window.resize(window.len() + size, b);
+                            stats.run += 1;
+                        }
+                        _ => {
+                            let here = segment.len() + window.len();
+                            let address = match mode {
+                                0 => addr.int(),
+                                1 => here - addr.int(),
+                                2..=5 => near[mode - 2] + addr.int(),
+                                _ => same[(mode - 6) * 256 + addr.byte() as usize],
+                            };
+                            near[next_slot] = address;
+                            next_slot = (next_slot + 1) % S_NEAR;
+                            same[address % same.len()] = address;
+                            // 地址超出源数据段时引用目标窗口中已经解码的数据，可能与输出重叠
+                            for i in address..address + size {
+                                let b = match segment.get(i) {
+                                    Some(b) => *b,
+                                    None => window[i - segment.len()],
+                                };
+                                window.push(b);
+                            }
+                            stats.copy += 1;
+                        }
+                    }
+                }
+            }
+            assert_eq!(data.pos, data.data.len());
+            assert_eq!(addr.pos, addr.data.len());
+            assert_eq!(window.len(), target_len);
+            output.extend_from_slice(&window);
+        }
+        (output, stats)
+    }
+
+    /// 测试用的 xorshift 伪随机数
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn bytes(&mut self, len: usize) -> Vec<u8> {
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+    }
+
+    fn round_trip(source: &[u8], target: &[u8]) -> (Vec<u8>, Stats) {
+        let patch = encode(source, target);
+        let (decoded, stats) = decode(source, &patch);
+        assert!(decoded == target, "解码结果与目标文件不一致");
+        (patch, stats)
+    }
+
+    #[test]
+    fn random_round_trip() {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        let source = rng.bytes(WINDOW_SIZE * 2 + 1234);
+        let target = rng.bytes(WINDOW_SIZE * 3 - 77);
+        round_trip(&source, &target);
+        round_trip(&[], &target[..5000]);
+        round_trip(&source, &[]);
+    }
+
+    #[test]
+    fn sparse_edit_round_trip() {
+        let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+        let source = rng.bytes(WINDOW_SIZE * 3 + 4321);
+        let mut target = source.clone();
+        for _ in 0..200 {
+            let pos = rng.below(target.len());
+            match rng.below(4) {
+                0 => target[pos] ^= 0xFF,
+                // 插入和删除数据会让匹配不再对齐源文件的块，需要向前扩展匹配
+                1 => {
+                    let len = 1 + rng.below(40);
+                    target.splice(pos..pos, rng.bytes(len));
+                }
+                2 => {
+                    let end = (pos + 1 + rng.below(40)).min(target.len());
+                    target.drain(pos..end);
+                }
+                _ => {
+                    let end = (pos + MIN_RUN + rng.below(200)).min(target.len());
+                    target[pos..end].fill(0);
+                }
+            }
+        }
+        // 跨越窗口边界的修改
+        target[WINDOW_SIZE - 3..WINDOW_SIZE + 300].fill(0xFF);
+
+        let (patch, stats) = round_trip(&source, &target);
+        assert!(patch.len() < 64 * 1024, "补丁过大：{} 字节", patch.len());
+        assert!(
+            stats.run > 0 && stats.copy > 0 && stats.add > 0,
+            "{stats:?}"
+        );
+    }
+
+    #[test]
+    fn copy_extends_backwards() {
+        let mut rng = Rng(0xD1B5_4A32_D192_ED03);
+        let source = rng.bytes(4096);
+        let mut target = source.clone();
+        target.splice(1000..1000, [1, 2, 3, 4, 5]);
+
+        let encoder = Encoder::new(&source, &target);
+        let ops = encoder.find_ops(0..target.len());
+        let ops = ops
+            .iter()
+            .map(|op| match op {
+                Op::Add(range) => (false, range.start, range.len()),
+                Op::Copy { source, len } => (true, *source, *len),
+            })
+            .collect::<Vec<_>>();
+        // 插入的数据之后第一个对齐的块在源文件 1024 处，匹配应向前扩展到插入点
+        assert_eq!(
+            ops,
+            [
+                (true, 0, 1000),
+                (false, 1000, 5),
+                (true, 1000, source.len() - 1000)
+            ]
+        );
+        round_trip(&source, &target);
+    }
+
+    /// 用 xdelta3 解码补丁，确认与 xdelta3 兼容，需要安装 xdelta3 后用 `cargo test -- --ignored` 运行
+    #[test]
+    #[ignore = "needs xdelta3"]
+    fn xdelta3_decodes_patch() {
+        let mut rng = Rng(0x8CB9_2BA7_2F3D_8DD7);
+        let source = rng.bytes(WINDOW_SIZE + 999);
+        let mut target = source.clone();
+        target[123_456..123_789].fill(0);
+        target.splice(500_000..500_000, rng.bytes(77));
+        target.extend(rng.bytes(WINDOW_SIZE / 2));
+
+        let dir = std::env::temp_dir().join(format!("vcdiff-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("source.bin"), &source).unwrap();
+        std::fs::write(dir.join("patch.vcdiff"), encode(&source, &target)).unwrap();
+        let status = std::process::Command::new("xdelta3")
+            .arg("-d")
+            .arg("-f")
+            .arg("-s")
+            .arg(dir.join("source.bin"))
+            .arg(dir.join("patch.vcdiff"))
+            .arg(dir.join("target.bin"))
+            .status()
+            .expect("没有找到 xdelta3");
+        assert!(status.success());
+        assert!(std::fs::read(dir.join("target.bin")).unwrap() == target);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}